@@ -0,0 +1,767 @@
+use super::error::{Error, ErrorCode};
+use super::interpreter::{unparsed, wrong_argument_count};
+use super::parser::{Definition, Expression, ExpressionData, Operation};
+use super::simplify::simplify;
+use std::collections::HashSet;
+
+fn constant_value(tree: &Expression) -> Option<f64> {
+    match tree.data {
+        ExpressionData::Constant(val) => Some(val),
+        _ => None,
+    }
+}
+
+fn is_constant(tree: &Expression, val: f64) -> bool {
+    constant_value(tree) == Some(val)
+}
+
+// the following constructors fold away the identities which differentiation
+// produces in abundance, so that the result stays readable; each node they build
+// spans the node of the source tree from which it was derived
+
+fn constant(val: f64, span: &Expression) -> Expression {
+    Expression::new_const(val, span.start, span.end)
+}
+
+fn fold(lhs: Expression, op: Operation, rhs: Expression, span: &Expression) -> Expression {
+    if let (Some(l), Some(r)) = (constant_value(&lhs), constant_value(&rhs)) {
+        let val = match op {
+            Operation::Add => l + r,
+            Operation::Subtract => l - r,
+            Operation::Multiply => l * r,
+            Operation::Divide => l / r,
+            Operation::Exponentiate => l.powf(r),
+        };
+        if val.is_finite() {
+            return constant(val, span);
+        }
+    }
+    Expression::new_op(lhs, op, rhs).with_bounds(span.start, span.end)
+}
+
+fn add(lhs: Expression, rhs: Expression, span: &Expression) -> Expression {
+    if is_constant(&lhs, 0.) {
+        rhs
+    } else if is_constant(&rhs, 0.) {
+        lhs
+    } else {
+        fold(lhs, Operation::Add, rhs, span)
+    }
+}
+
+fn sub(lhs: Expression, rhs: Expression, span: &Expression) -> Expression {
+    if is_constant(&rhs, 0.) {
+        lhs
+    } else if is_constant(&lhs, 0.) {
+        neg(rhs, span)
+    } else {
+        fold(lhs, Operation::Subtract, rhs, span)
+    }
+}
+
+fn mul(lhs: Expression, rhs: Expression, span: &Expression) -> Expression {
+    if is_constant(&lhs, 0.) || is_constant(&rhs, 1.) {
+        lhs
+    } else if is_constant(&rhs, 0.) || is_constant(&lhs, 1.) {
+        rhs
+    } else if is_constant(&lhs, -1.) {
+        neg(rhs, span)
+    } else if is_constant(&rhs, -1.) {
+        neg(lhs, span)
+    } else {
+        fold(lhs, Operation::Multiply, rhs, span)
+    }
+}
+
+fn div(lhs: Expression, rhs: Expression, span: &Expression) -> Expression {
+    if is_constant(&lhs, 0.) || is_constant(&rhs, 1.) {
+        lhs
+    } else {
+        fold(lhs, Operation::Divide, rhs, span)
+    }
+}
+
+fn pow(lhs: Expression, rhs: Expression, span: &Expression) -> Expression {
+    if is_constant(&rhs, 1.) {
+        lhs
+    } else if is_constant(&rhs, 0.) {
+        constant(1., span)
+    } else {
+        fold(lhs, Operation::Exponentiate, rhs, span)
+    }
+}
+
+fn neg(operand: Expression, span: &Expression) -> Expression {
+    let (start, end) = (operand.start, operand.end);
+    match operand.data {
+        ExpressionData::Constant(val) => constant(-val, span),
+        ExpressionData::Negate(inner) => *inner,
+        ExpressionData::Op(lhs, Operation::Multiply, rhs) if constant_value(&lhs).is_some() => {
+            let lhs = neg(*lhs, span);
+            Expression::new_op(lhs, Operation::Multiply, *rhs).with_bounds(span.start, span.end)
+        }
+        data => Expression::new_neg(Expression { data, start, end }, span.start, span.end),
+    }
+}
+
+// whether a tree is built from numbers alone
+fn is_literal(tree: &Expression) -> bool {
+    match &tree.data {
+        ExpressionData::Constant(_) => true,
+        ExpressionData::Negate(operand) => is_literal(operand),
+        ExpressionData::Op(_, _, _) => {
+            let (leftmost, chain) = tree.left_chain();
+            is_literal(leftmost) && chain.into_iter().all(|(_, _, rhs)| is_literal(rhs))
+        }
+        _ => false,
+    }
+}
+
+// an operand which the derivative copies, with any arithmetic on numbers alone folded first, so
+// that e.g. the power rule gives `-2x^-3` rather than `(0 - 2)x^(0 - 2 - 1)` for `x^(0 - 2)`
+fn folded(tree: &Expression) -> Expression {
+    if is_literal(tree) {
+        simplify(tree)
+    } else {
+        tree.clone()
+    }
+}
+
+fn call(name: &str, arg: Expression, span: &Expression) -> Expression {
+    Expression::new_call(name, vec![arg], span.start, span.end)
+}
+
+// every name in a tree, so that the names given to derivatives can be told apart from them
+fn collect_names(tree: &Expression, names: &mut HashSet<String>) {
+    match &tree.data {
+        ExpressionData::Identifier(name) | ExpressionData::NamedConstant(name) => {
+            names.insert(name.clone());
+        }
+        ExpressionData::Negate(operand) => collect_names(operand, names),
+        ExpressionData::Op(_, _, _) => {
+            let (leftmost, chain) = tree.left_chain();
+            collect_names(leftmost, names);
+            for (_, _, rhs) in chain {
+                collect_names(rhs, names);
+            }
+        }
+        ExpressionData::Call(name, args) => {
+            names.insert(name.clone());
+            args.iter().for_each(|arg| collect_names(arg, names));
+        }
+        ExpressionData::Let(name, value, body) => {
+            names.insert(name.clone());
+            collect_names(value, names);
+            collect_names(body, names);
+        }
+        ExpressionData::Define(definition, rest) => {
+            names.insert(definition.name.clone());
+            names.extend(definition.parameters.iter().cloned());
+            collect_names(&definition.body, names);
+            collect_names(rest, names);
+        }
+        ExpressionData::Constant(_) | ExpressionData::Error => {}
+    }
+}
+
+// the derivative of a call with respect to its only argument
+fn derivative_of_call(name: &str, u: &Expression, tree: &Expression) -> Result<Expression, Error> {
+    let u = || u.clone();
+    let c = |val| constant(val, tree);
+    let add = |lhs, rhs| add(lhs, rhs, tree);
+    let sub = |lhs, rhs| sub(lhs, rhs, tree);
+    let mul = |lhs, rhs| mul(lhs, rhs, tree);
+    let div = |lhs, rhs| div(lhs, rhs, tree);
+    let pow = |lhs, rhs| pow(lhs, rhs, tree);
+    let neg = |operand| neg(operand, tree);
+    let call = |name, arg| call(name, arg, tree);
+    Ok(match name {
+        "sqrt" => div(c(1.), mul(c(2.), call("sqrt", u()))),
+        "cbrt" => div(c(1.), mul(c(3.), pow(call("cbrt", u()), c(2.)))),
+        "exp" => call("exp", u()),
+        "ln" => div(c(1.), u()),
+        "log" => div(c(1.), mul(u(), call("ln", c(10.)))),
+        "log2" => div(c(1.), mul(u(), call("ln", c(2.)))),
+        "sin" => call("cos", u()),
+        "cos" => neg(call("sin", u())),
+        "tan" => div(c(1.), pow(call("cos", u()), c(2.))),
+        "sinh" => call("cosh", u()),
+        "cosh" => call("sinh", u()),
+        "tanh" => div(c(1.), pow(call("cosh", u()), c(2.))),
+        "asin" => div(c(1.), call("sqrt", sub(c(1.), pow(u(), c(2.))))),
+        "acos" => neg(div(c(1.), call("sqrt", sub(c(1.), pow(u(), c(2.)))))),
+        "atan" => div(c(1.), add(c(1.), pow(u(), c(2.)))),
+        "abs" => div(u(), call("abs", u())),
+        "floor" | "ceil" => c(0.),
+        _ => {
+            return Err(Error::from_code(
+                ErrorCode::NoDerivative(name.to_string()),
+                tree.start,
+                tree.end,
+            ))
+        }
+    })
+}
+
+// the derivative of an operation, given the derivatives of its operands; the operands are only
+// copied where the result uses them, so that a long sum is not copied once per term
+fn derivative_of_op(
+    tree: &Expression,
+    (u, du): (&Expression, Expression),
+    op: Operation,
+    (v, dv): (&Expression, Expression),
+) -> Expression {
+    let c = |val| constant(val, tree);
+    let add = |lhs, rhs| add(lhs, rhs, tree);
+    let sub = |lhs, rhs| sub(lhs, rhs, tree);
+    let mul = |lhs, rhs| mul(lhs, rhs, tree);
+    let div = |lhs, rhs| div(lhs, rhs, tree);
+    let pow = |lhs, rhs| pow(lhs, rhs, tree);
+    let call = |name, arg| call(name, arg, tree);
+    match op {
+        Operation::Add => add(du, dv),
+        Operation::Subtract => sub(du, dv),
+        Operation::Multiply => add(mul(du, folded(v)), mul(folded(u), dv)),
+        Operation::Divide => div(
+            sub(mul(du, folded(v)), mul(folded(u), dv)),
+            pow(folded(v), c(2.)),
+        ),
+        Operation::Exponentiate => {
+            if is_constant(&dv, 0.) {
+                // power rule: (u^c)' = c*u^(c-1)*u'
+                let v = folded(v);
+                let exponent = sub(v.clone(), c(1.));
+                mul(mul(v, pow(folded(u), exponent)), du)
+            } else if is_constant(&du, 0.) {
+                // exponential rule: (c^v)' = c^v*ln(c)*v'
+                let u = folded(u);
+                mul(mul(pow(u.clone(), v.clone()), call("ln", u)), dv)
+            } else {
+                // general rule: (u^v)' = u^v*(v'*ln(u) + v*u'/u)
+                let inner = add(
+                    mul(dv, call("ln", u.clone())),
+                    div(mul(v.clone(), du), u.clone()),
+                );
+                mul(tree.clone(), inner)
+            }
+        }
+    }
+}
+
+// what the derivative of a name bound by an assignment or a parameter is, or what a partial
+// derivative of a defined function is
+enum Binding {
+    Constant(f64),
+    // the name of an assignment to the derivative, or of a function which gives it
+    Named(String),
+}
+
+// the partial derivatives of a defined function, with respect to each of its parameters and to
+// the variable through the names its body uses from outside
+struct Partials<'t> {
+    name: &'t str,
+    parameters: Vec<Binding>,
+    variable: Binding,
+}
+
+struct Differentiator<'t> {
+    var: &'t str,
+    // the names in the tree and those given to derivatives so far
+    taken: HashSet<String>,
+    // the derivatives of the variable and of the names bound by the enclosing assignments and
+    // parameters, innermost last
+    variables: Vec<(&'t str, Binding)>,
+    // the partial derivatives of the functions defined by the enclosing definitions, innermost last
+    functions: Vec<Partials<'t>>,
+    // the number of variables and of functions from outside the body being differentiated with
+    // respect to a parameter, which are held constant
+    held: (usize, usize),
+}
+
+impl<'t> Differentiator<'t> {
+    // a name for a derivative which is not used anywhere else
+    fn fresh(&mut self, base: String) -> String {
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.taken.contains(&name) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        self.taken.insert(name.clone());
+        name
+    }
+
+    // the derivative of the body of a definition, given the derivative of each parameter, which is
+    // named after `base` unless it is a constant
+    fn derive_body(
+        &mut self,
+        definition: &'t Definition,
+        parameters: impl Iterator<Item = f64>,
+        base: String,
+        held: (usize, usize),
+    ) -> Result<(Binding, Option<Definition>), Error> {
+        let outer = std::mem::replace(&mut self.held, held);
+        let names = definition.parameters.iter().map(|name| name.as_str());
+        self.variables
+            .extend(names.zip(parameters.map(Binding::Constant)));
+        let body = self.derive(&definition.body);
+        self.variables
+            .truncate(self.variables.len() - definition.parameters.len());
+        self.held = outer;
+        let body = body?;
+        Ok(match constant_value(&body) {
+            Some(val) => (Binding::Constant(val), None),
+            None => {
+                let name = self.fresh(base);
+                let definition = Definition {
+                    name: name.clone(),
+                    parameters: definition.parameters.clone(),
+                    body,
+                };
+                (Binding::Named(name), Some(definition))
+            }
+        })
+    }
+
+    // a definition is followed by those of its partial derivatives which are not constants, which
+    // the derivative of each call to it then calls with the same arguments
+    fn derive_definition(
+        &mut self,
+        tree: &Expression,
+        definition: &'t Definition,
+        rest: &'t Expression,
+    ) -> Result<Expression, Error> {
+        let (mut parameters, mut definitions) = (vec![], vec![]);
+        let held = (self.variables.len(), self.functions.len());
+        for (i, parameter) in definition.parameters.iter().enumerate() {
+            let derivatives =
+                (0..definition.parameters.len()).map(|j| if i == j { 1. } else { 0. });
+            let base = format!("d_{}_{}", definition.name, parameter);
+            let (binding, partial) = self.derive_body(definition, derivatives, base, held)?;
+            parameters.push(binding);
+            definitions.extend(partial);
+        }
+        let derivatives = definition.parameters.iter().map(|_| 0.);
+        let base = format!("d_{}_{}", definition.name, self.var);
+        let (variable, partial) = self.derive_body(definition, derivatives, base, self.held)?;
+        definitions.extend(partial);
+
+        self.functions.push(Partials {
+            name: &definition.name,
+            parameters,
+            variable,
+        });
+        let d_rest = self.derive(rest);
+        self.functions.pop();
+        let mut d_rest = d_rest?;
+        for partial in definitions.into_iter().rev() {
+            d_rest = Expression::new_define(partial, d_rest, tree.start, tree.end);
+        }
+        Ok(Expression::new_define(
+            definition.clone(),
+            d_rest,
+            tree.start,
+            tree.end,
+        ))
+    }
+
+    // the chain rule over every argument of a call to a defined function
+    fn derive_defined_call(
+        &mut self,
+        i: usize,
+        args: &'t [Expression],
+        tree: &Expression,
+    ) -> Result<Expression, Error> {
+        let partials = &self.functions[i];
+        if args.len() != partials.parameters.len() {
+            let (name, expected) = (partials.name, partials.parameters.len());
+            return Err(wrong_argument_count(
+                name,
+                expected,
+                args.len(),
+                tree.start,
+                tree.end,
+            ));
+        }
+        let apply = |binding: &Binding| match binding {
+            Binding::Constant(val) => constant(*val, tree),
+            Binding::Named(name) => Expression::new_call(name, args.to_vec(), tree.start, tree.end),
+        };
+        let mut derivative = if i < self.held.1 {
+            constant(0., tree)
+        } else {
+            apply(&partials.variable)
+        };
+        let partials: Vec<Expression> = partials.parameters.iter().map(apply).collect();
+        for (partial, arg) in partials.into_iter().zip(args) {
+            let d_arg = self.derive(arg)?;
+            derivative = add(derivative, mul(partial, d_arg, tree), tree);
+        }
+        Ok(derivative)
+    }
+
+    fn derive(&mut self, tree: &'t Expression) -> Result<Expression, Error> {
+        match &tree.data {
+            ExpressionData::Error => Err(unparsed(tree.start, tree.end)),
+            ExpressionData::Let(name, value, body) => {
+                let d_value = self.derive(value)?;
+                let binding = match constant_value(&d_value) {
+                    Some(val) => Binding::Constant(val),
+                    None => Binding::Named(self.fresh(format!("d_{}", name))),
+                };
+                self.variables.push((name, binding));
+                let d_body = self.derive(body);
+                let binding = self.variables.pop().map(|(_, binding)| binding);
+                let d_body = match binding {
+                    Some(Binding::Named(d_name)) => {
+                        Expression::new_let(&d_name, d_value, d_body?, tree.start, tree.end)
+                    }
+                    _ => d_body?,
+                };
+                let value = (**value).clone();
+                Ok(Expression::new_let(
+                    name, value, d_body, tree.start, tree.end,
+                ))
+            }
+            ExpressionData::Define(definition, rest) => {
+                self.derive_definition(tree, definition, rest)
+            }
+            ExpressionData::Constant(_) => Ok(constant(0., tree)),
+            // a named constant may be shadowed, so it is treated like any other identifier
+            ExpressionData::Identifier(name) | ExpressionData::NamedConstant(name) => {
+                let i = self.variables.iter().rposition(|(bound, _)| bound == name);
+                Ok(match i.map(|i| (i, &self.variables[i].1)) {
+                    Some((i, _)) if i < self.held.0 => constant(0., tree),
+                    Some((_, Binding::Constant(val))) => constant(*val, tree),
+                    Some((_, Binding::Named(d_name))) => {
+                        Expression::new_id(d_name.as_str(), tree.start, tree.end)
+                    }
+                    None => constant(0., tree),
+                })
+            }
+            ExpressionData::Negate(operand) => Ok(neg(self.derive(operand)?, tree)),
+            // a chain of left-hand sides is differentiated from its leftmost operand outwards
+            ExpressionData::Op(_, _, _) => {
+                let (leftmost, chain) = tree.left_chain();
+                let (mut u, mut du) = (leftmost, self.derive(leftmost)?);
+                for (tree, op, v) in chain {
+                    du = derivative_of_op(tree, (u, du), op, (v, self.derive(v)?));
+                    u = tree;
+                }
+                Ok(du)
+            }
+            // a defined function shadows a built-in one
+            ExpressionData::Call(name, args) => {
+                let defined = self
+                    .functions
+                    .iter()
+                    .rposition(|partials| partials.name == name);
+                match (defined, args.as_slice()) {
+                    (Some(i), _) => self.derive_defined_call(i, args, tree),
+                    (None, [u]) => Ok(mul(
+                        derivative_of_call(name, u, tree)?,
+                        self.derive(u)?,
+                        tree,
+                    )),
+                    (None, _) => Err(Error::from_code(
+                        ErrorCode::NoDerivative(name.to_string()),
+                        tree.start,
+                        tree.end,
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// Differentiates a pre-parsed Serious expression with respect to the identifier `var`.
+///
+/// The result is an [`Expression`](crate::parser::Expression) which can be evaluated by [`interpret_tree`](crate::interpreter::interpret_tree).
+/// Identities such as `x*1` and `x+0`, and arithmetic on numbers alone such as `2^3`, are folded away as the derivative is built.
+/// Each node of the result spans the node of `tree` from which it was derived.
+/// An [assignment](crate::parser::ExpressionData::Let) `a = v; body` is kept, and followed by one of a new name such as `d_a`
+/// to the derivative of `v` wherever that is not a constant, so the result grows with the text rather than with the number of uses of `a`.
+/// Likewise, a [definition](crate::parser::ExpressionData::Define) `f(t) = body; rest` is kept, and followed by definitions of
+/// its partial derivatives such as `d_f_t(t)`, which the derivative of each call to `f` calls with the same arguments.
+/// A call to any other function whose derivative is not known yields a [`NoDerivative`](crate::error::ErrorCode::NoDerivative)
+/// error, whose type is [`Unsupported`](crate::error::ErrorType::Unsupported).
+///
+/// ```
+/// use serious::{calculus::derivative, create_context, interpreter::interpret_tree, parser::parse};
+///
+/// let tree = derivative(&parse("x^3 + sin(2x)").unwrap(), "x").unwrap();
+///
+/// let val = interpret_tree(tree, &create_context!{'x' => 0.}).unwrap();
+/// assert_eq!(val, 2.);
+/// ```
+pub fn derivative(tree: &Expression, var: &str) -> Result<Expression, Error> {
+    let mut taken = HashSet::new();
+    collect_names(tree, &mut taken);
+    taken.insert(var.to_string());
+    let mut differentiator = Differentiator {
+        var,
+        taken,
+        variables: vec![(var, Binding::Constant(1.))],
+        functions: vec![],
+        held: (0, 0),
+    };
+    differentiator.derive(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_context;
+    use crate::error::ErrorType;
+    use crate::interpreter::interpret_tree;
+    use crate::parser::{parse, parse_with, IdentifierMode, ParseOptions};
+    use crate::printer::{print, PrintOptions};
+
+    fn assert_matches_difference(text: &str, points: &[f64]) {
+        let tree = parse(text).unwrap();
+        let d = derivative(&tree, "x").unwrap();
+        for x in points {
+            let h = 1e-6;
+            let eval =
+                |x: f64| interpret_tree(tree.clone(), &create_context! {'x' => x, 'y' => 2.});
+            let difference = (eval(x + h).unwrap() - eval(x - h).unwrap()) / (2. * h);
+            let exact = interpret_tree(d.clone(), &create_context! {'x' => *x, 'y' => 2.}).unwrap();
+            assert!(
+                (difference - exact).abs() < 1e-4 * exact.abs().max(1.),
+                "d/dx {} at x = {}: expected {}, found {}",
+                text,
+                x,
+                difference,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn identifier() {
+        let tree = parse("x").unwrap();
+        assert_eq!(
+            derivative(&tree, "x").unwrap(),
+            Expression::new_const(1., 0, 1)
+        );
+        assert_eq!(
+            derivative(&tree, "y").unwrap(),
+            Expression::new_const(0., 0, 1)
+        );
+    }
+
+    #[test]
+    fn constant_folding() {
+        let tree = parse("2 + 3y - pi").unwrap();
+        let d = derivative(&tree, "x").unwrap();
+        assert_eq!(d.data, ExpressionData::Constant(0.));
+    }
+
+    #[test]
+    fn power_rule() {
+        let d = derivative(&parse("x^3").unwrap(), "x").unwrap();
+        assert_eq!(
+            d,
+            Expression::new_op(
+                Expression::new_const(3., 2, 3),
+                Operation::Multiply,
+                Expression::new_op(
+                    Expression::new_id('x', 0, 1),
+                    Operation::Exponentiate,
+                    Expression::new_const(2., 0, 3)
+                )
+                .with_bounds(0, 3)
+            )
+            .with_bounds(0, 3)
+        );
+    }
+
+    #[test]
+    fn folded_operands() {
+        let texts = [
+            ("x^(0 - 2)", "-2x^-3"),
+            ("x^2^3", "8x^7"),
+            ("x*(-2)^2", "4"),
+            ("(0 - 1)x^2", "-2x"),
+            ("(1 + 1)^x", "2^x ln(2)"),
+        ];
+        for (text, expected) in texts.iter() {
+            let d = derivative(&parse(text).unwrap(), "x").unwrap();
+            assert_eq!(d.to_string(), *expected);
+            assert_nested_spans(&d);
+        }
+    }
+
+    fn assert_nested_spans(tree: &Expression) {
+        assert!(tree.start <= tree.end, "{:?} is inverted", tree);
+        let children: Vec<&Expression> = match &tree.data {
+            ExpressionData::Op(lhs, _, rhs) => vec![lhs, rhs],
+            ExpressionData::Negate(operand) => vec![operand],
+            ExpressionData::Call(_, args) => args.iter().collect(),
+            _ => vec![],
+        };
+        for child in children {
+            assert!(
+                tree.start <= child.start && child.end <= tree.end,
+                "{:?} lies outside {:?}",
+                child,
+                tree
+            );
+            assert_nested_spans(child);
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let texts = [
+            "x^2*3",
+            "x^3",
+            "3x^2 - 2x + 1",
+            "(x^2 + 1)/(x - 3)",
+            "-x^2*sin(x)",
+            "2^x + x^x",
+            "sqrt(x^2 + 1)*acos(3x)",
+        ];
+        for text in texts.iter() {
+            assert_nested_spans(&derivative(&parse(text).unwrap(), "x").unwrap());
+        }
+    }
+
+    #[test]
+    fn negation() {
+        let d = derivative(&parse("-x").unwrap(), "x").unwrap();
+        assert_eq!(d, Expression::new_const(-1., 0, 2));
+    }
+
+    #[test]
+    fn sum_product_quotient() {
+        assert_matches_difference("3x^2 - 2x + 1", &[-2., 0., 1.5]);
+        assert_matches_difference("(x + 1)(x - y)x", &[-2., 0., 1.5]);
+        assert_matches_difference("(x^2 + 1)/(x - 3)", &[-2., 0., 1.5]);
+    }
+
+    #[test]
+    fn exponents() {
+        assert_matches_difference("x^-2", &[-2., 0.5, 1.5]);
+        assert_matches_difference("y^x", &[-2., 0., 1.5]);
+        assert_matches_difference("x^x", &[0.5, 1., 1.5]);
+        assert_matches_difference("(x^2 + 1)^(sin(x))", &[-2., 0., 1.5]);
+    }
+
+    #[test]
+    fn functions() {
+        let texts = [
+            "sqrt(x^2 + 1)",
+            "cbrt(x + 3)",
+            "exp(2x)",
+            "ln(x^2 + 1)",
+            "log(x + 3)",
+            "log2(x + 3)",
+            "sin(x)cos(x)",
+            "tan(x/2)",
+            "sinh(x) + cosh(x) + tanh(x)",
+            "asin(x/3) + acos(x/3) + atan(x)",
+            "abs(x - 0.25)",
+            "floor(x) + ceil(x) + x",
+        ];
+        for text in texts.iter() {
+            assert_matches_difference(text, &[-1.7, 0.3, 1.2]);
+        }
+    }
+
+    #[test]
+    fn shadowed_constant() {
+        let d = derivative(&parse("e^2").unwrap(), "e").unwrap();
+        let val = interpret_tree(d, &create_context! {'e' => 3.}).unwrap();
+        assert_eq!(val, 6.);
+    }
+
+    #[test]
+    fn unknown_function() {
+        let tree = Expression::new_call("sec", vec![Expression::new_id('x', 4, 5)], 0, 6);
+        let err = derivative(&tree, "x").unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::NoDerivative("sec".to_string()), 0, 6)
+        );
+        assert_eq!(err.error_type(), ErrorType::Unsupported);
+        let args = vec![Expression::new_id('x', 4, 5), Expression::new_id('y', 7, 8)];
+        let tree = Expression::new_call("max", args, 0, 9);
+        assert_eq!(
+            derivative(&tree, "x").unwrap_err(),
+            Error::from_code(ErrorCode::NoDerivative("max".to_string()), 0, 9)
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        assert_matches_difference("u = x^2 + 1; sin(u)/u", &[0.5, 1., 2.]);
+        assert_matches_difference("u = x; x = 3; u x + y", &[0.5, 1., 2.]);
+        let tree = parse("a = y; y = 2; a y").unwrap();
+        let d = derivative(&tree, "y").unwrap();
+        assert_eq!(d.to_string(), "a = y; y = 2; y");
+        let d = derivative(&parse("u = x^2 + 1; sin(u)/u").unwrap(), "x").unwrap();
+        assert_eq!(
+            d.to_string(),
+            "u = x^2 + 1; d_u = 2x; (cos(u)*d_u*u - sin(u)*d_u)/u^2"
+        );
+    }
+
+    #[test]
+    fn derivative_names() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("u = x^2; d_u = 3; d_u u", &options).unwrap();
+        let d = derivative(&tree, "x").unwrap();
+        let print_options = PrintOptions {
+            parse_options: options,
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            print(&d, &print_options),
+            "u = x^2; d_u_2 = 2x; d_u = 3; d_u d_u_2"
+        );
+    }
+
+    #[test]
+    fn repeated_assignments() {
+        let text = format!("a = x x; {}a", "a = a a; ".repeat(40));
+        let d = derivative(&parse(&text).unwrap(), "x").unwrap();
+        assert!(d.to_string().len() < 40 * text.len());
+        let val = interpret_tree(d, &create_context! {'x' => 1.}).unwrap();
+        assert_eq!(val, 2f64.powi(41));
+    }
+
+    #[test]
+    fn definitions() {
+        assert_matches_difference("f(t) = t^2; g(t) = 3f(t); g(x) + f(2x)", &[0.5, 1., 2.]);
+        assert_matches_difference("h(x, y) = x^2 y; h(y, x) + h(x, y)", &[0.5, 1., 2.]);
+        assert_matches_difference("a = x; f(t) = a t; a = 2; f(x)", &[0.5, 1., 2.]);
+        let tree = parse("f(t) = t^3; f(y)").unwrap();
+        let d = derivative(&tree, "x").unwrap();
+        assert_eq!(d.to_string(), "f(t) = t^3; d_f_t(t) = 3t^2; 0");
+        let d = derivative(&parse("a = x^2; f(t) = a t; f(3)").unwrap(), "x").unwrap();
+        assert_eq!(
+            d.to_string(),
+            "a = x^2; d_a = 2x; f(t) = at; d_f_t(t) = a; d_f_x(t) = d_a*t; d_f_x(3)"
+        );
+    }
+
+    #[test]
+    fn nested_calls() {
+        let names: Vec<char> = "abcdfghjklmnopqrsuvw".chars().collect();
+        let mut text = "a(t) = t t; ".to_string();
+        for pair in names.windows(2) {
+            text.push_str(&format!("{}(t) = {}({}(t)); ", pair[1], pair[0], pair[0]));
+        }
+        text.push_str("w(x)");
+        let d = derivative(&parse(&text).unwrap(), "x").unwrap();
+        assert!(d.to_string().len() < 4 * text.len());
+        assert_matches_difference(
+            "a(t) = t t; b(t) = a(a(t)); c(t) = b(b(t)); c(x)",
+            &[0.5, 1.],
+        );
+    }
+}
@@ -0,0 +1,816 @@
+use super::constants::constant;
+use super::error::{Error, ErrorCode};
+use super::functions::{builtins, Arity, Builtin, Entry, NativeFunction, Registry};
+use super::interpreter::{
+    apply_function, apply_native, apply_operation, unbound_identifier, unparsed,
+    wrong_argument_count,
+};
+use super::limits::Limits;
+use super::parser::{Definition, Expression, ExpressionData, Operation};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+enum Instruction {
+    Constant(f64),
+    Variable(usize),
+    // copies the value of an assignment from its position on the stack
+    Load(usize),
+    // removes the value of an assignment from under the result of its body
+    Unbind,
+    Negate,
+    Op(Operation, usize, usize),
+    Call(&'static Builtin, usize, usize),
+    // calls the native function at an index into the compiled expression's natives with a number of arguments
+    Native(usize, usize, usize, usize),
+}
+
+// a function implemented in Rust which is called by the bytecode
+#[derive(Clone)]
+struct Native {
+    name: String,
+    arity: Arity,
+    function: Arc<NativeFunction>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// An [`Expression`](crate::parser::Expression) compiled by [`compile`](crate::compiler::compile) into flat bytecode for repeated evaluation.
+///
+/// Identifiers are resolved to slots in the slice of values passed to [`eval`](crate::compiler::CompiledExpression::eval),
+/// in the order of the variables given to [`compile`](crate::compiler::compile).
+#[derive(Debug, Clone)]
+pub struct CompiledExpression {
+    instructions: Vec<Instruction>,
+    variables: Vec<String>,
+    natives: Vec<Native>,
+    stack_size: usize,
+}
+
+impl CompiledExpression {
+    /// The variable bound to each slot of the values passed to [`eval`](crate::compiler::CompiledExpression::eval).
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Evaluates the expression, given one value per variable slot.
+    ///
+    /// Yields the same errors as [`interpret_tree`](crate::interpreter::interpret_tree).
+    ///
+    /// # Panics
+    /// Panics if `values` is shorter than [`variables`](crate::compiler::CompiledExpression::variables).
+    pub fn eval(&self, values: &[f64]) -> Result<f64, Error> {
+        let mut stack = Vec::with_capacity(self.stack_size);
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::Constant(val) => stack.push(val),
+                Instruction::Variable(slot) => stack.push(values[slot]),
+                Instruction::Load(position) => stack.push(stack[position]),
+                Instruction::Unbind => {
+                    let binding = stack.len() - 2;
+                    stack.swap_remove(binding);
+                }
+                Instruction::Negate => {
+                    let operand = stack.pop().unwrap();
+                    stack.push(-operand);
+                }
+                Instruction::Op(op, start, end) => {
+                    let rhs = stack.pop().unwrap();
+                    let lhs = stack.pop().unwrap();
+                    stack.push(apply_operation(op, lhs, rhs, start, end)?);
+                }
+                Instruction::Call(function, start, end) => {
+                    let arg = stack.pop().unwrap();
+                    stack.push(apply_function(function, arg, start, end)?);
+                }
+                Instruction::Native(index, arg_count, start, end) => {
+                    let Native {
+                        name,
+                        arity,
+                        function,
+                    } = &self.natives[index];
+                    let first = stack.len() - arg_count;
+                    let native = (*arity, function.as_ref());
+                    let result = apply_native(name, native, &stack[first..], start, end)?;
+                    stack.truncate(first);
+                    stack.push(result);
+                }
+            }
+        }
+        Ok(stack.pop().unwrap())
+    }
+
+    /// Evaluates the expression over `rows` rows of data, given one column of values per variable slot.
+    ///
+    /// Each instruction is applied to whole columns at a time, so the inner loops can be vectorized by the compiler.
+    /// Rows which yield an error are re-evaluated with [`eval`](crate::compiler::CompiledExpression::eval) to report the same error.
+    ///
+    /// ```
+    /// use serious::{compiler::compile, parser::parse};
+    ///
+    /// let compiled = compile(&parse("x/y").unwrap(), &["x", "y"]).unwrap();
+    /// let results = compiled.eval_batch(3, &[&[1., 2., 3.], &[2., 0., 4.]]);
+    ///
+    /// assert_eq!(results[0], Ok(0.5));
+    /// assert_eq!(results[1].as_ref().unwrap_err().message(), "2/0 is undefined");
+    /// assert_eq!(results[2], Ok(0.75));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if there are fewer columns than [`variables`](crate::compiler::CompiledExpression::variables) or if a column does not have `rows` values.
+    pub fn eval_batch(&self, rows: usize, columns: &[&[f64]]) -> Vec<Result<f64, Error>> {
+        assert!(
+            columns.len() >= self.variables.len(),
+            "expected {} columns, found {}",
+            self.variables.len(),
+            columns.len()
+        );
+        for column in columns {
+            assert_eq!(column.len(), rows, "column length does not match rows");
+        }
+
+        let mut registers = vec![vec![0.; rows]; self.stack_size];
+        let mut invalid = vec![false; rows];
+        let mut height = 0;
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::Constant(val) => {
+                    registers[height].iter_mut().for_each(|r| *r = val);
+                    height += 1;
+                }
+                Instruction::Variable(slot) => {
+                    registers[height].copy_from_slice(columns[slot]);
+                    height += 1;
+                }
+                Instruction::Load(position) => {
+                    let (lower, upper) = registers.split_at_mut(height);
+                    upper[0].copy_from_slice(&lower[position]);
+                    height += 1;
+                }
+                Instruction::Unbind => {
+                    registers.swap(height - 2, height - 1);
+                    height -= 1;
+                }
+                Instruction::Negate => {
+                    registers[height - 1].iter_mut().for_each(|r| *r = -*r);
+                }
+                Instruction::Op(op, _, _) => {
+                    let (lower, upper) = registers.split_at_mut(height - 1);
+                    let (lhs, rhs) = (&mut lower[height - 2], &upper[0]);
+                    match op {
+                        Operation::Add => zip_apply(lhs, rhs, |l, r| l + r),
+                        Operation::Subtract => zip_apply(lhs, rhs, |l, r| l - r),
+                        Operation::Multiply => zip_apply(lhs, rhs, |l, r| l * r),
+                        Operation::Divide => {
+                            zip_apply(lhs, rhs, |l, r| if r == 0. { f64::NAN } else { l / r })
+                        }
+                        Operation::Exponentiate => zip_apply(lhs, rhs, f64::powf),
+                    }
+                    mark_invalid(&mut invalid, lhs);
+                    height -= 1;
+                }
+                Instruction::Call(function, _, _) => {
+                    let arg = &mut registers[height - 1];
+                    arg.iter_mut().for_each(|r| *r = (function.function)(*r));
+                    mark_invalid(&mut invalid, arg);
+                }
+                // a native function takes all of its arguments at once, so it is called row by row
+                Instruction::Native(index, arg_count, start, end) => {
+                    let Native {
+                        name,
+                        arity,
+                        function,
+                    } = &self.natives[index];
+                    let first = height - arg_count;
+                    let mut arguments = vec![0.; arg_count];
+                    let results: Vec<f64> = (0..rows)
+                        .map(|row| {
+                            for (argument, register) in
+                                arguments.iter_mut().zip(&registers[first..])
+                            {
+                                *argument = register[row];
+                            }
+                            let native = (*arity, function.as_ref());
+                            apply_native(name, native, &arguments, start, end).unwrap_or(f64::NAN)
+                        })
+                        .collect();
+                    registers[first].copy_from_slice(&results);
+                    mark_invalid(&mut invalid, &results);
+                    height = first + 1;
+                }
+            }
+        }
+
+        let values = registers.swap_remove(0);
+        values
+            .into_iter()
+            .zip(invalid)
+            .enumerate()
+            .map(|(row, (value, invalid))| {
+                if invalid {
+                    let row: Vec<f64> = columns.iter().map(|column| column[row]).collect();
+                    self.eval(&row)
+                } else {
+                    Ok(value)
+                }
+            })
+            .collect()
+    }
+}
+
+#[inline(always)]
+fn zip_apply<F: Fn(f64, f64) -> f64>(lhs: &mut [f64], rhs: &[f64], f: F) {
+    for (l, r) in lhs.iter_mut().zip(rhs) {
+        *l = f(*l, *r);
+    }
+}
+
+fn mark_invalid(invalid: &mut [bool], values: &[f64]) {
+    for (invalid, value) in invalid.iter_mut().zip(values) {
+        *invalid |= !value.is_finite();
+    }
+}
+
+struct Compiler<'a> {
+    variables: &'a [&'a str],
+    functions: &'a Registry,
+    natives: Vec<Native>,
+    // the names bound by the enclosing assignments and parameters and the stack positions of their values, innermost last
+    scope: Vec<(String, usize)>,
+    // the functions defined by the enclosing definitions, innermost last, each with the length of the scope where it was defined
+    definitions: Vec<(&'a Definition, usize)>,
+    // bytecode has no branches, so every instruction is one step of each evaluation
+    max_steps: usize,
+    // the number of calls to defined functions being inlined, which bounds a cycle between functions in the registry
+    calls: usize,
+    max_call_depth: usize,
+    instructions: Vec<Instruction>,
+    stack_height: usize,
+    stack_size: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+        self.stack_height += 1;
+        self.stack_size = self.stack_size.max(self.stack_height);
+    }
+
+    fn reduce(&mut self, instruction: Instruction, operand_count: usize) {
+        self.instructions.push(instruction);
+        self.stack_height = self.stack_height + 1 - operand_count;
+        self.stack_size = self.stack_size.max(self.stack_height);
+    }
+
+    fn native(&mut self, name: &str, arity: Arity, function: &Arc<NativeFunction>) -> usize {
+        match self.natives.iter().position(|native| native.name == name) {
+            Some(index) => index,
+            None => {
+                self.natives.push(Native {
+                    name: name.to_string(),
+                    arity,
+                    function: function.clone(),
+                });
+                self.natives.len() - 1
+            }
+        }
+    }
+
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.variables.iter().position(|variable| *variable == name)
+    }
+
+    fn bound(&self, name: &str) -> Option<usize> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, position)| *position)
+    }
+
+    // a call to a defined function is inlined: the arguments stay on the stack while the body is
+    // evaluated in the scope where the function was defined, which for a function from the registry is empty
+    fn inline(
+        &mut self,
+        definition: &'a Definition,
+        (visible, depth): (usize, usize),
+        args: &'a [Expression],
+        tree: &Expression,
+    ) -> Result<(), Error> {
+        let expected = definition.parameters.len();
+        if args.len() != expected {
+            let name = &definition.name;
+            return Err(wrong_argument_count(
+                name,
+                expected,
+                args.len(),
+                tree.start,
+                tree.end,
+            ));
+        }
+        if self.calls == self.max_call_depth {
+            let code = ErrorCode::CallsTooDeep(self.max_call_depth);
+            return Err(Error::from_code(code, tree.start, tree.end));
+        }
+        for arg in args {
+            self.emit(arg)?;
+        }
+        let first = self.stack_height - args.len();
+        let hidden_scope = self.scope.split_off(depth);
+        let hidden_definitions = self.definitions.split_off(visible);
+        let parameters = definition.parameters.iter().cloned();
+        self.scope.extend(parameters.zip(first..));
+
+        self.calls += 1;
+        self.emit(&definition.body)?;
+        self.calls -= 1;
+
+        self.scope.truncate(depth);
+        self.scope.extend(hidden_scope);
+        self.definitions.extend(hidden_definitions);
+        for _ in args {
+            self.reduce(Instruction::Unbind, 2);
+        }
+        if self.instructions.len() > self.max_steps {
+            let code = ErrorCode::TooManySteps(self.max_steps);
+            return Err(Error::from_code(code, tree.start, tree.end));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, tree: &'a Expression) -> Result<(), Error> {
+        match &tree.data {
+            ExpressionData::Constant(val) => self.push(Instruction::Constant(*val)),
+            ExpressionData::Identifier(name) => match (self.bound(name), self.slot(name)) {
+                (Some(position), _) => self.push(Instruction::Load(position)),
+                (None, Some(slot)) => self.push(Instruction::Variable(slot)),
+                (None, None) => return Err(unbound_identifier(name, tree.start, tree.end)),
+            },
+            // an assignment or a variable of the same name shadows the built-in value
+            ExpressionData::NamedConstant(name) => {
+                match (self.bound(name), self.slot(name), constant(name)) {
+                    (Some(position), _, _) => self.push(Instruction::Load(position)),
+                    (None, Some(slot), _) => self.push(Instruction::Variable(slot)),
+                    (None, None, Some(constant)) => {
+                        self.push(Instruction::Constant(constant.value))
+                    }
+                    (None, None, None) => {
+                        return Err(unbound_identifier(name, tree.start, tree.end))
+                    }
+                }
+            }
+            // the value stays on the stack until the body has been evaluated
+            ExpressionData::Let(name, value, body) => {
+                self.emit(value)?;
+                self.scope.push((name.clone(), self.stack_height - 1));
+                self.emit(body)?;
+                self.scope.pop();
+                self.reduce(Instruction::Unbind, 2);
+            }
+            ExpressionData::Define(definition, rest) => {
+                self.definitions.push((definition, self.scope.len()));
+                self.emit(rest)?;
+                self.definitions.pop();
+            }
+            ExpressionData::Negate(operand) => {
+                self.emit(operand)?;
+                self.reduce(Instruction::Negate, 1);
+            }
+            // a chain of left-hand sides is emitted from its leftmost operand outwards
+            ExpressionData::Op(_, _, _) => {
+                let (leftmost, chain) = tree.left_chain();
+                self.emit(leftmost)?;
+                for (node, op, rhs) in chain {
+                    self.emit(rhs)?;
+                    self.reduce(Instruction::Op(op, node.start, node.end), 2);
+                }
+            }
+            ExpressionData::Call(name, args) => {
+                let defined = self
+                    .definitions
+                    .iter()
+                    .rposition(|(definition, _)| definition.name == *name);
+                if let Some(i) = defined {
+                    let (definition, depth) = self.definitions[i];
+                    return self.inline(definition, (i, depth), args, tree);
+                }
+                let (start, end) = (tree.start, tree.end);
+                match self.functions.entry(name) {
+                    Some(Entry::Defined(definition)) => {
+                        return self.inline(definition, (0, 0), args, tree)
+                    }
+                    Some(Entry::Builtin(function)) => {
+                        if args.len() != 1 {
+                            return Err(wrong_argument_count(name, 1, args.len(), start, end));
+                        }
+                        self.emit(&args[0])?;
+                        self.reduce(Instruction::Call(function, start, end), 1);
+                    }
+                    Some(Entry::Native(_, arity, function)) => {
+                        let index = self.native(name, *arity, function);
+                        for arg in args {
+                            self.emit(arg)?;
+                        }
+                        let instruction = Instruction::Native(index, args.len(), start, end);
+                        self.reduce(instruction, args.len());
+                    }
+                    None => {
+                        let code = ErrorCode::UndefinedFunction(name.to_string());
+                        return Err(Error::from_code(code, start, end));
+                    }
+                }
+            }
+            ExpressionData::Error => return Err(unparsed(tree.start, tree.end)),
+        }
+        Ok(())
+    }
+}
+
+/// Compiles a pre-parsed Serious expression for repeated evaluation, resolving each identifier to the slot of its name in `variables`.
+///
+/// Built-in [constants](crate::constants) are shadowed by variables of the same name.
+/// Calls to functions [defined](crate::parser::ExpressionData::Define) in the expression are inlined; a program whose
+/// bytecode would be longer than the default [`Limits::max_steps`](crate::limits::Limits::max_steps) yields a
+/// [`TooManySteps`](crate::error::ErrorCode::TooManySteps) error, as the interpreter would.
+/// An identifier which is not in `variables` yields an [`UnboundIdentifier`](crate::error::ErrorType::UnboundIdentifier) error.
+/// Use [`compile_with`](crate::compiler::compile_with) for other limits or to call the functions in a [`Registry`](crate::functions::Registry).
+///
+/// ```
+/// use serious::{compiler::compile, parser::parse};
+///
+/// let compiled = compile(&parse("(x^2 + y^2)^0.5").unwrap(), &["x", "y"]).unwrap();
+///
+/// assert_eq!(compiled.eval(&[3., 4.]).unwrap(), 5.);
+/// assert_eq!(compiled.eval(&[5., 12.]).unwrap(), 13.);
+/// ```
+pub fn compile(tree: &Expression, variables: &[&str]) -> Result<CompiledExpression, Error> {
+    compile_with(tree, variables, &Limits::default(), builtins())
+}
+
+/// Compiles a pre-parsed Serious expression like [`compile`](crate::compiler::compile), calling the functions in a
+/// [`Registry`](crate::functions::Registry) as [`interpret_tree_with_functions`](crate::interpreter::interpret_tree_with_functions) would.
+///
+/// Calls to defined functions from the registry are inlined like those defined in the expression, so a program whose
+/// bytecode would be longer than [`Limits::max_steps`](crate::limits::Limits::max_steps) yields a
+/// [`TooManySteps`](crate::error::ErrorCode::TooManySteps) error, and calls nested deeper than
+/// [`Limits::max_call_depth`](crate::limits::Limits::max_call_depth), e.g. by functions which were redefined to call each other,
+/// yield a [`CallsTooDeep`](crate::error::ErrorCode::CallsTooDeep) error. The compiled expression keeps the functions
+/// implemented in Rust which it calls, so later changes to the registry do not affect it.
+///
+/// ```
+/// use serious::{compiler::compile_with, parser::parse_with};
+/// use serious::functions::{Arity, Registry};
+/// use serious::limits::Limits;
+/// use serious::parser::ParseOptions;
+///
+/// let options = ParseOptions::default();
+/// let mut functions = Registry::new();
+/// functions.register("max", Arity::AtLeast(1), |args| Ok(args.iter().cloned().fold(f64::MIN, f64::max)));
+/// functions.define("f(t) = max(t, 0)^2", &options).unwrap();
+///
+/// let tree = parse_with("f(x) + f(y)", &functions.parse_options(&options)).unwrap();
+/// let compiled = compile_with(&tree, &["x", "y"], &Limits::default(), &functions).unwrap();
+/// assert_eq!(compiled.eval(&[3., -4.]).unwrap(), 9.);
+/// ```
+pub fn compile_with(
+    tree: &Expression,
+    variables: &[&str],
+    limits: &Limits,
+    functions: &Registry,
+) -> Result<CompiledExpression, Error> {
+    let mut compiler = Compiler {
+        variables,
+        functions,
+        natives: vec![],
+        scope: vec![],
+        definitions: vec![],
+        max_steps: limits.max_steps,
+        calls: 0,
+        max_call_depth: limits.max_call_depth,
+        instructions: vec![],
+        stack_height: 0,
+        stack_size: 0,
+    };
+    compiler.emit(tree)?;
+    Ok(CompiledExpression {
+        instructions: compiler.instructions,
+        variables: variables
+            .iter()
+            .map(|variable| variable.to_string())
+            .collect(),
+        natives: compiler.natives,
+        stack_size: compiler.stack_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_context;
+    use crate::error::ErrorCode;
+    use crate::interpreter::{interpret_tree, interpret_tree_with_functions};
+    use crate::parser::{parse, parse_with, ParseOptions};
+
+    fn compile_text(text: &str, variables: &[&str]) -> Result<CompiledExpression, Error> {
+        compile(&parse(text).unwrap(), variables)
+    }
+
+    #[test]
+    fn literal() {
+        let compiled = compile_text("10.3", &[]).unwrap();
+        assert_eq!(compiled.eval(&[]).unwrap(), 10.3);
+    }
+
+    #[test]
+    fn slots() {
+        let compiled = compile_text("x - 2y", &["y", "x"]).unwrap();
+        assert_eq!(compiled.variables(), ["y", "x"]);
+        assert_eq!(compiled.eval(&[1., 5.]).unwrap(), 3.);
+        assert_eq!(compiled.eval(&[4., 5.]).unwrap(), -3.);
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let texts = [
+            "34.2x + y^2(-2x^3 + 1)/5.2",
+            "sqrt(x^2 + y^2) - abs(x - y)ln(e)",
+            "-x^-y^2 + 2pi",
+            "2^3^2 / (x + y)",
+        ];
+        for text in texts.iter() {
+            let compiled = compile_text(text, &["x", "y"]).unwrap();
+            for (x, y) in [(1., 2.), (12.34, 9999.), (-3., 0.5)].iter() {
+                let context = create_context! {'x' => *x, 'y' => *y};
+                assert_eq!(
+                    compiled.eval(&[*x, *y]),
+                    interpret_tree(parse(text).unwrap(), &context)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shadowed_constant() {
+        let compiled = compile_text("2e + pi", &["e"]).unwrap();
+        assert_eq!(compiled.eval(&[0.5]).unwrap(), 1. + std::f64::consts::PI);
+    }
+
+    #[test]
+    fn unbound_var() {
+        let err = compile_text("3 + xy", &["x"]).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::UnboundIdentifier("y".to_string()), 5, 6)
+        );
+    }
+
+    #[test]
+    fn div_zero() {
+        let compiled = compile_text("2^(56 / (x - 2)) * 3", &["x"]).unwrap();
+        assert_eq!(compiled.eval(&[3.]).unwrap(), 2f64.powf(56.) * 3.);
+        assert_eq!(
+            compiled.eval(&[2.]).unwrap_err(),
+            Error::from_code(
+                ErrorCode::UndefinedOperation {
+                    lhs: 56.,
+                    op: Operation::Divide,
+                    rhs: 0.,
+                },
+                2,
+                16
+            )
+        );
+    }
+
+    #[test]
+    fn bad_function_argument() {
+        let compiled = compile_text("2 + 3ln(1 - x)", &["x"]).unwrap();
+        assert_eq!(
+            compiled.eval(&[2.]).unwrap_err(),
+            Error::from_code(
+                ErrorCode::UndefinedCall {
+                    function: "ln".to_string(),
+                    arguments: vec![-1.]
+                },
+                5,
+                14
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_function() {
+        let tree = Expression::new_call("sec", vec![Expression::new_const(1., 4, 5)], 0, 6);
+        let err = compile(&tree, &[]).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::UndefinedFunction("sec".to_string()), 0, 6)
+        );
+    }
+
+    #[test]
+    fn batch_matches_eval() {
+        let compiled = compile_text("-x^-y^2 + 2pi - sqrt(x)/y", &["x", "y"]).unwrap();
+        let xs = [1., 2., 12.34, 0., 4., -1.];
+        let ys = [2., -0.5, 9999., 1., 0., 3.];
+        let results = compiled.eval_batch(xs.len(), &[&xs, &ys]);
+
+        assert_eq!(results.len(), xs.len());
+        for (row, result) in results.into_iter().enumerate() {
+            assert_eq!(result, compiled.eval(&[xs[row], ys[row]]));
+        }
+    }
+
+    #[test]
+    fn batch_errors() {
+        let compiled = compile_text("1/(10^x)", &["x"]).unwrap();
+        let results = compiled.eval_batch(2, &[&[1., 999.]]);
+
+        assert_eq!(results[0], Ok(0.1));
+        assert_eq!(
+            results[1],
+            Err(Error::from_code(
+                ErrorCode::OperationOverflow {
+                    lhs: 10.,
+                    op: Operation::Exponentiate,
+                    rhs: 999.,
+                },
+                2,
+                8
+            ))
+        );
+    }
+
+    #[test]
+    fn batch_constant() {
+        let compiled = compile_text("2^10", &[]).unwrap();
+        assert_eq!(compiled.eval_batch(2, &[]), vec![Ok(1024.), Ok(1024.)]);
+    }
+
+    #[test]
+    fn batch_empty() {
+        let compiled = compile_text("x", &["x"]).unwrap();
+        assert_eq!(compiled.eval_batch(0, &[&[]]), vec![]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn batch_column_length_mismatch() {
+        let compiled = compile_text("x + y", &["x", "y"]).unwrap();
+        compiled.eval_batch(2, &[&[1., 2.], &[1.]]);
+    }
+
+    #[test]
+    fn assignments() {
+        let texts = [
+            "d = x^2 - 4y; (-x + d^0.5)/2",
+            "a = x; a = a + 1; b = a a; a + b - x",
+            "e = 2; y = x; x = e; x y e",
+        ];
+        for text in texts.iter() {
+            let compiled = compile_text(text, &["x", "y"]).unwrap();
+            for (x, y) in [(5., 1.), (1., 2.), (-3., 0.5)].iter() {
+                let context = create_context! {'x' => *x, 'y' => *y};
+                assert_eq!(
+                    compiled.eval(&[*x, *y]),
+                    interpret_tree(parse(text).unwrap(), &context)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn batch_assignments() {
+        let compiled = compile_text("d = x - y; u = 1/d; u + d*y", &["x", "y"]).unwrap();
+        let xs = [1., 2., 3., 0.];
+        let ys = [2., 2., 1., 4.];
+        let results = compiled.eval_batch(xs.len(), &[&xs, &ys]);
+        for (row, result) in results.into_iter().enumerate() {
+            assert_eq!(result, compiled.eval(&[xs[row], ys[row]]));
+        }
+        assert!(compiled.eval(&[2., 2.]).is_err());
+    }
+
+    #[test]
+    fn definitions() {
+        let texts = [
+            "f(t) = 3t^2 + 1; f(x) + f(y)",
+            "f(t) = t + 1; a = 2; g(u, v) = f(u) a/v; g(x, y) - f(y)",
+            "a = 2; f(t) = a t; a = 3; f(x) + a",
+            "h(x, y) = x - 2y; h(y, x)",
+        ];
+        for text in texts.iter() {
+            let compiled = compile_text(text, &["x", "y"]).unwrap();
+            for (x, y) in [(5., 1.), (1., 2.), (-3., 0.5)].iter() {
+                let context = create_context! {'x' => *x, 'y' => *y};
+                assert_eq!(
+                    compiled.eval(&[*x, *y]),
+                    interpret_tree(parse(text).unwrap(), &context)
+                );
+                assert_eq!(
+                    compiled.eval_batch(1, &[&[*x], &[*y]]),
+                    vec![compiled.eval(&[*x, *y])]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inlining_is_bounded() {
+        let mut text = "a(t) = t + t;".to_string();
+        let names: Vec<char> = ('a'..='v').collect();
+        for pair in names.windows(2) {
+            text += &format!(" {}(t) = {}(t) + {}(t);", pair[1], pair[0], pair[0]);
+        }
+        text += " v(x)";
+        let err = compile_text(&text, &["x"]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManySteps(1_000_000));
+        assert_eq!(
+            interpret_tree(parse(&text).unwrap(), &create_context! {'x' => 1.})
+                .unwrap_err()
+                .code,
+            err.code
+        );
+    }
+
+    #[test]
+    fn long_chains() {
+        let tree = parse(&format!("x{}", "+x".repeat(8000))).unwrap();
+        let compiled = compile(&tree, &["x"]).unwrap();
+        assert_eq!(compiled.eval(&[1.]).unwrap(), 8001.);
+    }
+
+    fn registry() -> Registry {
+        let options = ParseOptions::default();
+        let mut functions = Registry::new();
+        functions.register("max", Arity::AtLeast(1), |args| {
+            Ok(args.iter().cloned().fold(f64::MIN, f64::max))
+        });
+        functions.register("rate", Arity::Exactly(1), |args| match args[0] {
+            income if income < 0. => Err("income is negative".to_string()),
+            _ => Ok(0.25),
+        });
+        functions.register("answer", Arity::Exactly(0), |_| Ok(42.));
+        functions.define("f(t) = max(t, 0)^2", &options).unwrap();
+        functions.define("g(t) = f(t) + sqrt(t)", &options).unwrap();
+        functions
+    }
+
+    #[test]
+    fn registry_functions() {
+        let functions = registry();
+        let options = functions.parse_options(&ParseOptions::default());
+        let texts = [
+            "f(x) + g(y)",
+            "max(x, y, 3) - answer()",
+            "rate(x)x + rate(y)y",
+            "f(t) = 2t; f(x) + g(y)",
+        ];
+        for text in texts.iter() {
+            let tree = parse_with(text, &options).unwrap();
+            let compiled =
+                compile_with(&tree, &["x", "y"], &Limits::default(), &functions).unwrap();
+            let (xs, ys) = ([5., 1., -3., 0.], [1., 2., 0.5, -1.]);
+            let results = compiled.eval_batch(xs.len(), &[&xs, &ys]);
+            for (row, (x, y)) in xs.iter().zip(&ys).enumerate() {
+                let context = create_context! {'x' => *x, 'y' => *y};
+                let expected = interpret_tree_with_functions(
+                    tree.clone(),
+                    &context,
+                    &functions,
+                    &Limits::default(),
+                );
+                assert_eq!(compiled.eval(&[*x, *y]), expected, "{}", text);
+                assert_eq!(results[row], expected, "{}", text);
+            }
+        }
+
+        let tree = parse("sqrt(x)").unwrap();
+        let err = compile_with(&tree, &["x"], &Limits::default(), &Registry::empty()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::UndefinedFunction("sqrt".to_string()));
+    }
+
+    #[test]
+    fn registry_limits() {
+        let options = ParseOptions::default();
+        let mut functions = registry();
+        functions.define("f(t) = g(t)", &options).unwrap();
+        let tree = parse_with("1 + g(x)", &functions.parse_options(&options)).unwrap();
+        let err = compile_with(&tree, &["x"], &Limits::default(), &functions).unwrap_err();
+        assert_eq!(err.code, ErrorCode::CallsTooDeep(32));
+
+        let limits = Limits {
+            max_steps: 5,
+            ..Limits::default()
+        };
+        let tree = parse("h(t) = t + t; h(x)").unwrap();
+        assert!(compile_with(&tree, &["x"], &limits, &functions).is_ok());
+        let tree = parse("h(t) = t + t; h(h(x))").unwrap();
+        let err = compile_with(&tree, &["x"], &limits, &functions).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManySteps(5));
+    }
+}
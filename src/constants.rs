@@ -0,0 +1,69 @@
+/// A built-in named constant which can be used in a Serious expression, e.g. `2pi r`.
+#[derive(Debug, Clone, Copy)]
+pub struct NamedConstant {
+    /// The name used to refer to the constant.
+    pub name: &'static str,
+    /// The value of the constant.
+    pub value: f64,
+}
+
+/// The constants available to every Serious expression, unless disabled by [`ParseOptions::builtin_constants`](crate::parser::ParseOptions::builtin_constants).
+///
+/// A [`Context`](crate::interpreter::Context) binding of the same name shadows the built-in value.
+///
+/// | Constant | Value
+/// | -------- | -----
+/// | `pi`     | [π](std::f64::consts::PI)
+/// | `tau`    | [τ](std::f64::consts::TAU)
+/// | `e`      | [Euler's number](std::f64::consts::E)
+/// | `phi`    | The golden ratio, (1 + √5)/2
+pub const CONSTANTS: &[NamedConstant] = &[
+    NamedConstant {
+        name: "pi",
+        value: std::f64::consts::PI,
+    },
+    NamedConstant {
+        name: "tau",
+        value: std::f64::consts::TAU,
+    },
+    NamedConstant {
+        name: "e",
+        value: std::f64::consts::E,
+    },
+    NamedConstant {
+        name: "phi",
+        value: 1.618_033_988_749_895,
+    },
+];
+
+/// Looks up a [`NamedConstant`](crate::constants::NamedConstant) by name.
+pub fn constant(name: &str) -> Option<&'static NamedConstant> {
+    CONSTANTS.iter().find(|constant| constant.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(constant("pi").unwrap().value, std::f64::consts::PI);
+        assert_eq!(
+            constant("tau").unwrap().value,
+            2. * constant("pi").unwrap().value
+        );
+    }
+
+    #[test]
+    fn lookup_missing() {
+        assert!(constant("inf").is_none());
+        assert!(constant("PI").is_none());
+    }
+
+    #[test]
+    fn golden_ratio() {
+        let phi = constant("phi").unwrap().value;
+        assert_eq!(phi, (1. + 5f64.sqrt()) / 2.);
+        assert!((phi * phi - phi - 1.).abs() < f64::EPSILON);
+    }
+}
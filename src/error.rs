@@ -1,47 +1,684 @@
+use super::parser::Operation;
+use super::span::{char_offset, clamp, line_column};
+use std::fmt;
+
 /// Categories for the errors from [`Result::Err`]s of [`parse`](crate::parser::parse), [`interpret`](crate::interpreter::interpret), and [`interpret_tree`](crate::interpreter::interpret_tree).
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ErrorType {
     /// Returned by [`parse`](crate::parser::parse)/[`interpret`](crate::interpreter::interpret) at unexpected tokens or unmatched parentheses.
     BadParse,
-    /// Returned by [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if an [`Identifier`](crate::parser::ExpressionData::Identifier) is not bound in the [`Context`](crate::interpreter::Context).
+    /// Returned by [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if an [`Identifier`](crate::parser::ExpressionData::Identifier) is not bound by the [`Resolver`](crate::interpreter::Resolver).
     UnboundIdentifier,
-    /// Returned by [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if an [`Operation`](crate::parser::Operation) returns NaN or a division by 0 is attempted.
+    /// Returned by [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if an [`Operation`](crate::parser::Operation) returns NaN or a division by 0 is attempted,
+    /// or if a function fails or is called with the wrong number of arguments.
     UndefinedOperation,
     /// Returned by [`parse`](crate::parser::parse)/[`interpret`](crate::interpreter::interpret) if a literal constant is too large to fit in an [`f64`] or by [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if an operation returns an infinity.
     Overflow,
+    /// Returned by [`parse`](crate::parser::parse)/[`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree) if the input or its evaluation exceeds one of the [`Limits`](crate::limits::Limits).
+    LimitExceeded,
+    /// Returned by [`derivative`](crate::calculus::derivative) if the expression calls a function whose derivative is not known.
+    Unsupported,
+}
+
+/// The specific cause of an [`Error`](crate::error::Error), with the values involved.
+///
+/// Each variant has a stable [`id`](crate::error::ErrorCode::id) which will not change between versions, and the
+/// [`ErrorType`](crate::error::ErrorType) and message of an error are derived from its code, so errors can be
+/// matched or localized without depending on the wording of the message.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ErrorCode {
+    /// The text is empty.
+    EmptyInput,
+    /// A character which is not part of the language.
+    InvalidCharacter(char),
+    /// A number literal which is not a valid [`f64`], e.g. `1.2.3`.
+    InvalidNumber(String),
+    /// A number literal which is too large to fit in an [`f64`].
+    NumberOverflow(String),
+    /// An operand is missing, e.g. after the `+` in `2 + * 3`.
+    ExpectedExpression,
+    /// An open paren without a close paren, or a close paren without an open paren.
+    UnmatchedParen,
+    /// A comma which does not separate the arguments of a call, e.g. `(1, 2)`.
+    UnexpectedComma,
+    /// A statement before a `;` which is not an assignment to a single name or a definition of a function, e.g. `2x; x`.
+    ExpectedAssignment,
+    /// An `=` which is not part of an assignment or a definition before a `;`, e.g. `x + y = 2`.
+    UnexpectedEquals,
+    /// Text given to [`Registry::define`](crate::functions::Registry::define) which is not a single definition, e.g. `f = 2t`.
+    ExpectedDefinition,
+    /// A parameter which is named more than once in a definition, e.g. the second `x` in `f(x, x) = 2x`.
+    DuplicateParameter(String),
+    /// A definition whose body calls the function being defined, e.g. `f(t) = t f(t - 1)`.
+    RecursiveDefinition(String),
+    /// A definition of a [built-in](crate::functions) function, e.g. `sin(x) = x`.
+    RedefinedBuiltin(String),
+    /// A constant which would be the right-hand side of an implicit multiplication, e.g. the `3` in `x 3`.
+    ImplicitMultiplicationByConstant(f64),
+    /// A call with the wrong number of arguments.
+    WrongArgumentCount {
+        function: String,
+        expected: usize,
+        supplied: usize,
+    },
+    /// A call with fewer arguments than a [variadic](crate::functions::Arity::AtLeast) function takes.
+    TooFewArguments {
+        function: String,
+        minimum: usize,
+        supplied: usize,
+    },
+    /// An identifier with no value.
+    UnboundIdentifier(String),
+    /// A call to a function which does not exist.
+    UndefinedFunction(String),
+    /// An operation which returned NaN, or a division by 0.
+    UndefinedOperation { lhs: f64, op: Operation, rhs: f64 },
+    /// An operation which returned an infinity.
+    OperationOverflow { lhs: f64, op: Operation, rhs: f64 },
+    /// A call which returned NaN.
+    UndefinedCall {
+        function: String,
+        arguments: Vec<f64>,
+    },
+    /// A call which returned an infinity.
+    CallOverflow {
+        function: String,
+        arguments: Vec<f64>,
+    },
+    /// A call to a [registered](crate::functions::Registry::register) function which returned an error.
+    CallFailed { function: String, message: String },
+    /// An [`Error`](crate::parser::ExpressionData::Error) node from [`parse_recovering`](crate::parser::parse_recovering) was evaluated.
+    Unparsed,
+    /// A [derivative](crate::calculus::derivative) of a function whose derivative is not known.
+    NoDerivative(String),
+    /// An expression nested deeper than [`Limits::max_depth`](crate::limits::Limits::max_depth).
+    TooDeep(usize),
+    /// Text longer than [`Limits::max_length`](crate::limits::Limits::max_length).
+    TooLong { length: usize, limit: usize },
+    /// Text with more tokens than [`Limits::max_tokens`](crate::limits::Limits::max_tokens).
+    TooManyTokens { count: usize, limit: usize },
+    /// An expression with more nodes than [`Limits::max_nodes`](crate::limits::Limits::max_nodes).
+    TooManyNodes(usize),
+    /// An evaluation which takes more steps than [`Limits::max_steps`](crate::limits::Limits::max_steps).
+    TooManySteps(usize),
+    /// Calls to defined functions nested deeper than [`Limits::max_call_depth`](crate::limits::Limits::max_call_depth).
+    CallsTooDeep(usize),
+    /// An error made with [`Error::new`](crate::error::Error::new) from a type and a message, rather than from a code.
+    Custom {
+        error_type: ErrorType,
+        message: String,
+    },
+}
+
+fn op_representation(op: Operation) -> char {
+    match op {
+        Operation::Exponentiate => '^',
+        Operation::Multiply => '*',
+        Operation::Divide => '/',
+        Operation::Add => '+',
+        Operation::Subtract => '-',
+    }
+}
+
+fn operand_representation(val: f64) -> String {
+    if val < 0. {
+        format!("({})", val)
+    } else {
+        format!("{}", val)
+    }
+}
+
+fn arguments_representation(arguments: &[f64]) -> String {
+    let arguments: Vec<String> = arguments.iter().map(f64::to_string).collect();
+    arguments.join(", ")
+}
+
+// e.g. `1 argument` or `2 arguments`
+fn arguments(count: usize) -> String {
+    format!("{} argument{}", count, if count == 1 { "" } else { "s" })
+}
+
+// e.g. `1 was` or `2 were`
+fn were(count: usize) -> String {
+    format!("{} {}", count, if count == 1 { "was" } else { "were" })
+}
+
+impl ErrorCode {
+    /// A stable identifier for the code, e.g. `"unmatched_paren"`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            ErrorCode::EmptyInput => "empty_input",
+            ErrorCode::InvalidCharacter(_) => "invalid_character",
+            ErrorCode::InvalidNumber(_) => "invalid_number",
+            ErrorCode::NumberOverflow(_) => "number_overflow",
+            ErrorCode::ExpectedExpression => "expected_expression",
+            ErrorCode::UnmatchedParen => "unmatched_paren",
+            ErrorCode::UnexpectedComma => "unexpected_comma",
+            ErrorCode::ExpectedAssignment => "expected_assignment",
+            ErrorCode::UnexpectedEquals => "unexpected_equals",
+            ErrorCode::ExpectedDefinition => "expected_definition",
+            ErrorCode::DuplicateParameter(_) => "duplicate_parameter",
+            ErrorCode::RecursiveDefinition(_) => "recursive_definition",
+            ErrorCode::RedefinedBuiltin(_) => "redefined_builtin",
+            ErrorCode::ImplicitMultiplicationByConstant(_) => "implicit_multiplication_by_constant",
+            ErrorCode::WrongArgumentCount { .. } => "wrong_argument_count",
+            ErrorCode::TooFewArguments { .. } => "too_few_arguments",
+            ErrorCode::UnboundIdentifier(_) => "unbound_identifier",
+            ErrorCode::UndefinedFunction(_) => "undefined_function",
+            ErrorCode::UndefinedOperation { .. } => "undefined_operation",
+            ErrorCode::OperationOverflow { .. } => "operation_overflow",
+            ErrorCode::UndefinedCall { .. } => "undefined_call",
+            ErrorCode::CallOverflow { .. } => "call_overflow",
+            ErrorCode::CallFailed { .. } => "call_failed",
+            ErrorCode::Unparsed => "unparsed",
+            ErrorCode::NoDerivative(_) => "no_derivative",
+            ErrorCode::TooDeep(_) => "too_deep",
+            ErrorCode::TooLong { .. } => "too_long",
+            ErrorCode::TooManyTokens { .. } => "too_many_tokens",
+            ErrorCode::TooManyNodes(_) => "too_many_nodes",
+            ErrorCode::TooManySteps(_) => "too_many_steps",
+            ErrorCode::CallsTooDeep(_) => "calls_too_deep",
+            ErrorCode::Custom { .. } => "custom",
+        }
+    }
+
+    /// The category of the code.
+    pub fn error_type(&self) -> ErrorType {
+        match self {
+            ErrorCode::EmptyInput
+            | ErrorCode::InvalidCharacter(_)
+            | ErrorCode::InvalidNumber(_)
+            | ErrorCode::ExpectedExpression
+            | ErrorCode::UnmatchedParen
+            | ErrorCode::UnexpectedComma
+            | ErrorCode::ExpectedAssignment
+            | ErrorCode::UnexpectedEquals
+            | ErrorCode::ExpectedDefinition
+            | ErrorCode::DuplicateParameter(_)
+            | ErrorCode::RecursiveDefinition(_)
+            | ErrorCode::RedefinedBuiltin(_)
+            | ErrorCode::ImplicitMultiplicationByConstant(_)
+            | ErrorCode::Unparsed => ErrorType::BadParse,
+            ErrorCode::UnboundIdentifier(_) | ErrorCode::UndefinedFunction(_) => {
+                ErrorType::UnboundIdentifier
+            }
+            ErrorCode::UndefinedOperation { .. }
+            | ErrorCode::UndefinedCall { .. }
+            | ErrorCode::CallFailed { .. }
+            | ErrorCode::WrongArgumentCount { .. }
+            | ErrorCode::TooFewArguments { .. } => ErrorType::UndefinedOperation,
+            ErrorCode::NumberOverflow(_)
+            | ErrorCode::OperationOverflow { .. }
+            | ErrorCode::CallOverflow { .. } => ErrorType::Overflow,
+            ErrorCode::TooDeep(_)
+            | ErrorCode::TooLong { .. }
+            | ErrorCode::TooManyTokens { .. }
+            | ErrorCode::TooManyNodes(_)
+            | ErrorCode::TooManySteps(_)
+            | ErrorCode::CallsTooDeep(_) => ErrorType::LimitExceeded,
+            ErrorCode::NoDerivative(_) => ErrorType::Unsupported,
+            ErrorCode::Custom { error_type, .. } => *error_type,
+        }
+    }
+
+    /// The English description of the code, e.g. `"4.3/0 is undefined"`.
+    pub fn message(&self) -> String {
+        match self {
+            ErrorCode::EmptyInput => "expected token".to_string(),
+            ErrorCode::InvalidCharacter(c) => format!("invalid character '{}'", c),
+            ErrorCode::InvalidNumber(_) => "invalid float literal".to_string(),
+            ErrorCode::NumberOverflow(_) => "number overflowed f64".to_string(),
+            ErrorCode::ExpectedExpression => "expected expression".to_string(),
+            ErrorCode::UnmatchedParen => "failed to match paren".to_string(),
+            ErrorCode::UnexpectedComma => "unexpected comma outside of function call".to_string(),
+            ErrorCode::ExpectedAssignment => {
+                "expected an assignment such as 'd = b^2 - 4ac' or a definition such as 'f(t) = 3t^2 + 1' before ';'"
+                    .to_string()
+            }
+            ErrorCode::UnexpectedEquals => {
+                "'=' is only allowed in an assignment or a definition before ';'".to_string()
+            }
+            ErrorCode::ExpectedDefinition => {
+                "expected a definition such as 'f(t) = 3t^2 + 1'".to_string()
+            }
+            ErrorCode::DuplicateParameter(name) => format!("parameter '{}' is repeated", name),
+            ErrorCode::RecursiveDefinition(name) => {
+                format!("function '{}' cannot call itself", name)
+            }
+            ErrorCode::RedefinedBuiltin(name) => {
+                format!("function '{}' is built in and cannot be redefined", name)
+            }
+            ErrorCode::ImplicitMultiplicationByConstant(_) => {
+                "constant on RHS of implicit multiplication".to_string()
+            }
+            ErrorCode::WrongArgumentCount {
+                function,
+                expected,
+                supplied,
+            } => format!(
+                "function '{}' takes {} but {} supplied",
+                function,
+                arguments(*expected),
+                were(*supplied)
+            ),
+            ErrorCode::TooFewArguments {
+                function,
+                minimum,
+                supplied,
+            } => format!(
+                "function '{}' takes at least {} but {} supplied",
+                function,
+                arguments(*minimum),
+                were(*supplied)
+            ),
+            ErrorCode::UnboundIdentifier(name) => format!("identifier '{}' is not bound", name),
+            ErrorCode::UndefinedFunction(name) => format!("function '{}' is not defined", name),
+            ErrorCode::UndefinedOperation { lhs, op, rhs } => format!(
+                "{}{}{} is undefined",
+                operand_representation(*lhs),
+                op_representation(*op),
+                operand_representation(*rhs)
+            ),
+            ErrorCode::OperationOverflow { lhs, op, rhs } => format!(
+                "{}{}{} overflowed f64",
+                operand_representation(*lhs),
+                op_representation(*op),
+                operand_representation(*rhs)
+            ),
+            ErrorCode::UndefinedCall {
+                function,
+                arguments,
+            } => format!("{}({}) is undefined", function, arguments_representation(arguments)),
+            ErrorCode::CallOverflow {
+                function,
+                arguments,
+            } => format!(
+                "{}({}) overflowed f64",
+                function,
+                arguments_representation(arguments)
+            ),
+            ErrorCode::CallFailed { function, message } => {
+                format!("function '{}' failed: {}", function, message)
+            }
+            ErrorCode::Unparsed => "expression failed to parse".to_string(),
+            ErrorCode::NoDerivative(name) => format!("function '{}' has no known derivative", name),
+            ErrorCode::TooDeep(limit) => format!("expression is nested deeper than {}", limit),
+            ErrorCode::TooLong { length, limit } => {
+                format!("text is {} bytes long, over the limit of {}", length, limit)
+            }
+            ErrorCode::TooManyTokens { count, limit } => {
+                format!("text has {} tokens, over the limit of {}", count, limit)
+            }
+            ErrorCode::TooManyNodes(limit) => format!("expression has more than {} nodes", limit),
+            ErrorCode::TooManySteps(limit) => {
+                format!("evaluation took more than {} steps", limit)
+            }
+            ErrorCode::CallsTooDeep(limit) => {
+                format!("calls to defined functions are nested deeper than {}", limit)
+            }
+            ErrorCode::Custom { message, .. } => message.clone(),
+        }
+    }
 }
 
 /// Defines the type for [`Result::Err`]s of [`parse`](crate::parser::parse), [`interpret`](crate::interpreter::interpret), and [`interpret_tree`](crate::interpreter::interpret_tree).
 ///
-/// `start` and `end` constitute the text span of the erroneous [`Expression`](crate::parser::Expression).
+/// `start` and `end` constitute the text span of the erroneous [`Expression`](crate::parser::Expression), as byte offsets;
+/// see [`span`](crate::span) to convert them into lines and columns.
+/// The [`error_type`](crate::error::Error::error_type) and [`message`](crate::error::Error::message) are derived from the [`ErrorCode`](crate::error::ErrorCode).
 ///
 /// ```
-/// use serious::{create_context, interpreter::interpret, error::Error, error::ErrorType};
+/// use serious::{create_context, interpreter::interpret, parser::Operation};
+/// use serious::error::{Error, ErrorCode, ErrorType};
 ///
 /// let err = interpret("6 + 4.3/(25 - 5^2)", &create_context!{}).unwrap_err();
 ///
-/// assert_eq!(err, Error::new(
-///     ErrorType::UndefinedOperation,
-///     "4.3/0 is undefined".to_string(),
+/// assert_eq!(err, Error::from_code(
+///     ErrorCode::UndefinedOperation { lhs: 4.3, op: Operation::Divide, rhs: 0. },
 ///     4,
 ///     18
 /// ));
+/// assert_eq!(err.error_type(), ErrorType::UndefinedOperation);
+/// assert_eq!(err.message(), "4.3/0 is undefined");
+/// assert_eq!(err.code.id(), "undefined_operation");
 /// ```
 #[derive(Debug, PartialEq)]
 pub struct Error {
-    pub error_type: ErrorType,
-    pub message: String,
+    pub code: ErrorCode,
     pub start: usize,
     pub end: usize,
 }
 
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorType::BadParse => "bad parse",
+            ErrorType::UnboundIdentifier => "unbound identifier",
+            ErrorType::UndefinedOperation => "undefined operation",
+            ErrorType::Overflow => "overflow",
+            ErrorType::LimitExceeded => "limit exceeded",
+            ErrorType::Unsupported => "unsupported",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+fn paint(text: &str, colour: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", colour, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
 impl Error {
+    /// Makes an error from a type and a message, with a [`Custom`](crate::error::ErrorCode::Custom) code.
+    #[deprecated(note = "use `Error::from_code`, which derives the type and message from a code")]
     pub fn new(error_type: ErrorType, message: String, start: usize, end: usize) -> Error {
-        Error {
+        let code = ErrorCode::Custom {
             error_type,
             message,
-            start,
-            end,
-        }
+        };
+        Error::from_code(code, start, end)
+    }
+
+    /// Makes an error from a code, from which its type and message are derived.
+    pub fn from_code(code: ErrorCode, start: usize, end: usize) -> Error {
+        Error { code, start, end }
+    }
+
+    /// The category of the error, derived from its [`code`](crate::error::ErrorCode::error_type).
+    pub fn error_type(&self) -> ErrorType {
+        self.code.error_type()
+    }
+
+    /// The English description of the error, derived from its [`code`](crate::error::ErrorCode::message).
+    pub fn message(&self) -> String {
+        self.code.message()
+    }
+
+    /// Renders the error against the `source` it came from: the [`ErrorType`](crate::error::ErrorType) and message,
+    /// then the offending line with the span underlined by a caret and tildes. With `colour`, the output uses ANSI escape codes.
+    ///
+    /// ```
+    /// use serious::parser::parse;
+    ///
+    /// let source = "2(x + 1";
+    /// let err = parse(source).unwrap_err();
+    ///
+    /// assert_eq!(err.render(source, false), "\
+    /// bad parse: failed to match paren
+    ///   |
+    /// 1 | 2(x + 1
+    ///   |  ^
+    /// ");
+    /// ```
+    pub fn render(&self, source: &str, colour: bool) -> String {
+        let start = clamp(source, self.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line_number = line_column(source, start).line;
+
+        // tabs are kept in the padding so the caret lines up with the source
+        let padding: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // an inverted span is shown as a single caret at its start
+        let end = self.end.clamp(start, line_end);
+        let underlined = char_offset(source, end).saturating_sub(char_offset(source, start));
+        let underline = format!("^{}", "~".repeat(underlined.saturating_sub(1)));
+
+        let number = line_number.to_string();
+        let gutter = " ".repeat(number.len());
+        let bar = paint("|", BLUE, colour);
+        format!(
+            "{}: {}\n{} {}\n{} {} {}\n{} {} {}{}\n",
+            paint(&self.error_type().to_string(), RED, colour),
+            self.message(),
+            gutter,
+            bar,
+            paint(&number, BLUE, colour),
+            bar,
+            source[line_start..line_end].trim_end_matches('\r'),
+            gutter,
+            bar,
+            padding,
+            paint(&underline, RED, colour),
+        )
+    }
+}
+
+/// Writes the [`ErrorType`](crate::error::ErrorType), the span, and the message, e.g. `bad parse at 4..5: expected expression`.
+/// Use [`render`](crate::error::Error::render) to show the span within the source text.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}: {}",
+            self.error_type(),
+            self.start,
+            self.end,
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_context;
+    use crate::interpreter::interpret;
+    use crate::parser::parse;
+
+    #[test]
+    fn display() {
+        let err = parse("2 + * 3").unwrap_err();
+        assert_eq!(err.to_string(), "bad parse at 4..5: expected expression");
+        let err: Box<dyn std::error::Error> = Box::new(err);
+        assert_eq!(err.to_string(), "bad parse at 4..5: expected expression");
+    }
+
+    #[test]
+    fn render_span() {
+        let source = "6 + 4.3/(25 - 5^2)";
+        let err = interpret(source, &create_context! {}).unwrap_err();
+        assert_eq!(
+            err.render(source, false),
+            "undefined operation: 4.3/0 is undefined\n  |\n1 | 6 + 4.3/(25 - 5^2)\n  |     ^~~~~~~~~~~~~~\n"
+        );
+    }
+
+    #[test]
+    fn render_end_of_input() {
+        let source = "2 +";
+        let err = parse(source).unwrap_err();
+        assert_eq!(err.start, 3);
+        assert_eq!(
+            err.render(source, false),
+            "bad parse: expected expression\n  |\n1 | 2 +\n  |    ^\n"
+        );
+    }
+
+    #[test]
+    fn render_inverted_span() {
+        let err = Error::from_code(ErrorCode::UnboundIdentifier("y".to_string()), 4, 1);
+        assert_eq!(
+            err.render("2x + y", false),
+            "unbound identifier: identifier 'y' is not bound\n  |\n1 | 2x + y\n  |     ^\n"
+        );
+    }
+
+    #[test]
+    fn render_later_line() {
+        let err = Error::from_code(ErrorCode::UnboundIdentifier("y".to_string()), 20, 21);
+        assert_eq!(
+            err.render("1\n2\n3\n4\n5\n6\n7\n8\n9\n\t2y", false),
+            "unbound identifier: identifier 'y' is not bound\n   |\n10 | \t2y\n   | \t ^\n"
+        );
+    }
+
+    #[test]
+    fn render_colour() {
+        let source = "x $";
+        let err = parse(source).unwrap_err();
+        assert_eq!(
+            err.render(source, true),
+            "\x1b[1;31mbad parse\x1b[0m: invalid character '$'\n  \x1b[1;34m|\x1b[0m\n\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m x $\n  \x1b[1;34m|\x1b[0m   \x1b[1;31m^\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn derived_from_code() {
+        let err = Error::from_code(ErrorCode::InvalidCharacter('$'), 2, 3);
+        assert_eq!(err.error_type(), ErrorType::BadParse);
+        assert_eq!(err.message(), "invalid character '$'");
+        assert_eq!(err.code.id(), "invalid_character");
+
+        let code = ErrorCode::OperationOverflow {
+            lhs: -10.,
+            op: Operation::Exponentiate,
+            rhs: 400.,
+        };
+        assert_eq!(code.error_type(), ErrorType::Overflow);
+        assert_eq!(code.message(), "(-10)^400 overflowed f64");
+    }
+
+    #[test]
+    fn argument_count_message() {
+        let code = |expected| ErrorCode::WrongArgumentCount {
+            function: "f".to_string(),
+            expected,
+            supplied: 3,
+        };
+        assert_eq!(
+            code(1).message(),
+            "function 'f' takes 1 argument but 3 were supplied"
+        );
+        assert_eq!(
+            code(2).message(),
+            "function 'f' takes 2 arguments but 3 were supplied"
+        );
+        let code = ErrorCode::WrongArgumentCount {
+            function: "f".to_string(),
+            expected: 2,
+            supplied: 1,
+        };
+        assert_eq!(
+            code.message(),
+            "function 'f' takes 2 arguments but 1 was supplied"
+        );
+    }
+
+    #[test]
+    fn native_call_messages() {
+        let code = ErrorCode::TooFewArguments {
+            function: "max".to_string(),
+            minimum: 2,
+            supplied: 1,
+        };
+        assert_eq!(code.error_type(), ErrorType::UndefinedOperation);
+        assert_eq!(
+            code.message(),
+            "function 'max' takes at least 2 arguments but 1 was supplied"
+        );
+        let code = ErrorCode::CallOverflow {
+            function: "f".to_string(),
+            arguments: vec![1., -2.5],
+        };
+        assert_eq!(code.message(), "f(1, -2.5) overflowed f64");
+        let code = ErrorCode::CallFailed {
+            function: "tax".to_string(),
+            message: "income is negative".to_string(),
+        };
+        assert_eq!(code.error_type(), ErrorType::UndefinedOperation);
+        assert_eq!(code.id(), "call_failed");
+        assert_eq!(code.message(), "function 'tax' failed: income is negative");
+    }
+
+    #[test]
+    fn codes_from_parse_and_interpret() {
+        let code = |text| match interpret(text, &create_context! {'x' => 1.}) {
+            Err(err) => err.code,
+            Ok(_) => panic!("{} should fail", text),
+        };
+        assert_eq!(
+            code("1e400"),
+            ErrorCode::NumberOverflow("1e400".to_string())
+        );
+        assert_eq!(code("x 2"), ErrorCode::ImplicitMultiplicationByConstant(2.));
+        assert_eq!(code("y"), ErrorCode::UnboundIdentifier("y".to_string()));
+        assert_eq!(
+            code("ln(-x)"),
+            ErrorCode::UndefinedCall {
+                function: "ln".to_string(),
+                arguments: vec![-1.]
+            }
+        );
+        assert_eq!(
+            code("f(t) = t f(t); 1"),
+            ErrorCode::RecursiveDefinition("f".to_string())
+        );
+    }
+
+    #[test]
+    fn definition_messages() {
+        assert_eq!(
+            ErrorCode::DuplicateParameter("x".to_string()).message(),
+            "parameter 'x' is repeated"
+        );
+        assert_eq!(
+            ErrorCode::RedefinedBuiltin("sin".to_string()).message(),
+            "function 'sin' is built in and cannot be redefined"
+        );
+        assert_eq!(
+            ErrorCode::CallsTooDeep(32).error_type(),
+            ErrorType::LimitExceeded
+        );
+        assert_eq!(ErrorCode::CallsTooDeep(32).id(), "calls_too_deep");
+        let code = ErrorCode::NoDerivative("sec".to_string());
+        assert_eq!(code.error_type(), ErrorType::Unsupported);
+        assert_eq!(
+            Error::from_code(code, 0, 6).to_string(),
+            "unsupported at 0..6: function 'sec' has no known derivative"
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn custom() {
+        let err = Error::new(ErrorType::BadParse, "expected token".to_string(), 0, 1);
+        assert_eq!(err.error_type(), ErrorType::BadParse);
+        assert_eq!(err.message(), "expected token");
+        assert_eq!(err.code.id(), "custom");
+        assert_eq!(err.to_string(), "bad parse at 0..1: expected token");
+    }
+
+    #[test]
+    fn render_wide_chars() {
+        let source = "2π × x";
+        let err = Error::from_code(ErrorCode::InvalidCharacter('×'), 4, 6);
+        assert_eq!(
+            err.render(source, false),
+            "bad parse: invalid character '×'\n  |\n1 | 2π × x\n  |    ^\n"
+        );
+    }
+
+    #[test]
+    fn render_crlf() {
+        let source = "1 +\r\n2 + )";
+        let err = parse(source).unwrap_err();
+        assert_eq!(
+            err.render(source, false),
+            "bad parse: expected expression\n  |\n2 | 2 + )\n  |     ^\n"
+        );
     }
 }
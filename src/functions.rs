@@ -0,0 +1,450 @@
+use super::error::Error;
+use super::parser::{parse_definition, Definition, ParseOptions};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// A built-in function of one argument which can be called from a Serious expression, e.g. `sin(x)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Builtin {
+    /// The name used to call the function.
+    pub name: &'static str,
+    /// The implementation over [`f64`].
+    pub function: fn(f64) -> f64,
+}
+
+/// The functions available to every Serious expression, unless they are removed from a [`Registry`](crate::functions::Registry).
+/// - Any NaN result will yield an [`UndefinedOperation`](crate::error::ErrorType::UndefinedOperation) error.
+/// - Any infinite result will yield an [`Overflow`](crate::error::ErrorType::Overflow) error.
+///
+/// | Function | Meaning         | Function | Meaning
+/// | -------- | --------------- | -------- | -------
+/// | `sqrt`   | [f64::sqrt]     | `sinh`   | [f64::sinh]
+/// | `cbrt`   | [f64::cbrt]     | `cosh`   | [f64::cosh]
+/// | `exp`    | [f64::exp]      | `tanh`   | [f64::tanh]
+/// | `ln`     | [f64::ln]       | `asin`   | [f64::asin]
+/// | `log`    | [f64::log10]    | `acos`   | [f64::acos]
+/// | `log2`   | [f64::log2]     | `atan`   | [f64::atan]
+/// | `sin`    | [f64::sin]      | `abs`    | [f64::abs]
+/// | `cos`    | [f64::cos]      | `floor`  | [f64::floor]
+/// | `tan`    | [f64::tan]      | `ceil`   | [f64::ceil]
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sqrt",
+        function: f64::sqrt,
+    },
+    Builtin {
+        name: "cbrt",
+        function: f64::cbrt,
+    },
+    Builtin {
+        name: "exp",
+        function: f64::exp,
+    },
+    Builtin {
+        name: "ln",
+        function: f64::ln,
+    },
+    Builtin {
+        name: "log",
+        function: f64::log10,
+    },
+    Builtin {
+        name: "log2",
+        function: f64::log2,
+    },
+    Builtin {
+        name: "sin",
+        function: f64::sin,
+    },
+    Builtin {
+        name: "cos",
+        function: f64::cos,
+    },
+    Builtin {
+        name: "tan",
+        function: f64::tan,
+    },
+    Builtin {
+        name: "sinh",
+        function: f64::sinh,
+    },
+    Builtin {
+        name: "cosh",
+        function: f64::cosh,
+    },
+    Builtin {
+        name: "tanh",
+        function: f64::tanh,
+    },
+    Builtin {
+        name: "asin",
+        function: f64::asin,
+    },
+    Builtin {
+        name: "acos",
+        function: f64::acos,
+    },
+    Builtin {
+        name: "atan",
+        function: f64::atan,
+    },
+    Builtin {
+        name: "abs",
+        function: f64::abs,
+    },
+    Builtin {
+        name: "floor",
+        function: f64::floor,
+    },
+    Builtin {
+        name: "ceil",
+        function: f64::ceil,
+    },
+];
+
+/// Looks up a [`Builtin`](crate::functions::Builtin) by name.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// The number of arguments accepted by a function [registered](crate::functions::Registry::register) in a [`Registry`](crate::functions::Registry).
+///
+/// A function which takes no arguments is called as `f()`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arity {
+    /// Exactly this many arguments, e.g. `Exactly(3)` for `lerp(a, b, t)`.
+    Exactly(usize),
+    /// This many arguments or more, e.g. `AtLeast(1)` for `max(a, b, c)`.
+    AtLeast(usize),
+}
+
+/// A function implemented in Rust, which is given the values of the arguments of a call.
+///
+/// An error is a message explaining why the call failed, which is reported as a [`CallFailed`](crate::error::ErrorCode::CallFailed) error.
+pub type NativeFunction = dyn Fn(&[f64]) -> Result<f64, String> + Send + Sync;
+
+// a function in a registry, which is looked up by the interpreter for every call
+#[derive(Clone)]
+pub(crate) enum Entry {
+    Builtin(&'static Builtin),
+    Native(String, Arity, Arc<NativeFunction>),
+    Defined(Definition),
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        match self {
+            Entry::Builtin(builtin) => builtin.name,
+            Entry::Native(name, _, _) => name,
+            Entry::Defined(definition) => &definition.name,
+        }
+    }
+}
+
+/// The functions which can be called from an expression, which can be passed to
+/// [`interpret_with_functions`](crate::interpreter::interpret_with_functions) alongside a [`Context`](crate::interpreter::Context).
+///
+/// A new registry holds the [built-in](crate::functions::BUILTINS) functions, which is the registry used by
+/// [`interpret`](crate::interpreter::interpret). Functions can be added to it, either
+/// [implemented in Rust](crate::functions::Registry::register) or [defined](crate::functions::Registry::define) in Serious,
+/// and any function, including a built-in one, can be replaced or [removed](crate::functions::Registry::remove).
+///
+/// The body of a defined function can call the functions in the registry, which are looked up when the call is made rather
+/// than when the body is defined, so replacing a function changes every body which calls it. Redefinitions can thus make
+/// functions call each other in a cycle, which stops with a [`CallsTooDeep`](crate::error::ErrorCode::CallsTooDeep) error
+/// once calls nest deeper than [`Limits::max_call_depth`](crate::limits::Limits::max_call_depth). The identifiers in a body,
+/// other than its parameters, are resolved from the context of each call.
+///
+/// ```
+/// use serious::{create_context, interpreter::interpret_with_functions};
+/// use serious::functions::Registry;
+/// use serious::parser::ParseOptions;
+///
+/// let options = ParseOptions::default();
+/// let mut functions = Registry::new();
+/// functions.define("f(t) = 3t^2 + 1", &options).unwrap();
+/// functions.define("g(a, b) = f(a) - f(b)", &options).unwrap();
+///
+/// let context = create_context!{'x' => 1., 'y' => 2.};
+/// assert_eq!(interpret_with_functions("f(x) + f(y)", &context, &functions, &options).unwrap(), 17.);
+/// assert_eq!(interpret_with_functions("g(y, x) + sqrt(x)", &context, &functions, &options).unwrap(), 10.);
+///
+/// let err = interpret_with_functions("f(x, y)", &context, &functions, &options).unwrap_err();
+/// assert_eq!(err.code.id(), "wrong_argument_count");
+/// ```
+#[derive(Clone)]
+pub struct Registry {
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    /// Creates a registry of the [built-in](crate::functions::BUILTINS) functions.
+    pub fn new() -> Registry {
+        Registry {
+            entries: BUILTINS.iter().map(Entry::Builtin).collect(),
+        }
+    }
+
+    /// Creates a registry without any functions, not even the built-in ones.
+    pub fn empty() -> Registry {
+        Registry { entries: vec![] }
+    }
+
+    fn add(&mut self, entry: Entry) {
+        self.entries.retain(|added| added.name() != entry.name());
+        self.entries.push(entry);
+    }
+
+    /// Adds a function implemented in Rust, replacing any function of the same name.
+    ///
+    /// A call with a number of arguments which `arity` does not accept yields a
+    /// [`WrongArgumentCount`](crate::error::ErrorCode::WrongArgumentCount) or
+    /// [`TooFewArguments`](crate::error::ErrorCode::TooFewArguments) error, without calling `function`.
+    /// As for the built-in functions, a NaN result yields an [`UndefinedCall`](crate::error::ErrorCode::UndefinedCall) error
+    /// and an infinite one yields a [`CallOverflow`](crate::error::ErrorCode::CallOverflow) error.
+    /// Every error spans the call.
+    ///
+    /// ```
+    /// use serious::{create_context, interpreter::interpret_with_functions};
+    /// use serious::functions::{Arity, Registry};
+    /// use serious::parser::{IdentifierMode, ParseOptions};
+    ///
+    /// let mut functions = Registry::new();
+    /// functions.register("lerp", Arity::Exactly(3), |args| Ok(args[0] + (args[1] - args[0]) * args[2]));
+    /// functions.register("max", Arity::AtLeast(1), |args| Ok(args.iter().cloned().fold(f64::MIN, f64::max)));
+    /// functions.register("tax", Arity::Exactly(1), |args| match args[0] {
+    ///     income if income < 0. => Err("income is negative".to_string()),
+    ///     income if income <= 10000. => Ok(0.),
+    ///     income => Ok(0.2 * (income - 10000.)),
+    /// });
+    ///
+    /// let options = ParseOptions {
+    ///     identifier_mode: IdentifierMode::Word,
+    ///     ..ParseOptions::default()
+    /// };
+    /// let context = create_context!{"income" => 25000., "bonus" => 5000.};
+    /// let eval = |text| interpret_with_functions(text, &context, &functions, &options);
+    ///
+    /// assert_eq!(eval("lerp(0, 10, 0.25)").unwrap(), 2.5);
+    /// assert_eq!(eval("max(1, income, bonus)").unwrap(), 25000.);
+    /// assert_eq!(eval("tax(income + bonus)").unwrap(), 4000.);
+    ///
+    /// let err = eval("1 + tax(bonus - income)").unwrap_err();
+    /// assert_eq!(err.message(), "function 'tax' failed: income is negative");
+    /// assert_eq!((err.start, err.end), (4, 23));
+    /// ```
+    pub fn register<F>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&[f64]) -> Result<f64, String> + Send + Sync + 'static,
+    {
+        self.add(Entry::Native(name.to_string(), arity, Arc::new(function)));
+    }
+
+    /// Parses a definition such as `f(t) = 3t^2 + 1` and adds it, replacing any function of the same name.
+    ///
+    /// The text must be a single definition, whose body may not call the function itself.
+    pub fn define(&mut self, text: &str, options: &ParseOptions) -> Result<(), Error> {
+        let definition = parse_definition(text, &self.parse_options(options))?;
+        self.insert(definition);
+        Ok(())
+    }
+
+    /// Adds a definition, replacing any function of the same name.
+    pub fn insert(&mut self, definition: Definition) {
+        self.add(Entry::Defined(definition));
+    }
+
+    /// Removes a function, returning whether it was in the registry.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.name() != name);
+        self.entries.len() < len
+    }
+
+    /// Whether a function of the given name is in the registry.
+    pub fn contains(&self, name: &str) -> bool {
+        self.entry(name).is_some()
+    }
+
+    /// Looks up a [defined](crate::functions::Registry::define) function by name.
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        match self.entry(name) {
+            Some(Entry::Defined(definition)) => Some(definition),
+            _ => None,
+        }
+    }
+
+    /// The names of the functions in the registry, in the order they were added.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(Entry::name)
+    }
+
+    /// Adds the functions in the registry to [`ParseOptions::functions`](crate::parser::ParseOptions::functions), so that calls to them are parsed as calls.
+    /// The built-in functions are always recognized, so they are only added if they were replaced.
+    pub fn parse_options(&self, options: &ParseOptions) -> ParseOptions {
+        let mut options = options.clone();
+        let added = self
+            .entries
+            .iter()
+            .filter(|entry| !matches!(entry, Entry::Builtin(_)))
+            .map(|entry| entry.name().to_string());
+        options.functions.extend(added);
+        options
+    }
+
+    pub(crate) fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name() == name)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+// the registry of built-in functions is shared, so that evaluating an expression does not build one
+pub(crate) fn builtins() -> &'static Registry {
+    static BUILTINS_REGISTRY: OnceLock<Registry> = OnceLock::new();
+    BUILTINS_REGISTRY.get_or_init(Registry::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(builtin("sqrt").unwrap().name, "sqrt");
+        assert_eq!((builtin("log").unwrap().function)(100.), 2.);
+    }
+
+    #[test]
+    fn lookup_missing() {
+        assert!(builtin("sec").is_none());
+        assert!(builtin("").is_none());
+    }
+
+    #[test]
+    fn unique_names() {
+        for (i, a) in BUILTINS.iter().enumerate() {
+            for b in &BUILTINS[i + 1..] {
+                assert_ne!(a.name, b.name);
+            }
+        }
+    }
+
+    fn define(functions: &mut Registry, text: &str) -> Result<(), Error> {
+        functions.define(text, &ParseOptions::default())
+    }
+
+    #[test]
+    fn define_and_get() {
+        let mut functions = Registry::empty();
+        define(&mut functions, "f(t) = 3t^2 + 1").unwrap();
+        define(&mut functions, "area(w, h) = w h").unwrap_err();
+        define(&mut functions, "g(w, h) = w h").unwrap();
+        assert_eq!(functions.names().collect::<Vec<_>>(), ["f", "g"]);
+
+        let f = functions.definition("f").unwrap();
+        assert_eq!(f.parameters, ["t"]);
+        assert_eq!(f.body.to_string(), "3t^2 + 1");
+        assert!(functions.definition("h").is_none());
+    }
+
+    #[test]
+    fn redefine() {
+        let mut functions = Registry::empty();
+        define(&mut functions, "f(t) = t").unwrap();
+        define(&mut functions, "g(t) = 2t").unwrap();
+        define(&mut functions, "f(t) = g(t) + 1").unwrap();
+        assert_eq!(functions.names().collect::<Vec<_>>(), ["g", "f"]);
+        assert_eq!(
+            functions.definition("f").unwrap().body.to_string(),
+            "g(t) + 1"
+        );
+    }
+
+    #[test]
+    fn define_errors() {
+        let mut functions = Registry::empty();
+        let code = |functions: &mut Registry, text| define(functions, text).unwrap_err().code;
+        assert_eq!(
+            code(&mut functions, "f = 2t"),
+            ErrorCode::ExpectedDefinition
+        );
+        assert_eq!(
+            code(&mut functions, "f(t) = t; f(2)"),
+            ErrorCode::ExpectedDefinition
+        );
+        assert_eq!(
+            code(&mut functions, "f(t, t) = t"),
+            ErrorCode::DuplicateParameter("t".to_string())
+        );
+        assert_eq!(
+            code(&mut functions, "f(t) = t f(t - 1)"),
+            ErrorCode::RecursiveDefinition("f".to_string())
+        );
+        assert_eq!(
+            code(&mut functions, "f(t) = "),
+            ErrorCode::ExpectedExpression
+        );
+        assert!(functions.names().next().is_none());
+    }
+
+    #[test]
+    fn builtins_by_default() {
+        let functions = Registry::new();
+        assert_eq!(functions.names().count(), BUILTINS.len());
+        assert!(functions.contains("sqrt"));
+        assert!(functions.definition("sqrt").is_none());
+        assert!(!Registry::empty().contains("sqrt"));
+        assert!(Registry::default().contains("ceil"));
+        assert_eq!(builtins().names().count(), BUILTINS.len());
+    }
+
+    #[test]
+    fn register_and_remove() {
+        let mut functions = Registry::new();
+        functions.register("max", Arity::AtLeast(1), |args| {
+            Ok(args.iter().cloned().fold(f64::MIN, f64::max))
+        });
+        functions.register("sqrt", Arity::Exactly(1), |args| Ok(args[0].abs().sqrt()));
+        define(&mut functions, "f(t) = max(t, 0)").unwrap();
+        assert!(functions.contains("max"));
+        assert!(functions.definition("max").is_none());
+        assert_eq!(functions.names().filter(|&name| name == "sqrt").count(), 1);
+        assert_eq!(
+            functions
+                .names()
+                .skip(BUILTINS.len() - 1)
+                .collect::<Vec<_>>(),
+            ["max", "sqrt", "f"]
+        );
+
+        assert!(functions.remove("sqrt"));
+        assert!(!functions.remove("sqrt"));
+        assert!(functions.remove("sin"));
+        assert!(!functions.contains("sin"));
+        assert!(functions.contains("cos"));
+    }
+
+    #[test]
+    fn parse_options_add_registered_functions() {
+        let mut functions = Registry::new();
+        functions.register("lerp", Arity::Exactly(3), |args| {
+            Ok(args[0] + (args[1] - args[0]) * args[2])
+        });
+        define(&mut functions, "f(t) = t").unwrap();
+        let options = functions.parse_options(&ParseOptions::default());
+        assert_eq!(options.functions, ["lerp", "f"]);
+        assert_eq!(format!("{:?}", functions).matches("lerp").count(), 1);
+    }
+}
@@ -1,12 +1,88 @@
-use super::error::{Error, ErrorType};
-use super::parser::{parse, Expression, ExpressionData, Operation};
+use super::constants::constant;
+use super::error::{Error, ErrorCode};
+use super::functions::{builtins, Arity, Builtin, Entry, NativeFunction, Registry};
+use super::limits::Limits;
+use super::parser::{parse_with, Definition, Expression, ExpressionData, Operation, ParseOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
 
 /// A hashmap from identifiers to values which can be applied to an expression using [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree).
-pub type Context = std::collections::HashMap<char, f64>;
+pub type Context = HashMap<String, f64>;
+
+/// Looks up the values of identifiers for [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree).
+///
+/// Identifiers are resolved lazily as the expression is evaluated, so values can be read straight from where they live.
+/// It is implemented for maps and for slices, arrays and vectors of pairs from names to values, and for closures.
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use serious::interpreter::interpret;
+///
+/// assert_eq!(interpret("2x + y", &[("x", 1.), ("y", 3.)]).unwrap(), 5.);
+///
+/// let values = BTreeMap::from([("rate".to_string(), 0.5)]);
+/// assert_eq!(interpret("4r", &values).unwrap_err().code.id(), "unbound_identifier");
+///
+/// struct Particle {
+///     mass: f64,
+///     speed: f64,
+/// }
+/// let particle = Particle { mass: 2., speed: 3. };
+/// let resolver = |name: &str| match name {
+///     "m" => Some(particle.mass),
+///     "v" => Some(particle.speed),
+///     _ => None,
+/// };
+/// assert_eq!(interpret("m v^2/2", &resolver).unwrap(), 9.);
+/// ```
+pub trait Resolver {
+    /// The value bound to `name`, or [`None`] if it is unbound.
+    fn resolve(&self, name: &str) -> Option<f64>;
+}
+
+impl<S: BuildHasher> Resolver for HashMap<String, f64, S> {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self.get(name).copied()
+    }
+}
+
+impl Resolver for BTreeMap<String, f64> {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self.get(name).copied()
+    }
+}
+
+// the last binding of a name wins, as it would when collecting the pairs into a map
+impl<K: AsRef<str>> Resolver for [(K, f64)] {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self.iter()
+            .rev()
+            .find(|(key, _)| key.as_ref() == name)
+            .map(|(_, val)| *val)
+    }
+}
+
+impl<K: AsRef<str>, const N: usize> Resolver for [(K, f64); N] {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self[..].resolve(name)
+    }
+}
+
+impl<K: AsRef<str>> Resolver for Vec<(K, f64)> {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self[..].resolve(name)
+    }
+}
+
+impl<F: Fn(&str) -> Option<f64>> Resolver for F {
+    fn resolve(&self, name: &str) -> Option<f64> {
+        self(name)
+    }
+}
 
 /// Creates a [`Context`](crate::interpreter::Context) which can be applied to an expression using [`interpret`](crate::interpreter::interpret)/[`interpret_tree`](crate::interpreter::interpret_tree).
 ///
-/// Each `id` will bound to its corresponding `val`.
+/// Each `id` will bound to its corresponding `val`; an `id` can be a [`char`] or a string.
 ///
 /// ```
 /// use serious::{interpreter::Context, create_context};
@@ -15,116 +91,421 @@ pub type Context = std::collections::HashMap<char, f64>;
 ///
 /// assert_eq!(create_context!{}, test_context);
 ///
-/// test_context.insert('a', 4.);
+/// test_context.insert("a".to_string(), 4.);
 /// assert_eq!(create_context!{'a' => 4.}, test_context);
 ///
-/// test_context.insert('b', 5.);
-/// assert_eq!(create_context!{'a' => 4., 'b' => 5.}, test_context);
+/// test_context.insert("rate".to_string(), 5.);
+/// assert_eq!(create_context!{'a' => 4., "rate" => 5.}, test_context);
 /// ```
 #[macro_export]
 macro_rules! create_context {
     ($($id:expr => $val:expr),*$(,)?) => {{
-        use std::iter::{Iterator, IntoIterator};
-        use std::collections::HashMap;
-        let iter = IntoIterator::into_iter([$(($id, $val),)*]);
-        HashMap::<char, f64>::from(Iterator::collect(iter))
+        #[allow(unused_mut)]
+        let mut context = std::collections::HashMap::<String, f64>::new();
+        $(context.insert($id.to_string(), $val);)*
+        context
     }};
 }
 
-fn op_representation(op: Operation) -> char {
-    match op {
-        Operation::Exponentiate => '^',
-        Operation::Multiply => '*',
-        Operation::Divide => '/',
-        Operation::Add => '+',
-        Operation::Subtract => '-',
-    }
-}
-
-/// Evaluates a pre-parsed Serious expression.
-pub fn interpret_tree(tree: Expression, context: &Context) -> Result<f64, Error> {
-    match tree.data {
-        ExpressionData::Constant(val) => Ok(val),
-        ExpressionData::Op(lhs, op, rhs) => {
-            let (lhs, rhs) = (
-                interpret_tree(*lhs, context)?,
-                interpret_tree(*rhs, context)?,
-            );
-            let result = match op {
-                Operation::Add => lhs + rhs,
-                Operation::Subtract => lhs - rhs,
-                Operation::Multiply => lhs * rhs,
-                Operation::Divide => {
-                    if rhs == 0. {
-                        f64::NAN
-                    } else {
-                        lhs / rhs
-                    }
-                }
-                Operation::Exponentiate => lhs.powf(rhs),
-            };
+// the code is only built if the result is an error
+fn check_result<F: FnOnce(bool) -> ErrorCode>(
+    result: f64,
+    code: F,
+    start: usize,
+    end: usize,
+) -> Result<f64, Error> {
+    if result.is_infinite() {
+        Err(Error::from_code(code(true), start, end))
+    } else if result.is_nan() {
+        Err(Error::from_code(code(false), start, end))
+    } else {
+        Ok(result)
+    }
+}
 
-            let lhs_rep = if lhs < 0. {
-                format!("({})", lhs)
+pub(crate) fn apply_operation(
+    op: Operation,
+    lhs: f64,
+    rhs: f64,
+    start: usize,
+    end: usize,
+) -> Result<f64, Error> {
+    let result = match op {
+        Operation::Add => lhs + rhs,
+        Operation::Subtract => lhs - rhs,
+        Operation::Multiply => lhs * rhs,
+        Operation::Divide => {
+            if rhs == 0. {
+                f64::NAN
             } else {
-                format!("{}", lhs)
-            };
+                lhs / rhs
+            }
+        }
+        Operation::Exponentiate => lhs.powf(rhs),
+    };
 
-            let rhs_rep = if rhs < 0. {
-                format!("({})", rhs)
+    check_result(
+        result,
+        |overflowed| {
+            if overflowed {
+                ErrorCode::OperationOverflow { lhs, op, rhs }
             } else {
-                format!("{}", rhs)
-            };
+                ErrorCode::UndefinedOperation { lhs, op, rhs }
+            }
+        },
+        start,
+        end,
+    )
+}
 
-            if result.is_infinite() {
-                Err(Error::new(
-                    ErrorType::Overflow,
-                    format!(
-                        "{}{}{} overflowed f64",
-                        lhs_rep,
-                        op_representation(op),
-                        rhs_rep
-                    ),
-                    tree.start,
-                    tree.end,
-                ))
-            } else if result.is_nan() {
-                Err(Error::new(
-                    ErrorType::UndefinedOperation,
-                    format!(
-                        "{}{}{} is undefined",
-                        lhs_rep,
-                        op_representation(op),
-                        rhs_rep
-                    ),
-                    tree.start,
-                    tree.end,
-                ))
+pub(crate) fn apply_function(
+    function: &Builtin,
+    argument: f64,
+    start: usize,
+    end: usize,
+) -> Result<f64, Error> {
+    check_result(
+        (function.function)(argument),
+        |overflowed| {
+            let function = function.name.to_string();
+            if overflowed {
+                ErrorCode::CallOverflow {
+                    function,
+                    arguments: vec![argument],
+                }
+            } else {
+                ErrorCode::UndefinedCall {
+                    function,
+                    arguments: vec![argument],
+                }
+            }
+        },
+        start,
+        end,
+    )
+}
+
+// the closure is only called with a number of arguments its arity accepts
+pub(crate) fn apply_native(
+    name: &str,
+    (arity, function): (Arity, &NativeFunction),
+    arguments: &[f64],
+    start: usize,
+    end: usize,
+) -> Result<f64, Error> {
+    match arity {
+        Arity::Exactly(expected) if arguments.len() != expected => {
+            return Err(wrong_argument_count(
+                name,
+                expected,
+                arguments.len(),
+                start,
+                end,
+            ));
+        }
+        Arity::AtLeast(minimum) if arguments.len() < minimum => {
+            let code = ErrorCode::TooFewArguments {
+                function: name.to_string(),
+                minimum,
+                supplied: arguments.len(),
+            };
+            return Err(Error::from_code(code, start, end));
+        }
+        _ => (),
+    }
+    let result = function(arguments).map_err(|message| {
+        let code = ErrorCode::CallFailed {
+            function: name.to_string(),
+            message,
+        };
+        Error::from_code(code, start, end)
+    })?;
+    check_result(
+        result,
+        |overflowed| {
+            let (function, arguments) = (name.to_string(), arguments.to_vec());
+            if overflowed {
+                ErrorCode::CallOverflow {
+                    function,
+                    arguments,
+                }
             } else {
-                Ok(result)
+                ErrorCode::UndefinedCall {
+                    function,
+                    arguments,
+                }
             }
+        },
+        start,
+        end,
+    )
+}
+
+pub(crate) fn wrong_argument_count(
+    name: &str,
+    expected: usize,
+    supplied: usize,
+    start: usize,
+    end: usize,
+) -> Error {
+    let code = ErrorCode::WrongArgumentCount {
+        function: name.to_string(),
+        expected,
+        supplied,
+    };
+    Error::from_code(code, start, end)
+}
+
+pub(crate) fn unbound_identifier(name: &str, start: usize, end: usize) -> Error {
+    Error::from_code(ErrorCode::UnboundIdentifier(name.to_string()), start, end)
+}
+
+pub(crate) fn unparsed(start: usize, end: usize) -> Error {
+    Error::from_code(ErrorCode::Unparsed, start, end)
+}
+
+// evaluates a tree, counting each node against the budget of steps
+struct Evaluator<'t, R: Resolver + ?Sized> {
+    context: &'t R,
+    functions: &'t Registry,
+    // the values bound by the enclosing assignments and parameters, innermost last
+    scope: Vec<(&'t str, f64)>,
+    // the functions defined by the enclosing definitions, innermost last, each with the length
+    // of the scope where it was defined
+    definitions: Vec<(&'t Definition, usize)>,
+    steps: usize,
+    max_steps: usize,
+    calls: usize,
+    max_call_depth: usize,
+}
+
+impl<'t, R: Resolver + ?Sized> Evaluator<'t, R> {
+    fn lookup(&self, name: &str) -> Option<f64> {
+        match self.scope.iter().rev().find(|(bound, _)| *bound == name) {
+            Some((_, val)) => Some(*val),
+            None => self.context.resolve(name),
         }
+    }
 
-        ExpressionData::Identifier(name) => match context.get(&name) {
-            Some(val) => Ok(*val),
-            None => Err(Error::new(
-                ErrorType::UnboundIdentifier,
-                format!("identifier '{}' is not bound", name),
+    // the body of a function only sees what was in scope where it was defined, so it cannot call
+    // itself; a function from the registry sees none of the expression's scope
+    fn call(
+        &mut self,
+        definition: &'t Definition,
+        (visible, depth): (usize, usize),
+        args: &[f64],
+        tree: &Expression,
+    ) -> Result<f64, Error> {
+        let expected = definition.parameters.len();
+        if args.len() != expected {
+            let name = &definition.name;
+            return Err(wrong_argument_count(
+                name,
+                expected,
+                args.len(),
                 tree.start,
                 tree.end,
-            )),
-        },
+            ));
+        }
+        if self.calls == self.max_call_depth {
+            let code = ErrorCode::CallsTooDeep(self.max_call_depth);
+            return Err(Error::from_code(code, tree.start, tree.end));
+        }
+        let hidden_scope = self.scope.split_off(depth);
+        let hidden_definitions = self.definitions.split_off(visible);
+        let parameters = definition.parameters.iter().map(|name| name.as_str());
+        self.scope.extend(parameters.zip(args.iter().copied()));
+
+        self.calls += 1;
+        let result = self.eval(&definition.body);
+        self.calls -= 1;
+
+        self.scope.truncate(depth);
+        self.scope.extend(hidden_scope);
+        self.definitions.extend(hidden_definitions);
+        result
+    }
+
+    fn step(&mut self, tree: &Expression) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            let code = ErrorCode::TooManySteps(self.max_steps);
+            return Err(Error::from_code(code, tree.start, tree.end));
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, tree: &'t Expression) -> Result<f64, Error> {
+        self.step(tree)?;
+        match &tree.data {
+            ExpressionData::Constant(val) => Ok(*val),
+            // a chain of left-hand sides is evaluated from its leftmost operand outwards, counting
+            // the steps in the same order as recursion would
+            ExpressionData::Op(_, _, _) => {
+                let (leftmost, chain) = tree.left_chain();
+                for (node, _, _) in chain.iter().rev().skip(1) {
+                    self.step(node)?;
+                }
+                let mut val = self.eval(leftmost)?;
+                for (node, op, rhs) in chain {
+                    let rhs = self.eval(rhs)?;
+                    val = apply_operation(op, val, rhs, node.start, node.end)?;
+                }
+                Ok(val)
+            }
+
+            // a binding in the context shadows the built-in value
+            ExpressionData::NamedConstant(name) => match self.lookup(name) {
+                Some(val) => Ok(val),
+                None => match constant(name) {
+                    Some(constant) => Ok(constant.value),
+                    None => Err(unbound_identifier(name, tree.start, tree.end)),
+                },
+            },
+
+            ExpressionData::Negate(operand) => Ok(-self.eval(operand)?),
+
+            // a defined function shadows one from the registry
+            ExpressionData::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<f64>, Error>>()?;
+                let defined = self
+                    .definitions
+                    .iter()
+                    .rposition(|(definition, _)| definition.name == *name);
+                if let Some(i) = defined {
+                    let (definition, depth) = self.definitions[i];
+                    return self.call(definition, (i, depth), &args, tree);
+                }
+                match self.functions.entry(name) {
+                    Some(Entry::Defined(definition)) => self.call(definition, (0, 0), &args, tree),
+                    Some(Entry::Builtin(function)) => {
+                        if args.len() != 1 {
+                            let count = args.len();
+                            return Err(wrong_argument_count(name, 1, count, tree.start, tree.end));
+                        }
+                        apply_function(function, args[0], tree.start, tree.end)
+                    }
+                    Some(Entry::Native(_, arity, function)) => {
+                        let native = (*arity, function.as_ref());
+                        apply_native(name, native, &args, tree.start, tree.end)
+                    }
+                    None => {
+                        let code = ErrorCode::UndefinedFunction(name.to_string());
+                        Err(Error::from_code(code, tree.start, tree.end))
+                    }
+                }
+            }
+
+            ExpressionData::Error => Err(unparsed(tree.start, tree.end)),
+
+            // the binding is dropped afterwards, so the context is never changed
+            ExpressionData::Let(name, value, body) => {
+                let value = self.eval(value)?;
+                self.scope.push((name, value));
+                let result = self.eval(body);
+                self.scope.pop();
+                result
+            }
+
+            ExpressionData::Define(definition, rest) => {
+                self.definitions.push((definition, self.scope.len()));
+                let result = self.eval(rest);
+                self.definitions.pop();
+                result
+            }
+
+            ExpressionData::Identifier(name) => match self.lookup(name) {
+                Some(val) => Ok(val),
+                None => Err(unbound_identifier(name, tree.start, tree.end)),
+            },
+        }
     }
 }
 
+/// Evaluates a pre-parsed Serious expression within the default [`Limits`](crate::limits::Limits).
+pub fn interpret_tree<R: Resolver + ?Sized>(tree: Expression, context: &R) -> Result<f64, Error> {
+    interpret_tree_with(tree, context, &Limits::default())
+}
+
+/// Evaluates a pre-parsed Serious expression which may call the built-in functions, taking no more than [`Limits::max_steps`](crate::limits::Limits::max_steps).
+pub fn interpret_tree_with<R: Resolver + ?Sized>(
+    tree: Expression,
+    context: &R,
+    limits: &Limits,
+) -> Result<f64, Error> {
+    interpret_tree_with_functions(tree, context, builtins(), limits)
+}
+
+/// Evaluates a pre-parsed Serious expression which may call the functions in a [`Registry`](crate::functions::Registry),
+/// taking no more than [`Limits::max_steps`](crate::limits::Limits::max_steps) and nesting calls no deeper than
+/// [`Limits::max_call_depth`](crate::limits::Limits::max_call_depth).
+///
+/// Only the functions in the registry can be called, so a call to a built-in function which is not in it yields an
+/// [`UndefinedFunction`](crate::error::ErrorCode::UndefinedFunction) error. Errors from a call span the whole call.
+pub fn interpret_tree_with_functions<R: Resolver + ?Sized>(
+    tree: Expression,
+    context: &R,
+    functions: &Registry,
+    limits: &Limits,
+) -> Result<f64, Error> {
+    let mut evaluator = Evaluator {
+        context,
+        functions,
+        scope: vec![],
+        definitions: vec![],
+        steps: 0,
+        max_steps: limits.max_steps,
+        calls: 0,
+        max_call_depth: limits.max_call_depth,
+    };
+    evaluator.eval(&tree)
+}
+
 /// Evaluates a Serious expression.
-pub fn interpret(text: &str, bound_vars: &Context) -> Result<f64, Error> {
-    interpret_tree(parse(text)?, bound_vars)
+pub fn interpret<R: Resolver + ?Sized>(text: &str, bound_vars: &R) -> Result<f64, Error> {
+    interpret_with(text, bound_vars, &ParseOptions::default())
+}
+
+/// Evaluates a Serious expression, given [`ParseOptions`](crate::parser::ParseOptions), whose [`limits`](crate::parser::ParseOptions::limits) apply to both the parse and the evaluation.
+pub fn interpret_with<R: Resolver + ?Sized>(
+    text: &str,
+    bound_vars: &R,
+    options: &ParseOptions,
+) -> Result<f64, Error> {
+    interpret_tree_with(parse_with(text, options)?, bound_vars, &options.limits)
+}
+
+/// Evaluates a Serious expression which may call the functions in a [`Registry`](crate::functions::Registry), given [`ParseOptions`](crate::parser::ParseOptions).
+///
+/// ```
+/// use serious::{create_context, interpreter::interpret_with_functions};
+/// use serious::functions::Registry;
+/// use serious::parser::ParseOptions;
+///
+/// let (options, functions) = (ParseOptions::default(), Registry::new());
+/// let context = create_context!{'x' => 1., 'y' => 2.};
+/// let result = interpret_with_functions("f(t) = 3t^2 + 1; f(x) + f(y)", &context, &functions, &options);
+/// assert_eq!(result.unwrap(), 17.);
+/// ```
+pub fn interpret_with_functions<R: Resolver + ?Sized>(
+    text: &str,
+    bound_vars: &R,
+    functions: &Registry,
+    options: &ParseOptions,
+) -> Result<f64, Error> {
+    let options = functions.parse_options(options);
+    let tree = parse_with(text, &options)?;
+    interpret_tree_with_functions(tree, bound_vars, functions, &options.limits)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorType;
+    use crate::parser::{parse, parse_recovering, IdentifierMode};
 
     #[test]
     fn literal() {
@@ -135,15 +516,7 @@ mod tests {
     #[test]
     fn err_from_parse() {
         let err = interpret("(1*(2+3)", &create_context! {}).unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(
-                ErrorType::BadParse,
-                "failed to match paren".to_string(),
-                0,
-                1
-            )
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 0, 1));
     }
 
     #[test]
@@ -153,12 +526,7 @@ mod tests {
         let err = interpret("3 + xy", &context).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::UnboundIdentifier,
-                "identifier 'y' is not bound".to_string(),
-                5,
-                6
-            )
+            Error::from_code(ErrorCode::UnboundIdentifier("y".to_string()), 5, 6)
         );
     }
 
@@ -167,9 +535,12 @@ mod tests {
         let err = interpret("10/0", &create_context! {}).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::UndefinedOperation,
-                "10/0 is undefined".to_string(),
+            Error::from_code(
+                ErrorCode::UndefinedOperation {
+                    lhs: 10.,
+                    op: Operation::Divide,
+                    rhs: 0.,
+                },
                 0,
                 4
             )
@@ -181,9 +552,12 @@ mod tests {
         let err = interpret("2^(56 / (2 - 2)) * 3", &create_context! {}).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::UndefinedOperation,
-                "56/0 is undefined".to_string(),
+            Error::from_code(
+                ErrorCode::UndefinedOperation {
+                    lhs: 56.,
+                    op: Operation::Divide,
+                    rhs: 0.,
+                },
                 2,
                 16
             )
@@ -212,14 +586,31 @@ mod tests {
         assert_eq!(val, -25.);
     }
 
+    #[test]
+    fn exponent_tower() {
+        let val = interpret("2^3^2 - -2^2", &create_context! {}).unwrap();
+        assert_eq!(val, 516.);
+    }
+
+    #[test]
+    fn unary_minus_operands() {
+        let context = create_context! {'a' => 2., 'b' => 5.};
+
+        let val = interpret("a*-b + a^-1 - +a", &context).unwrap();
+        assert_eq!(val, -11.5);
+    }
+
     #[test]
     fn bad_pow() {
         let err = interpret("4 + (1 - 2)^0.5", &create_context! {}).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::UndefinedOperation,
-                "(-1)^0.5 is undefined".to_string(),
+            Error::from_code(
+                ErrorCode::UndefinedOperation {
+                    lhs: -1.,
+                    op: Operation::Exponentiate,
+                    rhs: 0.5,
+                },
                 4,
                 15
             )
@@ -231,12 +622,467 @@ mod tests {
         let err = interpret("3 + (9 + 1)^999", &create_context! {}).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::Overflow,
-                "10^999 overflowed f64".to_string(),
+            Error::from_code(
+                ErrorCode::OperationOverflow {
+                    lhs: 10.,
+                    op: Operation::Exponentiate,
+                    rhs: 999.,
+                },
                 4,
                 15
             )
         );
     }
+
+    #[test]
+    fn builtin_functions() {
+        let context = create_context! {'x' => 3., 'y' => 4.};
+
+        let val = interpret("sqrt(x^2 + y^2) + abs(x - y)ln(1)", &context).unwrap();
+        assert_eq!(val, 5.);
+    }
+
+    #[test]
+    fn bad_function_argument() {
+        let err = interpret("2 + 3ln(1 - 2)", &create_context! {}).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(
+                ErrorCode::UndefinedCall {
+                    function: "ln".to_string(),
+                    arguments: vec![-1.]
+                },
+                5,
+                14
+            )
+        );
+    }
+
+    #[test]
+    fn function_to_infinity() {
+        let err = interpret("exp(1000)", &create_context! {}).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(
+                ErrorCode::CallOverflow {
+                    function: "exp".to_string(),
+                    arguments: vec![1000.]
+                },
+                0,
+                9
+            )
+        );
+    }
+
+    #[test]
+    fn multi_char_identifiers() {
+        let context = create_context! {"theta_max" => 2., "x_1" => 3.};
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+
+        let val = interpret_with("theta_max^x_1 - x_1", &context, &options).unwrap();
+        assert_eq!(val, 5.);
+    }
+
+    #[test]
+    fn unbound_multi_char_identifier() {
+        let options = ParseOptions {
+            names: vec!["rate".to_string()],
+            ..ParseOptions::default()
+        };
+
+        let err = interpret_with("2rate", &create_context! {'r' => 1.}, &options).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::UnboundIdentifier("rate".to_string()), 1, 5)
+        );
+    }
+
+    #[test]
+    fn named_constants() {
+        let context = create_context! {'r' => 2.};
+
+        let val = interpret("pi r^2 - tau r + ln(e)", &context).unwrap();
+        assert_eq!(
+            val,
+            std::f64::consts::PI * 4. - std::f64::consts::TAU * 2. + 1.
+        );
+    }
+
+    #[test]
+    fn shadowed_constant() {
+        let context = create_context! {'e' => 0.5, "pi" => 3.};
+
+        let val = interpret("2e + pi", &context).unwrap();
+        assert_eq!(val, 4.);
+    }
+
+    #[test]
+    fn constants_disabled() {
+        let options = ParseOptions {
+            builtin_constants: false,
+            ..ParseOptions::default()
+        };
+
+        let err = interpret_with("2e", &create_context! {}, &options).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::UnboundIdentifier("e".to_string()), 1, 2)
+        );
+        let val = interpret_with("2pi", &create_context! {'p' => 3., 'i' => 5.}, &options).unwrap();
+        assert_eq!(val, 30.);
+    }
+
+    #[test]
+    fn partial_tree() {
+        let (tree, _) = parse_recovering("x + (2 * )", &ParseOptions::default());
+        let err = interpret_tree(tree, &create_context! {'x' => 1.}).unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::Unparsed, 9, 10));
+    }
+
+    #[test]
+    fn step_limit() {
+        let limits = Limits {
+            max_steps: 5,
+            ..Limits::default()
+        };
+        let context = create_context! {'x' => 2.};
+        let tree = parse("2x + 1").unwrap();
+        assert_eq!(interpret_tree_with(tree, &context, &limits).unwrap(), 5.);
+
+        let tree = parse("2x + 1 + x").unwrap();
+        let err = interpret_tree_with(tree, &context, &limits).unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::TooManySteps(5), 5, 6));
+        assert_eq!(err.error_type(), ErrorType::LimitExceeded);
+
+        let options = ParseOptions {
+            limits,
+            ..ParseOptions::default()
+        };
+        let err = interpret_with("sin(x)^2 + 1", &context, &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManySteps(5));
+    }
+
+    #[test]
+    fn long_chains() {
+        let context = create_context! {'x' => 1., 'y' => 2.};
+        let text = format!("x{}", "+x".repeat(199));
+        assert_eq!(interpret(&text, &context).unwrap(), 200.);
+        let text = format!("y{}", "*y/y".repeat(4000));
+        assert_eq!(interpret(&text, &context).unwrap(), 2.);
+
+        // steps are counted in the order they would be with recursion, from the outermost operation in
+        let limits = Limits {
+            max_steps: 6,
+            ..Limits::default()
+        };
+        let tree = parse("x + 2 - x + y").unwrap();
+        let err = interpret_tree_with(tree, &context, &limits).unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::TooManySteps(6), 12, 13));
+    }
+
+    #[test]
+    fn resolvers() {
+        let text = "2x + y";
+        let map = create_context! {'x' => 1., 'y' => 3.};
+        assert_eq!(interpret(text, &map).unwrap(), 5.);
+        let map: BTreeMap<String, f64> = map.into_iter().collect();
+        assert_eq!(interpret(text, &map).unwrap(), 5.);
+        let pairs = [("x", 1.), ("y", 3.)];
+        assert_eq!(interpret(text, &pairs).unwrap(), 5.);
+        assert_eq!(interpret(text, &pairs[..]).unwrap(), 5.);
+        let pairs = vec![("x".to_string(), 1.), ("y".to_string(), 3.)];
+        assert_eq!(interpret(text, &pairs).unwrap(), 5.);
+        let closure = |name: &str| if name == "x" { Some(1.) } else { Some(3.) };
+        assert_eq!(interpret(text, &closure).unwrap(), 5.);
+    }
+
+    #[test]
+    fn resolver_last_pair_wins() {
+        let pairs = [("x", 1.), ("x", 2.)];
+        assert_eq!(interpret("x", &pairs).unwrap(), 2.);
+    }
+
+    #[test]
+    fn resolver_unbound() {
+        let closure = |_: &str| None;
+        let err = interpret("2x", &closure).unwrap_err();
+        assert_eq!(err, unbound_identifier("x", 1, 2));
+        assert_eq!(
+            interpret("2pi", &closure).unwrap(),
+            2. * std::f64::consts::PI
+        );
+    }
+
+    #[test]
+    fn resolver_shadows_constant() {
+        let pairs = [("e", 2.)];
+        assert_eq!(interpret("e^2", &pairs).unwrap(), 4.);
+    }
+
+    #[test]
+    fn resolved_lazily() {
+        let calls = std::cell::Cell::new(0);
+        let closure = |_: &str| {
+            calls.set(calls.get() + 1);
+            Some(1.)
+        };
+        assert_eq!(interpret("x + x + y", &closure).unwrap(), 3.);
+        assert_eq!(calls.get(), 3);
+        assert!(interpret("1/0 + x", &closure).is_err());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn assignments() {
+        let context = create_context! {'a' => 1., 'b' => 5., 'c' => 4.};
+        let val = interpret("d = b^2 - 4a c; (-b + d^0.5)/(2a)", &context).unwrap();
+        assert_eq!(val, -1.);
+        assert_eq!(
+            interpret("a = 1; a = a + 1; a = 3a; a", &context).unwrap(),
+            6.
+        );
+    }
+
+    #[test]
+    fn assignments_shadow_context() {
+        let context = create_context! {'x' => 1.};
+        assert_eq!(interpret("x = 2; x + 1", &context).unwrap(), 3.);
+        assert_eq!(context, create_context! {'x' => 1.});
+        assert_eq!(interpret("e = 2; e^2", &context).unwrap(), 4.);
+        assert_eq!(interpret("y = x; x = 5; x y", &context).unwrap(), 5.);
+    }
+
+    #[test]
+    fn assignment_errors() {
+        let context = create_context! {'x' => 0.};
+        let err = interpret("d = 1/x; 2", &context).unwrap_err();
+        assert_eq!(err.code.id(), "undefined_operation");
+        assert_eq!((err.start, err.end), (4, 7));
+        let err = interpret("d = 1; y", &context).unwrap_err();
+        assert_eq!(err, unbound_identifier("y", 7, 8));
+    }
+
+    #[test]
+    fn definitions() {
+        let context = create_context! {'x' => 1., 'y' => 2.};
+        let val = interpret("f(t) = 3t^2 + 1; f(x) + f(y)", &context).unwrap();
+        assert_eq!(val, 17.);
+        let val = interpret("f(t) = t + 1; g(a, b) = f(a)/b; g(3, y)", &context).unwrap();
+        assert_eq!(val, 2.);
+    }
+
+    #[test]
+    fn definitions_are_lexically_scoped() {
+        let context = create_context! {'t' => 5.};
+        assert_eq!(
+            interpret("a = 2; f(t) = a t; a = 3; f(1) + a", &context).unwrap(),
+            5.
+        );
+        assert_eq!(interpret("f(t) = 2t; f(1) + t", &context).unwrap(), 7.);
+        assert_eq!(interpret("f(x) = t x; t = 1; f(2)", &context).unwrap(), 10.);
+    }
+
+    #[test]
+    fn definition_errors() {
+        let context = create_context! {'x' => 0.};
+        let err = interpret("y = f(1); f(t) = t; y", &context).unwrap_err();
+        assert_eq!(err.code, ErrorCode::UndefinedFunction("f".to_string()));
+        assert_eq!((err.start, err.end), (4, 8));
+        let err = interpret("f(t) = 1/t; 2 + f(x)", &context).unwrap_err();
+        assert_eq!(err.code.id(), "undefined_operation");
+        assert_eq!((err.start, err.end), (7, 10));
+    }
+
+    fn registry(definitions: &[&str]) -> Registry {
+        let mut functions = Registry::new();
+        for text in definitions {
+            functions.define(text, &ParseOptions::default()).unwrap();
+        }
+        functions
+    }
+
+    #[test]
+    fn registry_functions() {
+        let functions = registry(&["f(t) = 3t^2 + 1", "g(t) = k f(t)"]);
+        let options = ParseOptions::default();
+        let context = create_context! {'k' => 2., 'x' => 1.};
+        let eval = |text| interpret_with_functions(text, &context, &functions, &options);
+        assert_eq!(eval("f(x) + g(x)"), Ok(12.));
+        assert_eq!(eval("k = 3; g(x)"), Ok(8.));
+        assert_eq!(eval("f(t) = t; f(2) + g(1)"), Ok(10.));
+        assert_eq!(eval("f(x, 2)"), Err(wrong_argument_count("f", 1, 2, 0, 7)));
+        assert_eq!(
+            interpret("f(x)", &context).unwrap_err().code,
+            ErrorCode::UnboundIdentifier("f".to_string())
+        );
+    }
+
+    #[test]
+    fn registry_cycles_terminate() {
+        let functions = registry(&["f(t) = t", "g(t) = f(t) + 1", "f(t) = g(t) + 1"]);
+        let options = ParseOptions::default();
+        let err = interpret_with_functions("2f(1)", &create_context! {}, &functions, &options)
+            .unwrap_err();
+        assert_eq!(err.error_type(), ErrorType::LimitExceeded);
+        assert_eq!(err.code, ErrorCode::CallsTooDeep(32));
+
+        // the calls in a body are looked up when they are made, so redefining `f` changes `g`
+        let mut functions = registry(&["f(t) = t + 1", "g(t) = f(t)*2"]);
+        let eval = |functions: &Registry| {
+            interpret_with_functions("g(1)", &create_context! {}, functions, &options)
+        };
+        assert_eq!(eval(&functions), Ok(4.));
+        functions.define("f(t) = g(t)", &options).unwrap();
+        assert_eq!(
+            eval(&functions).unwrap_err().code,
+            ErrorCode::CallsTooDeep(32)
+        );
+    }
+
+    #[test]
+    fn steps_are_counted_in_calls() {
+        let options = ParseOptions {
+            limits: Limits {
+                max_steps: 50,
+                ..Limits::default()
+            },
+            ..ParseOptions::default()
+        };
+        let text = "a(t) = t + t; b(t) = a(t) + a(t); c(t) = b(t) + b(t); c(1) + c(1)";
+        assert_eq!(interpret(text, &create_context! {}), Ok(16.));
+        let err = interpret_with(text, &create_context! {}, &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManySteps(50));
+    }
+
+    fn natives() -> Registry {
+        let mut functions = Registry::new();
+        functions.register("lerp", Arity::Exactly(3), |args| {
+            Ok(args[0] + (args[1] - args[0]) * args[2])
+        });
+        functions.register("max", Arity::AtLeast(2), |args| {
+            Ok(args.iter().cloned().fold(f64::MIN, f64::max))
+        });
+        functions.register("rate", Arity::Exactly(1), |args| match args[0] {
+            income if income < 0. => Err("income is negative".to_string()),
+            income if income <= 100. => Ok(0.),
+            _ => Ok(0.25),
+        });
+        functions.register("inv", Arity::Exactly(1), |args| Ok(1. / args[0]));
+        functions.register("root", Arity::Exactly(2), |args| {
+            Ok(args[0].powf(1. / args[1]))
+        });
+        functions
+    }
+
+    #[test]
+    fn native_functions() {
+        let functions = natives();
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let context = create_context! {"x" => 200., "y" => 50.};
+        let eval = |text| interpret_with_functions(text, &context, &functions, &options);
+        assert_eq!(eval("lerp(y, x, 0.5)"), Ok(125.));
+        assert_eq!(eval("max(y, x)"), Ok(200.));
+        assert_eq!(eval("max(1, y, 3, 4)"), Ok(50.));
+        assert_eq!(eval("x rate(x) - y rate(y)"), Ok(50.));
+        assert_eq!(eval("f(t) = rate(t) t; f(x) + sqrt(4)"), Ok(52.));
+    }
+
+    #[test]
+    fn native_function_errors() {
+        let functions = natives();
+        let options = ParseOptions::default();
+        let context = create_context! {'x' => 0., 'y' => -1.};
+        let err =
+            |text| interpret_with_functions(text, &context, &functions, &options).unwrap_err();
+        assert_eq!(
+            err("2 + lerp(x, y)"),
+            wrong_argument_count("lerp", 3, 2, 4, 14)
+        );
+        let code = ErrorCode::TooFewArguments {
+            function: "max".to_string(),
+            minimum: 2,
+            supplied: 1,
+        };
+        assert_eq!(err("max(y) + 1"), Error::from_code(code, 0, 6));
+        let code = ErrorCode::CallFailed {
+            function: "rate".to_string(),
+            message: "income is negative".to_string(),
+        };
+        assert_eq!(err("x - rate(y)"), Error::from_code(code, 4, 11));
+        let code = ErrorCode::CallOverflow {
+            function: "inv".to_string(),
+            arguments: vec![0.],
+        };
+        assert_eq!(err("1 + inv(x)"), Error::from_code(code, 4, 10));
+        let code = ErrorCode::UndefinedCall {
+            function: "root".to_string(),
+            arguments: vec![-1., 2.],
+        };
+        assert_eq!(err("root(y, 2)").code, code);
+    }
+
+    #[test]
+    fn no_arguments() {
+        let mut functions = natives();
+        functions.register("answer", Arity::Exactly(0), |_| Ok(42.));
+        functions.register("count", Arity::AtLeast(0), |args| Ok(args.len() as f64));
+        let context = create_context! {'x' => 2.};
+        let eval =
+            |text| interpret_with_functions(text, &context, &functions, &ParseOptions::default());
+        assert_eq!(eval("answer() + count()x + count(x, 1)"), Ok(44.));
+        assert_eq!(
+            eval("answer(x)").unwrap_err(),
+            wrong_argument_count("answer", 0, 1, 0, 9)
+        );
+        assert_eq!(
+            eval("2lerp()").unwrap_err(),
+            wrong_argument_count("lerp", 3, 0, 1, 7)
+        );
+    }
+
+    #[test]
+    fn replacing_builtins() {
+        let mut functions = Registry::new();
+        functions.register("sqrt", Arity::AtLeast(1), |args| {
+            Ok(args.iter().map(|arg| arg * arg).sum::<f64>().sqrt())
+        });
+        let options = ParseOptions::default();
+        let context = create_context! {'x' => 3., 'y' => -4.};
+        let eval = |text, functions: &Registry| {
+            interpret_with_functions(text, &context, functions, &options)
+        };
+        assert_eq!(eval("sqrt(x, y)", &functions), Ok(5.));
+        assert_eq!(eval("sqrt(y)", &functions), Ok(4.));
+
+        assert!(functions.remove("sqrt"));
+        assert_eq!(
+            eval("cos(0) + sqrt(x)", &functions),
+            Err(Error::from_code(
+                ErrorCode::UndefinedFunction("sqrt".to_string()),
+                9,
+                16
+            ))
+        );
+        assert_eq!(
+            eval("cos(0)", &Registry::empty()),
+            Err(Error::from_code(
+                ErrorCode::UndefinedFunction("cos".to_string()),
+                0,
+                6
+            ))
+        );
+        let tree = parse("cos(0)").unwrap();
+        let limits = Limits::default();
+        assert_eq!(
+            interpret_tree_with_functions(tree.clone(), &context, &Registry::new(), &limits),
+            Ok(1.)
+        );
+        assert_eq!(interpret_tree(tree, &context), Ok(1.));
+    }
 }
@@ -1,4 +1,7 @@
-use super::error::{Error, ErrorType};
+use super::constants::{constant, CONSTANTS};
+use super::error::{Error, ErrorCode};
+use super::functions::{builtin, BUILTINS};
+use super::parser::{IdentifierMode, ParseOptions};
 use std::iter::FromIterator;
 
 /// The operations in the Serious language.
@@ -22,9 +25,18 @@ pub enum Operation {
 pub enum TokenType {
     OpenParen,
     CloseParen,
+    Comma,
+    Equals,
+    Semicolon,
     Op(Operation),
     Constant(f64),
-    Identifier(char),
+    Identifier(String),
+    NamedConstant(String),
+    Function(String),
+    /// Text which failed to lex, only emitted by [`lex_recovering`].
+    Invalid,
+    /// A radical sign for the named root function, e.g. `√` for `sqrt`.
+    Root(&'static str),
 }
 
 #[derive(Debug, PartialEq)]
@@ -45,129 +57,321 @@ impl Token {
 }
 
 #[derive(Debug)]
-struct LexerState {
+struct LexerState<'a> {
+    chars: Vec<char>,
+    // the byte offset of each char, and of the end of the text
+    offsets: Vec<usize>,
     index: usize,
-    curr_number: Vec<char>,
     tokens: Vec<Token>,
+    options: &'a ParseOptions,
 }
 
-type IntermediateLexerState = Result<LexerState, Error>;
-
-impl LexerState {
-    fn new() -> LexerState {
+impl<'a> LexerState<'a> {
+    fn new(text: &str, options: &'a ParseOptions) -> LexerState<'a> {
+        let offsets = text
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(text.len()))
+            .collect();
         LexerState {
+            chars: text.chars().collect(),
+            offsets,
             index: 0,
-            curr_number: vec![],
             tokens: Vec::new(),
+            options,
+        }
+    }
+
+    // spans are byte offsets into the text, although the lexer steps through chars
+    fn span(&self, len: usize) -> (usize, usize) {
+        (self.offsets[self.index], self.offsets[self.index + len])
+    }
+
+    fn push(&mut self, token_type: TokenType, len: usize) {
+        let (start, end) = self.span(len);
+        self.tokens.push(Token::new(token_type, start, end));
+        self.index += len;
+    }
+
+    fn count_digits(&self, from: usize) -> usize {
+        self.chars[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit() || **c == '.')
+            .count()
+    }
+
+    // an `e` or `E` after a number only starts an exponent if it is directly
+    // followed by a digit or a signed digit; otherwise it is an identifier,
+    // so `2e` means `2*e` and `2e+x` means `2*e + x`, but `2e+1` means `20`
+    fn exponent_len(&self, mantissa_end: usize) -> usize {
+        match self.chars[mantissa_end..] {
+            ['e' | 'E', '0'..='9', ..] => 1 + self.count_digits(mantissa_end + 1),
+            ['e' | 'E', '+' | '-', '0'..='9', ..] => 2 + self.count_digits(mantissa_end + 2),
+            _ => 0,
         }
     }
 
-    fn parse_current_number(mut self) -> IntermediateLexerState {
-        if !self.curr_number.is_empty() {
-            let n_len = self.curr_number.len();
-            let parsed_number = String::from_iter(self.curr_number).parse::<f64>();
-            match parsed_number {
-                Ok(n) => {
-                    if n.is_infinite() {
-                        return Err(Error::new(
-                            ErrorType::Overflow,
-                            "number overflowed f64".to_string(),
-                            self.index - n_len,
-                            self.index,
-                        ));
-                    }
-                    self.tokens.push(Token::new(
-                        TokenType::Constant(n),
-                        self.index - n_len,
-                        self.index,
+    fn parse_number(&mut self) -> Result<(), Error> {
+        let mantissa_len = self.count_digits(self.index);
+        let n_len = mantissa_len + self.exponent_len(self.index + mantissa_len);
+        let number = String::from_iter(&self.chars[self.index..self.index + n_len]);
+        let (start, end) = self.span(n_len);
+        match number.parse::<f64>() {
+            Ok(n) => {
+                if n.is_infinite() {
+                    return Err(Error::from_code(
+                        ErrorCode::NumberOverflow(number),
+                        start,
+                        end,
                     ));
                 }
-                Err(msg) => {
-                    return Err(Error::new(
-                        ErrorType::BadParse,
-                        msg.to_string(),
-                        self.index - n_len,
-                        self.index,
-                    ))
-                }
+                self.push(TokenType::Constant(n), n_len);
+                Ok(())
             }
-            self.curr_number = vec![];
+            Err(_) => Err(Error::from_code(
+                ErrorCode::InvalidNumber(number),
+                start,
+                end,
+            )),
         }
+    }
 
-        Ok(self)
+    fn starts_with(&self, name: &str) -> bool {
+        let mut rest = self.chars[self.index..].iter();
+        name.chars().all(|c| rest.next() == Some(&c))
     }
 
-    fn finalize(state: IntermediateLexerState) -> Result<Vec<Token>, Error> {
-        let state = state?.parse_current_number()?;
-        Ok(state.tokens)
+    // a function name is only recognized if it is followed by an open paren,
+    // so that e.g. `ln` without an argument still means `l*n`
+    fn followed_by_paren(&self, len: usize) -> bool {
+        self.chars[self.index + len..]
+            .iter()
+            .find(|c| !c.is_whitespace())
+            == Some(&'(')
     }
-}
 
-fn consume_char(state: IntermediateLexerState, (i, next): (usize, char)) -> IntermediateLexerState {
-    let mut state = state?;
-    match next {
-        '0'..='9' | '.' => {
-            state.curr_number.push(next);
+    fn push_name(&mut self, name: String, len: usize) {
+        if self.options.builtin_constants && constant(&name).is_some() {
+            self.push(TokenType::NamedConstant(name), len)
+        } else {
+            self.push(TokenType::Identifier(name), len)
         }
-        _ => {
-            state = state.parse_current_number()?;
-            match next {
-                ' ' => (),
-                '(' => state
-                    .tokens
-                    .push(Token::new(TokenType::OpenParen, i, i + 1)),
-                ')' => state
-                    .tokens
-                    .push(Token::new(TokenType::CloseParen, i, i + 1)),
-                '+' => state
-                    .tokens
-                    .push(Token::new(TokenType::Op(Operation::Add), i, i + 1)),
-                '-' => state
-                    .tokens
-                    .push(Token::new(TokenType::Op(Operation::Subtract), i, i + 1)),
-                '*' => state
-                    .tokens
-                    .push(Token::new(TokenType::Op(Operation::Multiply), i, i + 1)),
-                '/' => state
-                    .tokens
-                    .push(Token::new(TokenType::Op(Operation::Divide), i, i + 1)),
-                '^' => {
-                    state
-                        .tokens
-                        .push(Token::new(TokenType::Op(Operation::Exponentiate), i, i + 1))
-                }
-                'A'..='Z' | 'a'..='z' => {
-                    state
-                        .tokens
-                        .push(Token::new(TokenType::Identifier(next), i, i + 1))
-                }
-                _ => {
-                    return Err(Error::new(
-                        ErrorType::BadParse,
-                        format!("invalid character '{}'", next),
-                        i,
-                        i + 1,
-                    ))
-                }
+    }
+
+    fn is_function(&self, name: &str) -> bool {
+        builtin(name).is_some()
+            || self
+                .options
+                .functions
+                .iter()
+                .any(|function| function == name)
+    }
+
+    // the longest function, constant or declared name at the current position wins;
+    // otherwise each letter is its own identifier
+    fn lex_letters(&mut self) {
+        let function = BUILTINS
+            .iter()
+            .map(|builtin| builtin.name)
+            .chain(self.options.functions.iter().map(|name| name.as_str()))
+            .filter(|name| {
+                !name.is_empty()
+                    && self.starts_with(name)
+                    && self.followed_by_paren(name.chars().count())
+            })
+            .max_by_key(|name| name.chars().count())
+            .map(|name| name.to_string());
+        let constants = CONSTANTS
+            .iter()
+            .filter(|_| self.options.builtin_constants)
+            .map(|constant| constant.name);
+        let declared = self
+            .options
+            .names
+            .iter()
+            .map(|name| name.as_str())
+            .chain(constants)
+            .filter(|name| !name.is_empty() && self.starts_with(name))
+            .max_by_key(|name| name.chars().count())
+            .map(|name| name.to_string());
+
+        match (function, declared) {
+            (Some(function), Some(declared))
+                if declared.chars().count() > function.chars().count() =>
+            {
+                let len = declared.chars().count();
+                self.push_name(declared, len)
+            }
+            (Some(function), _) => {
+                let len = function.chars().count();
+                self.push(TokenType::Function(function), len)
+            }
+            (None, Some(declared)) => {
+                let len = declared.chars().count();
+                self.push_name(declared, len)
+            }
+            (None, None) => {
+                let letter = self.chars[self.index].to_string();
+                self.push_name(letter, 1)
+            }
+        }
+    }
+
+    fn lex_word(&mut self) {
+        let len = self.chars[self.index..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+            .count();
+        let word = String::from_iter(&self.chars[self.index..self.index + len]);
+        if self.is_function(&word) && self.followed_by_paren(len) {
+            self.push(TokenType::Function(word), len)
+        } else {
+            self.push_name(word, len)
+        }
+    }
+
+    // a run of superscript digits is an exponent, pushed as tokens which all point into the run
+    fn lex_superscript(&mut self) -> Result<(), Error> {
+        let negative = self.chars[self.index] == '⁻';
+        let sign_len = if negative { 1 } else { 0 };
+        let digits: String = self.chars[self.index + sign_len..]
+            .iter()
+            .map_while(|c| superscript_digit(*c))
+            .collect();
+        if digits.is_empty() {
+            let (start, end) = self.span(1);
+            return Err(Error::from_code(
+                ErrorCode::InvalidCharacter('⁻'),
+                start,
+                end,
+            ));
+        }
+
+        let (start, end) = self.span(sign_len + digits.len());
+        let exponent = match digits.parse::<f64>() {
+            Ok(n) if n.is_finite() => n,
+            _ => {
+                return Err(Error::from_code(
+                    ErrorCode::NumberOverflow(digits),
+                    start,
+                    end,
+                ))
+            }
+        };
+        self.tokens.push(Token::new(
+            TokenType::Op(Operation::Exponentiate),
+            start,
+            end,
+        ));
+        if negative {
+            self.push(TokenType::Op(Operation::Subtract), 1);
+        }
+        self.push(TokenType::Constant(exponent), digits.len());
+        Ok(())
+    }
+
+    fn lex_unicode(&mut self, next: char) -> Result<(), Error> {
+        match next {
+            '×' | '·' | '⋅' => self.push(TokenType::Op(Operation::Multiply), 1),
+            '÷' => self.push(TokenType::Op(Operation::Divide), 1),
+            '−' => self.push(TokenType::Op(Operation::Subtract), 1),
+            '√' => self.push(TokenType::Root("sqrt"), 1),
+            '∛' => self.push(TokenType::Root("cbrt"), 1),
+            'π' => self.push_name("pi".to_string(), 1),
+            'τ' => self.push_name("tau".to_string(), 1),
+            'φ' => self.push_name("phi".to_string(), 1),
+            c if c == '⁻' || superscript_digit(c).is_some() => return self.lex_superscript(),
+            _ => {
+                let (start, end) = self.span(1);
+                return Err(Error::from_code(
+                    ErrorCode::InvalidCharacter(next),
+                    start,
+                    end,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn consume_char(&mut self) -> Result<(), Error> {
+        let next = self.chars[self.index];
+        match next {
+            '0'..='9' | '.' => return self.parse_number(),
+            c if c.is_whitespace() => self.index += 1,
+            '(' => self.push(TokenType::OpenParen, 1),
+            ')' => self.push(TokenType::CloseParen, 1),
+            ',' => self.push(TokenType::Comma, 1),
+            '=' => self.push(TokenType::Equals, 1),
+            ';' => self.push(TokenType::Semicolon, 1),
+            '+' => self.push(TokenType::Op(Operation::Add), 1),
+            '-' => self.push(TokenType::Op(Operation::Subtract), 1),
+            '*' => self.push(TokenType::Op(Operation::Multiply), 1),
+            '/' => self.push(TokenType::Op(Operation::Divide), 1),
+            '^' => self.push(TokenType::Op(Operation::Exponentiate), 1),
+            'A'..='Z' | 'a'..='z' => match self.options.identifier_mode {
+                IdentifierMode::Letter => self.lex_letters(),
+                IdentifierMode::Word => self.lex_word(),
+            },
+            _ if self.options.unicode => return self.lex_unicode(next),
+            _ => {
+                let (start, end) = self.span(1);
+                return Err(Error::from_code(
+                    ErrorCode::InvalidCharacter(next),
+                    start,
+                    end,
+                ));
             }
         }
+        Ok(())
+    }
+}
+
+fn superscript_digit(c: char) -> Option<char> {
+    match c {
+        '⁰' => Some('0'),
+        '¹' => Some('1'),
+        '²' => Some('2'),
+        '³' => Some('3'),
+        '⁴' => Some('4'),
+        '⁵' => Some('5'),
+        '⁶' => Some('6'),
+        '⁷' => Some('7'),
+        '⁸' => Some('8'),
+        '⁹' => Some('9'),
+        _ => None,
     }
-    state.index += 1;
-    Ok(state)
 }
 
-pub fn lex(text: &str) -> Result<Vec<Token>, Error> {
+pub fn lex(text: &str, options: &ParseOptions) -> Result<Vec<Token>, Error> {
     if text.is_empty() {
-        return Err(Error::new(
-            ErrorType::BadParse,
-            "expected token".to_string(),
-            0,
-            1,
-        ));
+        return Err(Error::from_code(ErrorCode::EmptyInput, 0, 1));
+    }
+    let mut state = LexerState::new(text, options);
+    while state.index < state.chars.len() {
+        state.consume_char()?;
     }
-    let chars = text.chars().enumerate();
-    let state = chars.fold(Ok(LexerState::new()), consume_char);
-    LexerState::finalize(state)
+    Ok(state.tokens)
+}
+
+// every error is collected, and the text it covers becomes an invalid token
+pub fn lex_recovering(text: &str, options: &ParseOptions) -> (Vec<Token>, Vec<Error>) {
+    if text.is_empty() {
+        return (vec![], vec![Error::from_code(ErrorCode::EmptyInput, 0, 1)]);
+    }
+    let mut state = LexerState::new(text, options);
+    let mut errors = vec![];
+    while state.index < state.chars.len() {
+        if let Err(err) = state.consume_char() {
+            while state.offsets[state.index] < err.end {
+                state.index += 1;
+            }
+            state
+                .tokens
+                .push(Token::new(TokenType::Invalid, err.start, err.end));
+            errors.push(err);
+        }
+    }
+    (state.tokens, errors)
 }
 
 #[cfg(test)]
@@ -176,61 +380,111 @@ mod tests {
 
     #[test]
     fn empty() {
-        let err = lex("").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(ErrorType::BadParse, "expected token".to_string(), 0, 1)
-        );
+        let err = lex("", &ParseOptions::default()).unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::EmptyInput, 0, 1));
     }
 
     #[test]
     fn too_large() {
         let mut too_big = f64::MAX.to_string();
-        too_big.push_str("0");
+        too_big.push('0');
 
-        let err = lex(too_big.as_str()).unwrap_err();
+        let err = lex(too_big.as_str(), &ParseOptions::default()).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::Overflow,
-                "number overflowed f64".to_string(),
-                0,
-                310
-            )
+            Error::from_code(ErrorCode::NumberOverflow(too_big.clone()), 0, 310)
         );
     }
 
     #[test]
     fn invalid_float_extra_decimal() {
-        let err = lex("0.2.3").unwrap_err();
+        let err = lex("0.2.3", &ParseOptions::default()).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::BadParse,
-                "invalid float literal".to_string(),
-                0,
-                5
-            )
+            Error::from_code(ErrorCode::InvalidNumber("0.2.3".to_string()), 0, 5)
         );
     }
 
     #[test]
     fn invalid_float_only_decimal() {
-        let err = lex("abc.").unwrap_err();
+        let err = lex("abc.", &ParseOptions::default()).unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::BadParse,
-                "invalid float literal".to_string(),
-                3,
-                4
-            )
+            Error::from_code(ErrorCode::InvalidNumber(".".to_string()), 3, 4)
+        );
+    }
+
+    #[test]
+    fn scientific_notation() {
+        let tokens = lex("1.5e-3+6.02E23 - 2e+1", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(1.5e-3), 0, 6),
+                Token::new(TokenType::Op(Operation::Add), 6, 7),
+                Token::new(TokenType::Constant(6.02e23), 7, 14),
+                Token::new(TokenType::Op(Operation::Subtract), 15, 16),
+                Token::new(TokenType::Constant(20.), 17, 21)
+            ]
+        );
+    }
+
+    #[test]
+    fn scientific_notation_bare_e() {
+        let tokens = lex("2e+x3E", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(2.), 0, 1),
+                Token::new(TokenType::NamedConstant("e".to_string()), 1, 2),
+                Token::new(TokenType::Op(Operation::Add), 2, 3),
+                Token::new(TokenType::Identifier("x".to_string()), 3, 4),
+                Token::new(TokenType::Constant(3.), 4, 5),
+                Token::new(TokenType::Identifier("E".to_string()), 5, 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn scientific_notation_words() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tokens = lex("1e3exp(1e-3)", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(1e3), 0, 3),
+                Token::new(TokenType::Function("exp".to_string()), 3, 6),
+                Token::new(TokenType::OpenParen, 6, 7),
+                Token::new(TokenType::Constant(1e-3), 7, 11),
+                Token::new(TokenType::CloseParen, 11, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn scientific_notation_too_large() {
+        let err = lex("4e309", &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::NumberOverflow("4e309".to_string()), 0, 5)
+        );
+    }
+
+    #[test]
+    fn scientific_notation_fractional_exponent() {
+        let err = lex("1e5.3", &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::InvalidNumber("1e5.3".to_string()), 0, 5)
         );
     }
 
     #[test]
     fn simple_mult() {
-        let tokens = lex("4*0.23").unwrap();
+        let tokens = lex("4*0.23", &ParseOptions::default()).unwrap();
         assert_eq!(
             tokens,
             vec![
@@ -243,7 +497,7 @@ mod tests {
 
     #[test]
     fn simple_add() {
-        let tokens = lex("0+45").unwrap();
+        let tokens = lex("0+45", &ParseOptions::default()).unwrap();
         assert_eq!(
             tokens,
             vec![
@@ -256,7 +510,7 @@ mod tests {
 
     #[test]
     fn with_spaces() {
-        let tokens = lex("5+ 4 * 3     * 9").unwrap();
+        let tokens = lex("5+ 4 * 3     * 9", &ParseOptions::default()).unwrap();
         assert_eq!(
             tokens,
             vec![
@@ -273,7 +527,7 @@ mod tests {
 
     #[test]
     fn parens() {
-        let tokens = lex("0+(7*5)+(6*(7+8+90))").unwrap();
+        let tokens = lex("0+(7*5)+(6*(7+8+90))", &ParseOptions::default()).unwrap();
         assert_eq!(
             tokens,
             vec![
@@ -302,19 +556,332 @@ mod tests {
 
     #[test]
     fn identifier() {
-        let tokens = lex("8y(4X + 7.3)").unwrap();
+        let tokens = lex("8y(4X + 7.3)", &ParseOptions::default()).unwrap();
         assert_eq!(
             tokens,
             vec![
                 Token::new(TokenType::Constant(8.), 0, 1),
-                Token::new(TokenType::Identifier('y'), 1, 2),
+                Token::new(TokenType::Identifier("y".to_string()), 1, 2),
                 Token::new(TokenType::OpenParen, 2, 3),
                 Token::new(TokenType::Constant(4.), 3, 4),
-                Token::new(TokenType::Identifier('X'), 4, 5),
+                Token::new(TokenType::Identifier("X".to_string()), 4, 5),
                 Token::new(TokenType::Op(Operation::Add), 6, 7),
                 Token::new(TokenType::Constant(7.3), 8, 11),
                 Token::new(TokenType::CloseParen, 11, 12)
             ]
         );
     }
+
+    #[test]
+    fn function() {
+        let tokens = lex("2sin (x)", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(2.), 0, 1),
+                Token::new(TokenType::Function("sin".to_string()), 1, 4),
+                Token::new(TokenType::OpenParen, 5, 6),
+                Token::new(TokenType::Identifier("x".to_string()), 6, 7),
+                Token::new(TokenType::CloseParen, 7, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn function_longest_match() {
+        let tokens = lex("xasin(y)", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("x".to_string()), 0, 1),
+                Token::new(TokenType::Function("asin".to_string()), 1, 5),
+                Token::new(TokenType::OpenParen, 5, 6),
+                Token::new(TokenType::Identifier("y".to_string()), 6, 7),
+                Token::new(TokenType::CloseParen, 7, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn function_name_without_paren() {
+        let tokens = lex("ln", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("l".to_string()), 0, 1),
+                Token::new(TokenType::Identifier("n".to_string()), 1, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn comma() {
+        let tokens = lex("(1,x)", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::OpenParen, 0, 1),
+                Token::new(TokenType::Constant(1.), 1, 2),
+                Token::new(TokenType::Comma, 2, 3),
+                Token::new(TokenType::Identifier("x".to_string()), 3, 4),
+                Token::new(TokenType::CloseParen, 4, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_names() {
+        let options = ParseOptions {
+            names: vec!["x_1".to_string(), "rate".to_string(), "r".to_string()],
+            ..ParseOptions::default()
+        };
+        let tokens = lex("2ratex_1", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(2.), 0, 1),
+                Token::new(TokenType::Identifier("rate".to_string()), 1, 5),
+                Token::new(TokenType::Identifier("x_1".to_string()), 5, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn function_wins_over_declared_name_of_equal_length() {
+        let options = ParseOptions {
+            names: vec!["sinh".to_string(), "cos".to_string()],
+            ..ParseOptions::default()
+        };
+        let tokens = lex("sinh(cos(", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Function("sinh".to_string()), 0, 4),
+                Token::new(TokenType::OpenParen, 4, 5),
+                Token::new(TokenType::Function("cos".to_string()), 5, 8),
+                Token::new(TokenType::OpenParen, 8, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_name_longer_than_function() {
+        let options = ParseOptions {
+            names: vec!["cosine".to_string()],
+            ..ParseOptions::default()
+        };
+        let tokens = lex("cosine(2)", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("cosine".to_string()), 0, 6),
+                Token::new(TokenType::OpenParen, 6, 7),
+                Token::new(TokenType::Constant(2.), 7, 8),
+                Token::new(TokenType::CloseParen, 8, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn words() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tokens = lex("2theta_max x1 sin(cos)", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(2.), 0, 1),
+                Token::new(TokenType::Identifier("theta_max".to_string()), 1, 10),
+                Token::new(TokenType::Identifier("x1".to_string()), 11, 13),
+                Token::new(TokenType::Function("sin".to_string()), 14, 17),
+                Token::new(TokenType::OpenParen, 17, 18),
+                Token::new(TokenType::Identifier("cos".to_string()), 18, 21),
+                Token::new(TokenType::CloseParen, 21, 22)
+            ]
+        );
+    }
+
+    #[test]
+    fn named_constants() {
+        let tokens = lex("2pi r + phi", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Constant(2.), 0, 1),
+                Token::new(TokenType::NamedConstant("pi".to_string()), 1, 3),
+                Token::new(TokenType::Identifier("r".to_string()), 4, 5),
+                Token::new(TokenType::Op(Operation::Add), 6, 7),
+                Token::new(TokenType::NamedConstant("phi".to_string()), 8, 11)
+            ]
+        );
+    }
+
+    #[test]
+    fn named_constants_disabled() {
+        let options = ParseOptions {
+            builtin_constants: false,
+            ..ParseOptions::default()
+        };
+        let tokens = lex("pie", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("p".to_string()), 0, 1),
+                Token::new(TokenType::Identifier("i".to_string()), 1, 2),
+                Token::new(TokenType::Identifier("e".to_string()), 2, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn named_constants_words() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tokens = lex("tau taus", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::NamedConstant("tau".to_string()), 0, 3),
+                Token::new(TokenType::Identifier("taus".to_string()), 4, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn byte_spans() {
+        let tokens = lex("x\u{a0}+\n\ty", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("x".to_string()), 0, 1),
+                Token::new(TokenType::Op(Operation::Add), 3, 4),
+                Token::new(TokenType::Identifier("y".to_string()), 6, 7)
+            ]
+        );
+
+        let err = lex("2 ÷ 3", &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::InvalidCharacter('÷'), 2, 4)
+        );
+    }
+
+    fn unicode_options() -> ParseOptions {
+        ParseOptions {
+            unicode: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn unicode_disabled() {
+        let err = lex("2 × 3", &ParseOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::InvalidCharacter('×'), 2, 4)
+        );
+    }
+
+    #[test]
+    fn unicode_operators() {
+        let tokens = lex("a×b·c÷d−π√e", &unicode_options()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("a".to_string()), 0, 1),
+                Token::new(TokenType::Op(Operation::Multiply), 1, 3),
+                Token::new(TokenType::Identifier("b".to_string()), 3, 4),
+                Token::new(TokenType::Op(Operation::Multiply), 4, 6),
+                Token::new(TokenType::Identifier("c".to_string()), 6, 7),
+                Token::new(TokenType::Op(Operation::Divide), 7, 9),
+                Token::new(TokenType::Identifier("d".to_string()), 9, 10),
+                Token::new(TokenType::Op(Operation::Subtract), 10, 13),
+                Token::new(TokenType::NamedConstant("pi".to_string()), 13, 15),
+                Token::new(TokenType::Root("sqrt"), 15, 18),
+                Token::new(TokenType::NamedConstant("e".to_string()), 18, 19)
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode_superscripts() {
+        let tokens = lex("x²⁰y⁻¹", &unicode_options()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("x".to_string()), 0, 1),
+                Token::new(TokenType::Op(Operation::Exponentiate), 1, 6),
+                Token::new(TokenType::Constant(20.), 1, 6),
+                Token::new(TokenType::Identifier("y".to_string()), 6, 7),
+                Token::new(TokenType::Op(Operation::Exponentiate), 7, 12),
+                Token::new(TokenType::Op(Operation::Subtract), 7, 10),
+                Token::new(TokenType::Constant(1.), 10, 12)
+            ]
+        );
+
+        let err = lex("x⁻", &unicode_options()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(ErrorCode::InvalidCharacter('⁻'), 1, 4)
+        );
+    }
+
+    #[test]
+    fn assignment() {
+        let tokens = lex("d=1; d", &ParseOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(TokenType::Identifier("d".to_string()), 0, 1),
+                Token::new(TokenType::Equals, 1, 2),
+                Token::new(TokenType::Constant(1.), 2, 3),
+                Token::new(TokenType::Semicolon, 3, 4),
+                Token::new(TokenType::Identifier("d".to_string()), 5, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_functions() {
+        let options = ParseOptions {
+            functions: vec!["f".to_string(), "area".to_string()],
+            ..ParseOptions::default()
+        };
+        let types: Vec<TokenType> = lex("area(r) + f x f (x)", &options)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.token_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Function("area".to_string()),
+                TokenType::OpenParen,
+                TokenType::Identifier("r".to_string()),
+                TokenType::CloseParen,
+                TokenType::Op(Operation::Add),
+                TokenType::Identifier("f".to_string()),
+                TokenType::Identifier("x".to_string()),
+                TokenType::Function("f".to_string()),
+                TokenType::OpenParen,
+                TokenType::Identifier("x".to_string()),
+                TokenType::CloseParen,
+            ]
+        );
+
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            functions: vec!["speed".to_string()],
+            ..ParseOptions::default()
+        };
+        let tokens = lex("speed(t)", &options).unwrap();
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenType::Function("speed".to_string()), 0, 5)
+        );
+    }
 }
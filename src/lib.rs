@@ -1,10 +1,19 @@
 //! Serious is a simple language for concise mathematical expressions.
 //!
 //! - The numerical type is [`f64`] (infinities and NaNs yield errors).
-//! - Variables are identified by characters within `[A-Za-z]`.
+//! - Literals may use scientific notation, e.g. `1.5e-3`; an `e` only starts an exponent when it is directly followed by a digit or a signed digit.
+//! - Variables are identified by characters within `[A-Za-z]`, unless longer names are declared or enabled through [`ParseOptions`](crate::parser::ParseOptions).
 //! - Multiplication is implicit where an operator is omitted.
-//! - All operations are infix binary, except for the unary minus.
-//! - Operations are left-associative unless overridden by parentheses or precedence rules:
+//! - Unicode math symbols such as `×`, `−`, `x²`, `√` and `π` are accepted when enabled through [`ParseOptions::unicode`](crate::parser::ParseOptions::unicode).
+//! - The length, nesting depth and size of the input, and the steps taken to evaluate it, are bounded by configurable [`Limits`](crate::limits::Limits), so untrusted input yields an error rather than overflowing the stack or running unchecked.
+//! - All operations are infix binary, except for the prefix minus and plus, which can precede any operand (e.g. `a*-b`).
+//! - Built-in [functions](crate::functions) are called with parentheses, e.g. `sin(2x)`.
+//! - Built-in [constants](crate::constants) such as `pi` and `e` are available unless bound by the [`Context`](crate::interpreter::Context), and are recognized ahead of splitting letters (e.g. `2pi r`) unless [disabled](crate::parser::ParseOptions::builtin_constants).
+//! - A formula may start with [assignments](crate::parser::ExpressionData::Let) ending in `;`, e.g. `d = b^2 - 4ac; (-b + d^0.5)/(2a)`; each name is in scope for the rest of the formula and shadows the [`Context`](crate::interpreter::Context).
+//! - Functions may be [defined](crate::parser::ExpressionData::Define) in the same way, e.g. `f(t) = 3t^2 + 1; f(x) + f(y)`, or kept in a [`Registry`](crate::functions::Registry) which is passed to the interpreter alongside the [`Context`](crate::interpreter::Context); a function cannot call itself.
+//! - Functions implemented in Rust can be [registered](crate::functions::Registry::register) under a name with a fixed or variable number of arguments, e.g. `max(a, b, c)`. A [`Registry`](crate::functions::Registry) starts with the built-in functions, which can also be replaced or removed.
+//! - Prefix operators bind tighter than multiplication but looser than exponentiation, so `-x^2` means `-(x^2)` and `2^-x` means `2^(-x)`.
+//! - Operations are left-associative unless overridden by parentheses or precedence rules, except for `^`, which is right-associative (`2^3^2` means `2^(3^2)`):
 //!
 //! | Operator | Meaning                                                | Precedence
 //! | -------- | ------------------------------------------------------ | ----------
@@ -33,8 +42,32 @@ mod lexer;
 /// Converts text into an [`Expression`](crate::parser::Expression) (an abstract syntax tree).
 pub mod parser;
 
-/// Evaluates an [`Expression`](crate::parser::Expression), given a [`Context`](crate::interpreter::Context) of bound identifiers.
+/// Defines the built-in functions which can be called from an expression, e.g. `sqrt(x)`, and the [`Registry`](crate::functions::Registry) which holds them alongside defined and registered ones.
+pub mod functions;
+
+/// Defines the built-in named constants which can be used in an expression, e.g. `2pi`.
+pub mod constants;
+
+/// Evaluates an [`Expression`](crate::parser::Expression), given a [`Context`](crate::interpreter::Context) or any other [`Resolver`](crate::interpreter::Resolver) of bound identifiers.
 pub mod interpreter;
 
+/// Compiles an [`Expression`](crate::parser::Expression) into bytecode which can be evaluated repeatedly over slices of values, or over columns of data in batches.
+pub mod compiler;
+
+/// Computes symbolic derivatives of an [`Expression`](crate::parser::Expression).
+pub mod calculus;
+
+/// Simplifies an [`Expression`](crate::parser::Expression) by folding constants and collecting like terms.
+pub mod simplify;
+
+/// Writes an [`Expression`](crate::parser::Expression) back to Serious source with minimal parentheses, or renders it as LaTeX, MathML, or Unicode text.
+pub mod printer;
+
+/// Converts the byte offsets of spans into lines and columns, chars, or UTF-16 code units.
+pub mod span;
+
+/// Defines the [`Limits`](crate::limits::Limits) which bound the work done on untrusted input.
+pub mod limits;
+
 /// Defines the type for [`Result::Err`]s of [`parse`](crate::parser::parse), [`interpret`](crate::interpreter::interpret), and [`interpret_tree`](crate::interpreter::interpret_tree).
 pub mod error;
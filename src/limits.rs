@@ -0,0 +1,79 @@
+/// Budgets for the work done on an expression, so that untrusted input cannot exhaust the stack or monopolize a thread.
+///
+/// Limits are set through [`ParseOptions::limits`](crate::parser::ParseOptions::limits), which are applied by
+/// [`parse_with`](crate::parser::parse_with) and [`interpret_with`](crate::interpreter::interpret_with), or passed to
+/// [`interpret_tree_with`](crate::interpreter::interpret_tree_with). Exceeding any of them yields a
+/// [`LimitExceeded`](crate::error::ErrorType::LimitExceeded) error, even from
+/// [`parse_recovering`](crate::parser::parse_recovering).
+///
+/// ```
+/// use serious::{create_context, interpreter::interpret_with};
+/// use serious::error::{ErrorCode, ErrorType};
+/// use serious::limits::Limits;
+/// use serious::parser::ParseOptions;
+///
+/// let options = ParseOptions {
+///     limits: Limits {
+///         max_nodes: 8,
+///         ..Limits::default()
+///     },
+///     ..ParseOptions::default()
+/// };
+/// let context = create_context!{'x' => 2.};
+/// assert_eq!(interpret_with("2x + 1", &context, &options).unwrap(), 5.);
+///
+/// let err = interpret_with("2x + 3x^2 + 1", &context, &options).unwrap_err();
+/// assert_eq!(err.error_type(), ErrorType::LimitExceeded);
+/// assert_eq!(err.code, ErrorCode::TooManyNodes(8));
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Limits {
+    /// The greatest length of the text in bytes; defaults to 65536.
+    /// Longer input yields a [`TooLong`](crate::error::ErrorCode::TooLong) error before it is lexed.
+    pub max_length: usize,
+    /// The greatest number of tokens in the text; defaults to 16384.
+    /// More yield a [`TooManyTokens`](crate::error::ErrorCode::TooManyTokens) error before parsing.
+    pub max_tokens: usize,
+    /// The greatest depth of nesting, counting parentheses, calls, prefix operators, exponents and assignments; defaults to 128.
+    /// A chain of operations such as a long sum is walked without recursion, so it is only bounded by `max_nodes`.
+    /// Deeper input yields a [`TooDeep`](crate::error::ErrorCode::TooDeep) error rather than overflowing the stack,
+    /// and every tree from a parse can be evaluated, printed or compiled without overflowing it.
+    pub max_depth: usize,
+    /// The greatest number of nodes in the tree; defaults to 32768.
+    /// More yield a [`TooManyNodes`](crate::error::ErrorCode::TooManyNodes) error.
+    /// Dropping a tree recurses once for each operation in a chain, so this also bounds the stack which that takes.
+    pub max_nodes: usize,
+    /// The greatest number of nodes which are evaluated by the interpreter; defaults to 1000000.
+    /// More yield a [`TooManySteps`](crate::error::ErrorCode::TooManySteps) error.
+    pub max_steps: usize,
+    /// The greatest nesting of calls to [defined](crate::parser::Definition) functions during an evaluation; defaults to 32.
+    /// Deeper calls yield a [`CallsTooDeep`](crate::error::ErrorCode::CallsTooDeep) error, so that functions which
+    /// were redefined to call each other in a cycle still terminate.
+    pub max_call_depth: usize,
+}
+
+impl Limits {
+    /// Limits which never stop a parse or an evaluation, except by the depth of nesting and of calls and by the number of nodes,
+    /// which still guard the stack.
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_length: usize::MAX,
+            max_tokens: usize::MAX,
+            max_steps: usize::MAX,
+            ..Limits::default()
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_length: 65536,
+            max_tokens: 16384,
+            max_depth: 128,
+            max_nodes: 32768,
+            max_steps: 1_000_000,
+            max_call_depth: 32,
+        }
+    }
+}
@@ -1,30 +1,91 @@
-use super::error::{Error, ErrorType};
+use super::error::{Error, ErrorCode};
+use super::functions::builtin;
+use super::interpreter::{unbound_identifier, wrong_argument_count};
 pub use super::lexer::Operation;
-use super::lexer::{lex, Token, TokenType};
+use super::lexer::{lex, lex_recovering, Token, TokenType};
+use super::limits::Limits;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 
 /// The semantic content of an expression.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ExpressionData {
     /// A binary operation.
-    /// In the case of unary minus, the left-hand side will be a zero-width 0.
     Op(Box<Expression>, Operation, Box<Expression>),
+    /// A unary minus applied to an operand.
+    Negate(Box<Expression>),
     /// A literal constant.
     Constant(f64),
     /// A named identifier.
-    Identifier(char),
+    Identifier(String),
+    /// A built-in [constant](crate::constants) such as `pi`, which can be shadowed by the [`Context`](crate::interpreter::Context).
+    NamedConstant(String),
+    /// A call to a named [function](crate::functions) with its arguments.
+    Call(String, Vec<Expression>),
+    /// A part of the text which failed to parse, only found in the partial trees from [`parse_recovering`](crate::parser::parse_recovering).
+    Error,
+    /// An assignment `name = value; body`, which binds the name to the value of the first expression while the second is evaluated.
+    /// The binding shadows identifiers of the same name from the [`Context`](crate::interpreter::Context) and from earlier assignments.
+    /// Assignments are only found at the top of a tree, or as the body of another assignment.
+    Let(String, Box<Expression>, Box<Expression>),
+    /// A definition `name(parameters) = body; rest`, which makes the function callable while the second expression is evaluated.
+    /// The body sees the assignments and definitions before it, but not the function itself or anything after it.
+    /// Definitions are found in the same places as assignments.
+    Define(Box<Definition>, Box<Expression>),
+}
+
+/// A function defined in terms of its parameters, e.g. `f(t) = 3t^2 + 1`.
+///
+/// Definitions are made at the start of a formula, or kept in a [`Registry`](crate::functions::Registry).
+#[derive(Debug, PartialEq, Clone)]
+pub struct Definition {
+    /// The name used to call the function.
+    pub name: String,
+    /// The names bound to the arguments of a call, in order.
+    pub parameters: Vec<String>,
+    /// The expression which gives the result of a call.
+    pub body: Expression,
 }
 
 /// The output of a successful parse; contains sub-expressions in a tree structure.
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Expression {
     /// The semantic content of the expression.
     pub data: ExpressionData,
-    /// The start of the expression in the original text.
+    /// The byte offset of the start of the expression in the original text.
     pub start: usize,
-    /// The end of the expression in the original text.
+    /// The byte offset of the end of the expression in the original text.
     pub end: usize,
 }
 
+// a chain of left-hand sides, e.g. a long sum, is only bounded by the number of nodes, so it is
+// cloned and compared without recursion
+impl Clone for Expression {
+    fn clone(&self) -> Expression {
+        self.map_chain(|operand| Expression {
+            data: operand.data.clone(),
+            start: operand.start,
+            end: operand.end,
+        })
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Expression) -> bool {
+        let ((leftmost, chain), (other_leftmost, other_chain)) =
+            (self.left_chain(), other.left_chain());
+        let same_span = |a: &Expression, b: &Expression| a.start == b.start && a.end == b.end;
+        chain.len() == other_chain.len()
+            && same_span(leftmost, other_leftmost)
+            && leftmost.data == other_leftmost.data
+            && chain.iter().zip(&other_chain).all(
+                |((node, op, rhs), (other_node, other_op, other_rhs))| {
+                    same_span(node, other_node) && op == other_op && rhs == other_rhs
+                },
+            )
+    }
+}
+
 impl Expression {
     /// Create an expression for a constant literal.
     pub fn new_const(val: f64, start: usize, end: usize) -> Expression {
@@ -32,9 +93,56 @@ impl Expression {
         Expression { data, start, end }
     }
 
-    /// Create an expression for an identifier.
-    pub fn new_id(name: char, start: usize, end: usize) -> Expression {
-        let data = ExpressionData::Identifier(name);
+    /// Create an expression for an identifier; accepts either a [`char`] or a string.
+    pub fn new_id<S: Into<String>>(name: S, start: usize, end: usize) -> Expression {
+        let data = ExpressionData::Identifier(name.into());
+        Expression { data, start, end }
+    }
+
+    /// Create an expression for a built-in named constant.
+    pub fn new_named_const(name: &str, start: usize, end: usize) -> Expression {
+        let data = ExpressionData::NamedConstant(name.to_string());
+        Expression { data, start, end }
+    }
+
+    /// Create an expression for a call to a named function.
+    pub fn new_call(name: &str, args: Vec<Expression>, start: usize, end: usize) -> Expression {
+        let data = ExpressionData::Call(name.to_string(), args);
+        Expression { data, start, end }
+    }
+
+    /// Create a placeholder for a part of the text which failed to parse.
+    pub fn new_error(start: usize, end: usize) -> Expression {
+        let data = ExpressionData::Error;
+        Expression { data, start, end }
+    }
+
+    /// Create an assignment of `value` to `name`, which is in scope for `body`.
+    pub fn new_let(
+        name: &str,
+        value: Expression,
+        body: Expression,
+        start: usize,
+        end: usize,
+    ) -> Expression {
+        let data = ExpressionData::Let(name.to_string(), Box::new(value), Box::new(body));
+        Expression { data, start, end }
+    }
+
+    /// Create a definition of a function, which is in scope for `rest`.
+    pub fn new_define(
+        definition: Definition,
+        rest: Expression,
+        start: usize,
+        end: usize,
+    ) -> Expression {
+        let data = ExpressionData::Define(Box::new(definition), Box::new(rest));
+        Expression { data, start, end }
+    }
+
+    /// Create an expression for the negation of an operand.
+    pub fn new_neg(operand: Expression, start: usize, end: usize) -> Expression {
+        let data = ExpressionData::Negate(Box::new(operand));
         Expression { data, start, end }
     }
 
@@ -56,6 +164,242 @@ impl Expression {
             end,
         }
     }
+
+    // the operand at the bottom of a chain of left-hand sides, e.g. the first term of a sum, and the
+    // operations above it from the innermost out with their right-hand sides; a chain is only bounded
+    // by the number of nodes, so it is walked with this rather than by recursion
+    pub(crate) fn left_chain(&self) -> (&Expression, Vec<(&Expression, Operation, &Expression)>) {
+        let mut chain = vec![];
+        let mut leftmost = self;
+        while let ExpressionData::Op(lhs, op, rhs) = &leftmost.data {
+            chain.push((leftmost, *op, rhs.as_ref()));
+            leftmost = lhs;
+        }
+        chain.reverse();
+        (leftmost, chain)
+    }
+
+    // rebuilds a chain of left-hand sides with each operand mapped by `f`, keeping the operations and their spans
+    pub(crate) fn map_chain<F: FnMut(&Expression) -> Expression>(&self, mut f: F) -> Expression {
+        let (leftmost, chain) = self.left_chain();
+        let mut tree = f(leftmost);
+        for (node, op, rhs) in chain {
+            let data = ExpressionData::Op(Box::new(tree), op, Box::new(f(rhs)));
+            tree = Expression {
+                data,
+                start: node.start,
+                end: node.end,
+            };
+        }
+        tree
+    }
+
+    /// Compare the structure of two expressions, ignoring the `start` and `end` positions of every node.
+    pub fn eq_ignoring_spans(&self, other: &Expression) -> bool {
+        match (&self.data, &other.data) {
+            (ExpressionData::Op(_, _, _), ExpressionData::Op(_, _, _)) => {
+                let ((leftmost, chain), (other_leftmost, other_chain)) =
+                    (self.left_chain(), other.left_chain());
+                chain.len() == other_chain.len()
+                    && leftmost.eq_ignoring_spans(other_leftmost)
+                    && chain.iter().zip(&other_chain).all(
+                        |((_, op, rhs), (_, other_op, other_rhs))| {
+                            op == other_op && rhs.eq_ignoring_spans(other_rhs)
+                        },
+                    )
+            }
+            (ExpressionData::Negate(operand), ExpressionData::Negate(other_operand)) => {
+                operand.eq_ignoring_spans(other_operand)
+            }
+            (
+                ExpressionData::Let(name, value, body),
+                ExpressionData::Let(other_name, other_value, other_body),
+            ) => {
+                name == other_name
+                    && value.eq_ignoring_spans(other_value)
+                    && body.eq_ignoring_spans(other_body)
+            }
+            (
+                ExpressionData::Define(definition, rest),
+                ExpressionData::Define(other_definition, other_rest),
+            ) => {
+                definition.name == other_definition.name
+                    && definition.parameters == other_definition.parameters
+                    && definition.body.eq_ignoring_spans(&other_definition.body)
+                    && rest.eq_ignoring_spans(other_rest)
+            }
+            (ExpressionData::Call(name, args), ExpressionData::Call(other_name, other_args)) => {
+                name == other_name
+                    && args.len() == other_args.len()
+                    && args
+                        .iter()
+                        .zip(other_args)
+                        .all(|(arg, other_arg)| arg.eq_ignoring_spans(other_arg))
+            }
+            (data, other_data) => data == other_data,
+        }
+    }
+
+    // visits every identifier in the tree which is not bound by an assignment or a parameter, in order of position
+    fn for_each_identifier<'a, F: FnMut(&'a str, &'a Expression)>(
+        &'a self,
+        bound: &mut Vec<&'a str>,
+        f: &mut F,
+    ) {
+        match &self.data {
+            ExpressionData::Identifier(name) => {
+                if !bound.contains(&name.as_str()) {
+                    f(name, self)
+                }
+            }
+            ExpressionData::Op(_, _, _) => {
+                let (leftmost, chain) = self.left_chain();
+                leftmost.for_each_identifier(bound, f);
+                for (_, _, rhs) in chain {
+                    rhs.for_each_identifier(bound, f);
+                }
+            }
+            ExpressionData::Negate(operand) => operand.for_each_identifier(bound, f),
+            ExpressionData::Call(_, args) => args
+                .iter()
+                .for_each(|arg| arg.for_each_identifier(bound, f)),
+            ExpressionData::Let(name, value, body) => {
+                value.for_each_identifier(bound, f);
+                bound.push(name);
+                body.for_each_identifier(bound, f);
+                bound.pop();
+            }
+            ExpressionData::Define(definition, rest) => {
+                let outer = bound.len();
+                bound.extend(definition.parameters.iter().map(|name| name.as_str()));
+                definition.body.for_each_identifier(bound, f);
+                bound.truncate(outer);
+                rest.for_each_identifier(bound, f);
+            }
+            ExpressionData::Constant(_)
+            | ExpressionData::NamedConstant(_)
+            | ExpressionData::Error => {}
+        }
+    }
+
+    /// The identifiers which must be bound to evaluate the expression, each with the spans where it is used.
+    ///
+    /// Uses of a name in the body of an [assignment](crate::parser::ExpressionData::Let) to it are not included, since they refer to the assigned value,
+    /// and neither are the uses of a parameter in the body of a [definition](crate::parser::ExpressionData::Define).
+    /// [Named constants](crate::parser::ExpressionData::NamedConstant) are not included, since they have built-in values.
+    ///
+    /// ```
+    /// use serious::parser::parse;
+    ///
+    /// let free = parse("2x + y/x - pi").unwrap().free_identifiers();
+    /// assert_eq!(free.keys().collect::<Vec<_>>(), ["x", "y"]);
+    /// assert_eq!(free["x"], [(1, 2), (7, 8)]);
+    /// ```
+    pub fn free_identifiers(&self) -> BTreeMap<String, Vec<(usize, usize)>> {
+        let mut free: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+        self.for_each_identifier(&mut vec![], &mut |name, node| {
+            free.entry(name.to_string())
+                .or_default()
+                .push((node.start, node.end));
+        });
+        free
+    }
+
+    /// Checks that every identifier is in `allowed`, without evaluating anything.
+    ///
+    /// Returns an [`UnboundIdentifier`](crate::error::ErrorCode::UnboundIdentifier) error for every use of an unknown name, ordered by position.
+    ///
+    /// ```
+    /// use serious::parser::parse;
+    ///
+    /// let tree = parse("a x^2 + b x + c").unwrap();
+    /// assert!(tree.validate(&["a", "b", "c", "x"]).is_ok());
+    ///
+    /// let errors = tree.validate(&["x", "b"]).unwrap_err();
+    /// let spans: Vec<(usize, usize)> = errors.iter().map(|err| (err.start, err.end)).collect();
+    /// assert_eq!(spans, [(0, 1), (14, 15)]);
+    /// ```
+    pub fn validate(&self, allowed: &[&str]) -> Result<(), Vec<Error>> {
+        let mut errors = vec![];
+        self.for_each_identifier(&mut vec![], &mut |name, node| {
+            if !allowed.contains(&name) {
+                errors.push(unbound_identifier(name, node.start, node.end));
+            }
+        });
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// How runs of letters are split into identifiers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IdentifierMode {
+    /// Every letter is its own identifier, so `xy` means `x*y`.
+    /// Names declared in [`ParseOptions::names`](crate::parser::ParseOptions::names) are matched greedily first, so with `rate` declared, `2rate x` means `2*rate*x`.
+    Letter,
+    /// Identifiers are whole words matching `[A-Za-z][A-Za-z0-9_]*`, such as `theta_max` or `x_1`.
+    /// Multiplication between two identifiers needs a space or an operator, e.g. `rate time`.
+    Word,
+}
+
+/// Options for [`parse_with`](crate::parser::parse_with).
+///
+/// ```
+/// use serious::{create_context, interpreter::interpret_with};
+/// use serious::parser::{parse_with, Expression, IdentifierMode, ParseOptions};
+///
+/// let options = ParseOptions {
+///     identifier_mode: IdentifierMode::Word,
+///     ..ParseOptions::default()
+/// };
+/// assert_eq!(parse_with("x_1", &options).unwrap(), Expression::new_id("x_1", 0, 3));
+///
+/// let options = ParseOptions {
+///     names: vec!["rate".to_string()],
+///     ..ParseOptions::default()
+/// };
+/// let context = create_context!{"rate" => 0.5, 'x' => 3.};
+/// assert_eq!(interpret_with("2rate x", &context, &options).unwrap(), 3.);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseOptions {
+    /// How runs of letters are split into identifiers; defaults to [`Letter`](crate::parser::IdentifierMode::Letter).
+    pub identifier_mode: IdentifierMode,
+    /// Multi-character identifiers to recognize in [`Letter`](crate::parser::IdentifierMode::Letter) mode.
+    pub names: Vec<String>,
+    /// Functions to recognize in calls besides the [built-in](crate::functions) ones, such as those in a [`Registry`](crate::functions::Registry).
+    /// Functions defined in the text itself are always recognized, but in [`Letter`](crate::parser::IdentifierMode::Letter) mode a
+    /// function with a longer name must be declared here to be defined, since otherwise its name is split into letters.
+    pub functions: Vec<String>,
+    /// Whether to recognize the built-in [constants](crate::constants); defaults to `true`.
+    /// When disabled, their names are ordinary identifiers, so that in [`Letter`](crate::parser::IdentifierMode::Letter)
+    /// mode e.g. `pi` is `p*i`.
+    pub builtin_constants: bool,
+    /// Whether to accept Unicode math symbols; defaults to `false`.
+    /// - `×`, `·` and `⋅` mean `*`, `÷` means `/`, and `−` means `-`.
+    /// - Superscript digits are exponents, so `x²` means `x^2` and `x⁻¹` means `x^-1`.
+    /// - `√` and `∛` take the root of the operand after them, so `√x` means `sqrt(x)`.
+    /// - `π`, `τ` and `φ` are the constants `pi`, `tau` and `phi`.
+    pub unicode: bool,
+    /// Budgets for the length, tokens, depth and size of the input, and for evaluating it with
+    /// [`interpret_with`](crate::interpreter::interpret_with); see [`Limits`](crate::limits::Limits).
+    pub limits: Limits,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            identifier_mode: IdentifierMode::Letter,
+            names: vec![],
+            functions: vec![],
+            builtin_constants: true,
+            unicode: false,
+            limits: Limits::default(),
+        }
+    }
 }
 
 fn precedence(operation: &Operation) -> i32 {
@@ -68,161 +412,610 @@ fn precedence(operation: &Operation) -> i32 {
     }
 }
 
-fn parse_tokens(
-    tokens: &[Token],
-    start: usize,
-    expect_close_paren: bool,
-) -> Result<(Expression, usize), Error> {
-    let mut stack: Vec<(Operation, Expression)> = vec![];
-    let (mut curr_lhs, mut i) = match tokens[start].token_type {
-        TokenType::Constant(val) => (
-            Expression::new_const(val, tokens[start].start, tokens[start].end),
-            start + 1,
-        ),
-        TokenType::Identifier(name) => (
-            Expression::new_id(name, tokens[start].start, tokens[start].end),
-            start + 1,
-        ),
-        TokenType::Op(Operation::Subtract) => {
-            // unary minus implemented as a zero-width 0
-            (
-                Expression::new_const(0., tokens[start].start, tokens[start].start),
-                start,
-            )
+fn expected_expression(tokens: &[Token], i: usize) -> Error {
+    let (start, end) = if i < tokens.len() {
+        (tokens[i].start, tokens[i].end)
+    } else if i > 0 {
+        (tokens[i - 1].end, tokens[i - 1].end + 1)
+    } else {
+        (0, 1)
+    };
+    Error::from_code(ErrorCode::ExpectedExpression, start, end)
+}
+
+fn unmatched_paren(paren: &Token) -> Error {
+    Error::from_code(ErrorCode::UnmatchedParen, paren.start, paren.end)
+}
+
+fn unexpected_comma(comma: &Token) -> Error {
+    Error::from_code(ErrorCode::UnexpectedComma, comma.start, comma.end)
+}
+
+// `2^3^2` means `2^(3^2)`
+fn right_associative(operation: &Operation) -> bool {
+    *operation == Operation::Exponentiate
+}
+
+// the name in an identifier or a named constant, which can both be bound or defined
+fn name_of(token: &Token) -> Option<&str> {
+    match &token.token_type {
+        TokenType::Identifier(name) | TokenType::NamedConstant(name) => Some(name),
+        _ => None,
+    }
+}
+
+// a statement which starts `f(x, y) =` defines a function, given its name, the list of its parameters
+// separated by commas, and the index of the equals sign
+fn definition_head(statement: &[Token]) -> Option<(&str, &[Token], usize)> {
+    let name = match &statement.first()?.token_type {
+        TokenType::Function(name) => name,
+        _ => return None,
+    };
+    let close_paren = statement
+        .iter()
+        .position(|token| token.token_type == TokenType::CloseParen)?;
+    if statement.get(close_paren + 1)?.token_type != TokenType::Equals {
+        return None;
+    }
+    let list = &statement[2..close_paren];
+    let well_formed = list.len() % 2 == 1
+        && list.iter().enumerate().all(|(i, token)| {
+            if i % 2 == 0 {
+                name_of(token).is_some()
+            } else {
+                token.token_type == TokenType::Comma
+            }
+        });
+    if !well_formed {
+        return None;
+    }
+    Some((name, list, close_paren + 1))
+}
+
+// the first call to `name` in a tree, in order of position
+fn find_call<'e>(tree: &'e Expression, name: &str) -> Option<&'e Expression> {
+    match &tree.data {
+        ExpressionData::Call(function, args) => {
+            if function == name {
+                Some(tree)
+            } else {
+                args.iter().find_map(|arg| find_call(arg, name))
+            }
         }
-        TokenType::OpenParen => {
-            if start + 1 == tokens.len() {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "failed to match paren".to_string(),
-                    tokens[start].start,
-                    tokens[start].end,
-                ));
+        ExpressionData::Op(_, _, _) => {
+            let (leftmost, chain) = tree.left_chain();
+            find_call(leftmost, name)
+                .or_else(|| chain.iter().find_map(|(_, _, rhs)| find_call(rhs, name)))
+        }
+        ExpressionData::Negate(operand) => find_call(operand, name),
+        ExpressionData::Let(_, value, body) => {
+            find_call(value, name).or_else(|| find_call(body, name))
+        }
+        ExpressionData::Define(definition, rest) => {
+            find_call(&definition.body, name).or_else(|| find_call(rest, name))
+        }
+        ExpressionData::Constant(_)
+        | ExpressionData::Identifier(_)
+        | ExpressionData::NamedConstant(_)
+        | ExpressionData::Error => None,
+    }
+}
+
+// the part of a statement before its value
+#[derive(Debug)]
+enum Statement {
+    Assignment(String),
+    Definition(String, Vec<String>),
+}
+
+// a parsed expression along with the depth of recursion needed to walk it and its number of nodes
+#[derive(Debug)]
+struct Node {
+    tree: Expression,
+    height: usize,
+    size: usize,
+}
+
+impl Node {
+    fn leaf(tree: Expression) -> Node {
+        Node {
+            tree,
+            height: 1,
+            size: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ParserState<'a> {
+    tokens: &'a [Token],
+    errors: Vec<Error>,
+    recover: bool,
+    // the last stray token which was skipped to recover from an error
+    skipped: Option<usize>,
+    max_depth: usize,
+    max_nodes: usize,
+    // the current nesting of parentheses, calls, prefixes and exponents
+    depth: usize,
+    // the name and number of parameters of each function defined so far
+    definitions: Vec<(String, usize)>,
+    // functions from elsewhere, which may replace the built-in functions
+    functions: &'a [String],
+}
+
+impl<'a> ParserState<'a> {
+    fn new(tokens: &'a [Token], recover: bool, options: &'a ParseOptions) -> ParserState<'a> {
+        ParserState {
+            tokens,
+            errors: vec![],
+            recover,
+            skipped: None,
+            max_depth: options.limits.max_depth,
+            max_nodes: options.limits.max_nodes,
+            depth: 0,
+            definitions: vec![],
+            functions: &options.functions,
+        }
+    }
+
+    // a recovering parse records the error and carries on; otherwise the error is returned
+    fn fail(&mut self, err: Error) -> Result<(), Error> {
+        if !self.recover {
+            return Err(err);
+        }
+        let reported = self
+            .errors
+            .iter()
+            .any(|prev| prev.start == err.start && prev.end == err.end);
+        if !reported {
+            self.errors.push(err);
+        }
+        Ok(())
+    }
+
+    // whether the token before `i` was already reported, so that it does not cause a second error
+    fn after_error(&self, i: usize) -> bool {
+        i > 0
+            && (self.tokens[i - 1].token_type == TokenType::Invalid || self.skipped == Some(i - 1))
+    }
+
+    // every branch is built through here, so no tree is ever deeper or larger than the limits;
+    // exceeding them is an error even in a recovering parse
+    fn branch(&self, tree: Expression, height: usize, size: usize) -> Result<Node, Error> {
+        let code = if height > self.max_depth {
+            ErrorCode::TooDeep(self.max_depth)
+        } else if size > self.max_nodes {
+            ErrorCode::TooManyNodes(self.max_nodes)
+        } else {
+            return Ok(Node { tree, height, size });
+        };
+        Err(Error::from_code(code, tree.start, tree.end))
+    }
+
+    // a chain of left-hand sides, such as a long sum, is walked without recursion, so only the
+    // right-hand side is a level deeper; a chain of exponents still nests
+    fn op(&self, lhs: Node, op: Operation, rhs: Node) -> Result<Node, Error> {
+        let (height, size) = (lhs.height.max(1 + rhs.height), 1 + lhs.size + rhs.size);
+        self.branch(Expression::new_op(lhs.tree, op, rhs.tree), height, size)
+    }
+
+    // each level of recursion is counted, so that nesting cannot overflow the stack
+    fn descend(&mut self, i: usize) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            let code = ErrorCode::TooDeep(self.max_depth);
+            return Err(Error::from_code(
+                code,
+                self.tokens[i].start,
+                self.tokens[i].end,
+            ));
+        }
+        Ok(())
+    }
+
+    fn ascend(&mut self) {
+        self.depth -= 1;
+    }
+
+    // the end of a parenthesized list and the index after it, even if the list was never closed
+    fn close(&self, end_paren: usize) -> (usize, usize) {
+        match self.tokens.get(end_paren) {
+            Some(paren) => (paren.end, end_paren + 1),
+            None => (self.tokens[end_paren - 1].end, end_paren),
+        }
+    }
+
+    // an operand which is missing from a recovering parse becomes an error node, and
+    // parsing resumes at the unexpected token
+    fn missing_operand(&mut self, i: usize) -> Result<(Node, usize), Error> {
+        let err = expected_expression(self.tokens, i);
+        let (start, end) = (err.start, err.end);
+        self.fail(err)?;
+        Ok((Node::leaf(Expression::new_error(start, end)), i))
+    }
+
+    // parses comma-separated expressions after `separator` up to the paren which closes `open_paren`
+    fn parse_args(
+        &mut self,
+        open_paren: usize,
+        mut separator: usize,
+    ) -> Result<(Vec<Node>, usize), Error> {
+        let tokens = self.tokens;
+        let mut args = vec![];
+        // a call may have no arguments, e.g. `rand()`
+        if separator == open_paren
+            && tokens.get(separator + 1).map(|token| &token.token_type)
+                == Some(&TokenType::CloseParen)
+        {
+            return Ok((args, separator + 1));
+        }
+        loop {
+            if separator + 1 == tokens.len() {
+                self.fail(unmatched_paren(&tokens[open_paren]))?;
+                return Ok((args, tokens.len()));
             }
-            let (inner_expr, end_paren) = parse_tokens(tokens, start + 1, true)?;
-            if end_paren == tokens.len() {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "failed to match paren".to_string(),
-                    tokens[start].start,
-                    tokens[start].end,
-                ));
+            let (arg, end) = self.parse_tokens(separator + 1, true)?;
+            args.push(arg);
+            if end == tokens.len() {
+                self.fail(unmatched_paren(&tokens[open_paren]))?;
+                return Ok((args, end));
             }
-            (
-                inner_expr.with_bounds(tokens[start].start, tokens[end_paren].end),
-                end_paren + 1,
-            )
+            if tokens[end].token_type == TokenType::CloseParen {
+                return Ok((args, end));
+            }
+            separator = end;
         }
-        _ => {
-            return Err(Error::new(
-                ErrorType::BadParse,
-                "expected expression".to_string(),
-                tokens[start].start,
-                tokens[start].end,
-            ))
+    }
+
+    fn parse_parens(&mut self, open_paren: usize) -> Result<(Node, usize), Error> {
+        let tokens = self.tokens;
+        let start = tokens[open_paren].start;
+        if open_paren + 1 == tokens.len() {
+            self.fail(unmatched_paren(&tokens[open_paren]))?;
+            let tree = Expression::new_error(start, tokens[open_paren].end);
+            return Ok((Node::leaf(tree), tokens.len()));
         }
-    };
+        self.descend(open_paren)?;
+        let (inner, end_paren) = self.parse_tokens(open_paren + 1, true)?;
+        if end_paren == tokens.len() {
+            self.fail(unmatched_paren(&tokens[open_paren]))?;
+        } else if tokens[end_paren].token_type == TokenType::Comma {
+            self.fail(unexpected_comma(&tokens[end_paren]))?;
+            // the rest of the list is skipped over as if it were the arguments of a call
+            let (_, end_paren) = self.parse_args(open_paren, end_paren)?;
+            self.ascend();
+            let (end, next) = self.close(end_paren);
+            return Ok((Node::leaf(Expression::new_error(start, end)), next));
+        }
+        self.ascend();
+        let (end, next) = self.close(end_paren);
+        let tree = inner.tree.with_bounds(start, end);
+        Ok((Node { tree, ..inner }, next))
+    }
 
-    while i < tokens.len() {
-        let curr_op = match tokens[i].token_type {
-            TokenType::Op(op) => {
-                i += 1;
-                op
-            }
-            TokenType::Identifier(_) | TokenType::OpenParen => Operation::Multiply,
-            TokenType::Constant(_) => {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "constant on RHS of implicit multiplication".to_string(),
-                    tokens[i].start,
-                    tokens[i].end,
-                ))
+    // the lexer only emits a function token when it is followed by an open paren
+    fn parse_call(&mut self, name_index: usize) -> Result<(Node, usize), Error> {
+        let name = match &self.tokens[name_index].token_type {
+            TokenType::Function(name) => name,
+            _ => return self.missing_operand(name_index),
+        };
+        self.descend(name_index)?;
+        let (args, end_paren) = self.parse_args(name_index + 1, name_index + 1)?;
+        self.ascend();
+
+        let (start, (end, next)) = (self.tokens[name_index].start, self.close(end_paren));
+        // functions from elsewhere are checked when they are called
+        let expected = match self
+            .definitions
+            .iter()
+            .rev()
+            .find(|(defined, _)| defined == name)
+        {
+            Some((_, parameters)) => Some(*parameters),
+            None if self.functions.contains(name) => None,
+            None => builtin(name).map(|_| 1),
+        };
+        match expected {
+            Some(expected) if args.len() != expected => {
+                self.fail(wrong_argument_count(name, expected, args.len(), start, end))?
             }
-            TokenType::CloseParen => {
-                if expect_close_paren {
-                    break;
-                } else {
-                    return Err(Error::new(
-                        ErrorType::BadParse,
-                        "expected expression".to_string(),
-                        tokens[i].start,
-                        tokens[i].end,
-                    ));
-                }
+            _ => {}
+        }
+        let height = 1 + args.iter().map(|arg| arg.height).max().unwrap_or(0);
+        let size = 1 + args.iter().map(|arg| arg.size).sum::<usize>();
+        let args = args.into_iter().map(|arg| arg.tree).collect();
+        let tree = Expression::new_call(name, args, start, end);
+        Ok((self.branch(tree, height, size)?, next))
+    }
+
+    fn parse_operand(&mut self, i: usize) -> Result<(Node, usize), Error> {
+        let token = match self.tokens.get(i) {
+            Some(token) => token,
+            None => return self.missing_operand(i),
+        };
+        let leaf = match &token.token_type {
+            TokenType::Constant(val) => Expression::new_const(*val, token.start, token.end),
+            TokenType::Identifier(name) => {
+                Expression::new_id(name.as_str(), token.start, token.end)
+            }
+            TokenType::NamedConstant(name) => {
+                Expression::new_named_const(name, token.start, token.end)
+            }
+            // the lexer has already reported the error
+            TokenType::Invalid => Expression::new_error(token.start, token.end),
+            // a radical only covers the operand after it, so `√x²` means `(√x)²`
+            TokenType::Root(name) => {
+                self.descend(i)?;
+                let (radicand, end) = self.parse_operand(i + 1)?;
+                self.ascend();
+                let (start, radicand_end) = (token.start, radicand.tree.end);
+                let tree = Expression::new_call(name, vec![radicand.tree], start, radicand_end);
+                return Ok((
+                    self.branch(tree, radicand.height + 1, radicand.size + 1)?,
+                    end,
+                ));
             }
+            TokenType::Function(_) => return self.parse_call(i),
+            TokenType::OpenParen => return self.parse_parens(i),
+            TokenType::Op(Operation::Subtract) | TokenType::Op(Operation::Add) => {
+                return self.parse_prefix(i)
+            }
+            _ => return self.missing_operand(i),
         };
+        Ok((Node::leaf(leaf), i + 1))
+    }
 
-        if i == tokens.len() {
-            return Err(Error::new(
-                ErrorType::BadParse,
-                "expected expression".to_string(),
-                tokens[i - 1].end,
-                tokens[i - 1].end + 1,
-            ));
+    // an operand followed by a right-associative chain of exponents
+    fn parse_power(&mut self, i: usize) -> Result<(Node, usize), Error> {
+        let (base, i) = self.parse_operand(i)?;
+        match self.tokens.get(i).map(|token| &token.token_type) {
+            Some(TokenType::Op(Operation::Exponentiate)) => {
+                self.descend(i)?;
+                let (exponent, i) = self.parse_power(i + 1)?;
+                self.ascend();
+                Ok((self.op(base, Operation::Exponentiate, exponent)?, i))
+            }
+            _ => Ok((base, i)),
         }
+    }
 
-        let curr_rhs = match tokens[i].token_type {
+    // prefix operators bind tighter than multiplication but looser than exponentiation,
+    // so `-2x` means `(-2)x` and `-x^2` means `-(x^2)`
+    fn parse_prefix(&mut self, i: usize) -> Result<(Node, usize), Error> {
+        self.descend(i)?;
+        let (operand, end) = self.parse_power(i + 1)?;
+        self.ascend();
+        let (start, operand_end) = (self.tokens[i].start, operand.tree.end);
+        match self.tokens[i].token_type {
             TokenType::Op(Operation::Subtract) => {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "expected expression; wrap in parens for unary minus".to_string(),
-                    tokens[i].start,
-                    tokens[i].end,
+                let tree = Expression::new_neg(operand.tree, start, operand_end);
+                Ok((
+                    self.branch(tree, operand.height + 1, operand.size + 1)?,
+                    end,
                 ))
             }
-            TokenType::Op(_) => {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "expected expression".to_string(),
-                    tokens[i].start,
-                    tokens[i].end,
-                ))
+            _ => {
+                let tree = operand.tree.with_bounds(start, operand_end);
+                Ok((Node { tree, ..operand }, end))
             }
-            TokenType::Identifier(name) => {
-                i += 1;
-                Expression::new_id(name, tokens[i - 1].start, tokens[i - 1].end)
-            }
-            TokenType::Constant(val) => {
-                i += 1;
-                Expression::new_const(val, tokens[i - 1].start, tokens[i - 1].end)
-            }
-            TokenType::OpenParen => {
-                let old_i = i;
-                let (inner_expr, end_paren) = parse_tokens(tokens, old_i + 1, true)?;
-                i = end_paren + 1;
-                if end_paren == tokens.len() {
-                    return Err(Error::new(
-                        ErrorType::BadParse,
-                        "failed to match paren".to_string(),
-                        tokens[old_i].start,
-                        tokens[old_i].end,
-                    ));
+        }
+    }
+
+    fn parse_tokens(
+        &mut self,
+        start: usize,
+        expect_close_paren: bool,
+    ) -> Result<(Node, usize), Error> {
+        let tokens = self.tokens;
+        let mut stack: Vec<(Operation, Node)> = vec![];
+        let (mut curr_lhs, mut i) = self.parse_operand(start)?;
+
+        while i < tokens.len() {
+            let (curr_op, curr_rhs, next_i) = match tokens[i].token_type {
+                TokenType::Op(op) => {
+                    let (rhs, next_i) = self.parse_operand(i + 1)?;
+                    (op, rhs, next_i)
                 }
-                inner_expr.with_bounds(tokens[old_i].start, tokens[end_paren].end)
-            }
-            TokenType::CloseParen => {
-                return Err(Error::new(
-                    ErrorType::BadParse,
-                    "expected expression".to_string(),
-                    tokens[i].start,
-                    tokens[i].end,
-                ))
+                TokenType::Identifier(_)
+                | TokenType::NamedConstant(_)
+                | TokenType::Function(_)
+                | TokenType::OpenParen
+                | TokenType::Root(_)
+                | TokenType::Invalid => {
+                    let (rhs, next_i) = self.parse_operand(i)?;
+                    (Operation::Multiply, rhs, next_i)
+                }
+                TokenType::Constant(val) => {
+                    let (rhs, next_i) = self.parse_operand(i)?;
+                    if self.after_error(i) {
+                        (Operation::Multiply, rhs, next_i)
+                    } else {
+                        self.fail(Error::from_code(
+                            ErrorCode::ImplicitMultiplicationByConstant(val),
+                            tokens[i].start,
+                            tokens[i].end,
+                        ))?;
+                        let rhs = Expression::new_error(rhs.tree.start, rhs.tree.end);
+                        (Operation::Multiply, Node::leaf(rhs), next_i)
+                    }
+                }
+                TokenType::CloseParen | TokenType::Comma if expect_close_paren => break,
+                TokenType::Semicolon => unreachable!("statements are split at semicolons"),
+                TokenType::CloseParen => {
+                    // a stray close paren is skipped without standing for a missing operand
+                    self.fail(unmatched_paren(&tokens[i]))?;
+                    self.skipped = Some(i);
+                    i += 1;
+                    if i == tokens.len() {
+                        curr_lhs = self.reduce(&mut stack, curr_lhs, true)?;
+                    }
+                    continue;
+                }
+                TokenType::Comma | TokenType::Equals => {
+                    self.fail(match tokens[i].token_type {
+                        TokenType::Comma => unexpected_comma(&tokens[i]),
+                        _ => Error::from_code(
+                            ErrorCode::UnexpectedEquals,
+                            tokens[i].start,
+                            tokens[i].end,
+                        ),
+                    })?;
+                    self.skipped = Some(i);
+                    let rhs = Expression::new_error(tokens[i].start, tokens[i].end);
+                    (Operation::Multiply, Node::leaf(rhs), i + 1)
+                }
+            };
+            i = next_i;
+
+            stack.push((curr_op, curr_rhs));
+
+            let at_end = if i == tokens.len() {
+                true
+            } else if expect_close_paren {
+                tokens[i].token_type == TokenType::CloseParen
+                    || tokens[i].token_type == TokenType::Comma
+            } else {
+                false
+            };
+
+            curr_lhs = self.reduce(&mut stack, curr_lhs, at_end)?;
+        }
+
+        if let Some((last_op, last_rhs)) = stack.pop() {
+            curr_lhs = self.op(curr_lhs, last_op, last_rhs)?;
+        }
+
+        Ok((curr_lhs, i))
+    }
+
+    // a statement before a semicolon must assign a value to a single name, or define a function
+    // of distinct parameters which is not built in and does not call itself
+    fn parse_statement(
+        &mut self,
+        statement: &'a [Token],
+        semicolon: Option<&Token>,
+    ) -> Result<Option<(Statement, usize, Node)>, Error> {
+        let (head, equals) = if let Some((name, parameters, equals)) = definition_head(statement) {
+            if builtin(name).is_some() {
+                let code = ErrorCode::RedefinedBuiltin(name.to_string());
+                self.fail(Error::from_code(code, statement[0].start, statement[0].end))?;
+            }
+            let mut names: Vec<String> = vec![];
+            let parameters = parameters
+                .iter()
+                .step_by(2)
+                .filter_map(|token| Some((name_of(token)?, token)));
+            for (parameter, token) in parameters {
+                if names.iter().any(|name| name == parameter) {
+                    let code = ErrorCode::DuplicateParameter(parameter.to_string());
+                    self.fail(Error::from_code(code, token.start, token.end))?;
+                } else {
+                    names.push(parameter.to_string());
+                }
+            }
+            (Statement::Definition(name.to_string(), names), equals)
+        } else {
+            match statement {
+                [name, equals, ..] if equals.token_type == TokenType::Equals => match name_of(name)
+                {
+                    Some(name) => (Statement::Assignment(name.to_string()), 1),
+                    None => return self.expected_assignment(statement, semicolon),
+                },
+                _ => return self.expected_assignment(statement, semicolon),
+            }
+        };
+        self.tokens = statement;
+        self.skipped = None;
+        let (value, _) = self.parse_tokens(equals + 1, false)?;
+        if let Statement::Definition(name, parameters) = &head {
+            if let Some(call) = find_call(&value.tree, name) {
+                let code = ErrorCode::RecursiveDefinition(name.to_string());
+                self.fail(Error::from_code(code, call.start, call.end))?;
             }
+            self.definitions.push((name.to_string(), parameters.len()));
+        }
+        Ok(Some((head, statement[0].start, value)))
+    }
+
+    fn expected_assignment(
+        &mut self,
+        statement: &[Token],
+        semicolon: Option<&Token>,
+    ) -> Result<Option<(Statement, usize, Node)>, Error> {
+        let (start, end) = match (statement, semicolon) {
+            ([], Some(semicolon)) => (semicolon.start, semicolon.end),
+            ([], None) => (0, 1),
+            ([first, .., last], _) => (first.start, last.end),
+            ([only], _) => (only.start, only.end),
         };
+        self.fail(Error::from_code(ErrorCode::ExpectedAssignment, start, end))?;
+        Ok(None)
+    }
 
-        stack.push((curr_op, curr_rhs));
+    // a program is a sequence of assignments and definitions, each followed by a semicolon, and then
+    // the expression which gives its result; each statement is parsed on its own, so a semicolon
+    // always ends one
+    fn parse_program(&mut self) -> Result<Node, Error> {
+        let tokens = self.tokens;
+        let mut statements = vec![];
+        let mut start = 0;
+        while let Some(len) = tokens[start..]
+            .iter()
+            .position(|token| token.token_type == TokenType::Semicolon)
+        {
+            let (statement, semicolon) = (&tokens[start..start + len], &tokens[start + len]);
+            if let Some(parsed) = self.parse_statement(statement, Some(semicolon))? {
+                statements.push(parsed);
+            }
+            start += len + 1;
+        }
 
-        let at_end = if i == tokens.len() {
-            true
-        } else if expect_close_paren {
-            tokens[i].token_type == TokenType::CloseParen
+        let mut result = if start > 0 && start == tokens.len() {
+            let err = expected_expression(tokens, start);
+            let (start, end) = (err.start, err.end);
+            self.fail(err)?;
+            Node::leaf(Expression::new_error(start, end))
         } else {
-            false
+            self.tokens = &tokens[start..];
+            self.skipped = None;
+            self.parse_tokens(0, false)?.0
         };
+        self.tokens = tokens;
+
+        for (head, start, value) in statements.into_iter().rev() {
+            let height = 1 + value.height.max(result.height);
+            let size = 1 + value.size + result.size;
+            let end = result.tree.end;
+            let tree = match head {
+                Statement::Assignment(name) => {
+                    Expression::new_let(&name, value.tree, result.tree, start, end)
+                }
+                Statement::Definition(name, parameters) => {
+                    let definition = Definition {
+                        name,
+                        parameters,
+                        body: value.tree,
+                    };
+                    Expression::new_define(definition, result.tree, start, end)
+                }
+            };
+            result = self.branch(tree, height, size)?;
+        }
+        Ok(result)
+    }
 
+    // combines the operands on the stack for as long as precedence allows; this is kept out of
+    // `parse_tokens` so that its locals do not add to each level of recursion
+    fn reduce(
+        &self,
+        stack: &mut Vec<(Operation, Node)>,
+        mut curr_lhs: Node,
+        at_end: bool,
+    ) -> Result<Node, Error> {
         while let Some((curr_op, curr_rhs)) = stack.pop() {
             if let Some((prev_op, prev_rhs)) = stack.pop() {
-                let prev_precedence_wins = precedence(&prev_op) < precedence(&curr_op);
+                let prev_precedence_wins = precedence(&prev_op) < precedence(&curr_op)
+                    || (prev_op == curr_op && right_associative(&curr_op));
                 if prev_precedence_wins && !at_end {
                     stack.push((prev_op, prev_rhs));
                     stack.push((curr_op, curr_rhs));
@@ -230,18 +1023,15 @@ fn parse_tokens(
                 } else if let Some((prev_prev_op, prev_prev_rhs)) = stack.pop() {
                     if prev_precedence_wins {
                         stack.push((prev_prev_op, prev_prev_rhs));
-                        stack.push((prev_op, Expression::new_op(prev_rhs, curr_op, curr_rhs)));
+                        stack.push((prev_op, self.op(prev_rhs, curr_op, curr_rhs)?));
                     } else {
-                        stack.push((
-                            prev_prev_op,
-                            Expression::new_op(prev_prev_rhs, prev_op, prev_rhs),
-                        ));
+                        stack.push((prev_prev_op, self.op(prev_prev_rhs, prev_op, prev_rhs)?));
                         stack.push((curr_op, curr_rhs));
                     }
                 } else if prev_precedence_wins {
-                    stack.push((prev_op, Expression::new_op(prev_rhs, curr_op, curr_rhs)));
+                    stack.push((prev_op, self.op(prev_rhs, curr_op, curr_rhs)?));
                 } else {
-                    curr_lhs = Expression::new_op(curr_lhs, prev_op, prev_rhs);
+                    curr_lhs = self.op(curr_lhs, prev_op, prev_rhs)?;
                     stack.push((curr_op, curr_rhs));
                 }
             } else {
@@ -249,32 +1039,184 @@ fn parse_tokens(
                 break;
             }
         }
+        Ok(curr_lhs)
     }
+}
+
+// the names of the functions which a program defines, other than those which were already lexed as
+// functions; only the start of each statement is looked at, so no parse is needed
+fn defined_functions(tokens: &[Token]) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let starts = std::iter::once(0).chain(
+        tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| token.token_type == TokenType::Semicolon)
+            .map(|(i, _)| i + 1),
+    );
+    for start in starts {
+        let statement = tokens[start..]
+            .split(|token| token.token_type == TokenType::Semicolon)
+            .next()
+            .unwrap_or_default();
+        let close_paren = statement
+            .iter()
+            .position(|token| token.token_type == TokenType::CloseParen);
+        match (statement, close_paren) {
+            ([name, open_paren, ..], Some(close_paren))
+                if open_paren.token_type == TokenType::OpenParen
+                    && statement
+                        .get(close_paren + 1)
+                        .map(|token| &token.token_type)
+                        == Some(&TokenType::Equals) =>
+            {
+                if let Some(name) = name_of(name) {
+                    if !names.iter().any(|defined| defined == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
 
-    if let Some((last_op, last_rhs)) = stack.pop() {
-        curr_lhs = Expression::new_op(curr_lhs, last_op, last_rhs);
+// calls to functions defined in the text can only be lexed once the definitions are known, so the
+// text is lexed a second time if it defines any
+fn with_definitions<'o>(tokens: &[Token], options: &'o ParseOptions) -> Cow<'o, ParseOptions> {
+    let names = defined_functions(tokens);
+    if names.is_empty() {
+        return Cow::Borrowed(options);
     }
+    let mut options = options.clone();
+    options.functions.extend(names);
+    Cow::Owned(options)
+}
+
+// the length is checked before lexing, so that no work is done on input which is too long
+fn check_length(text: &str, limits: &Limits) -> Result<(), Error> {
+    if text.len() > limits.max_length {
+        let code = ErrorCode::TooLong {
+            length: text.len(),
+            limit: limits.max_length,
+        };
+        return Err(Error::from_code(code, limits.max_length, text.len()));
+    }
+    Ok(())
+}
 
-    Ok((curr_lhs, i))
+// the span covers the tokens past the limit
+fn check_tokens(tokens: &[Token], limits: &Limits) -> Result<(), Error> {
+    if tokens.len() > limits.max_tokens {
+        let code = ErrorCode::TooManyTokens {
+            count: tokens.len(),
+            limit: limits.max_tokens,
+        };
+        let (start, end) = (
+            tokens[limits.max_tokens].start,
+            tokens[tokens.len() - 1].end,
+        );
+        return Err(Error::from_code(code, start, end));
+    }
+    Ok(())
 }
 
 /// Parses a Serious expression into an abstract syntax tree.
 pub fn parse(text: &str) -> Result<Expression, Error> {
-    let tokens = lex(text)?;
-    Ok(parse_tokens(&tokens, 0, false)?.0)
+    parse_with(text, &ParseOptions::default())
+}
+
+/// Parses a Serious expression into an abstract syntax tree, given [`ParseOptions`](crate::parser::ParseOptions).
+pub fn parse_with(text: &str, options: &ParseOptions) -> Result<Expression, Error> {
+    check_length(text, &options.limits)?;
+    let tokens = lex(text, options)?;
+    let tokens = match with_definitions(&tokens, options) {
+        Cow::Owned(options) => lex(text, &options)?,
+        Cow::Borrowed(_) => tokens,
+    };
+    check_tokens(&tokens, &options.limits)?;
+    let node = ParserState::new(&tokens, false, options).parse_program()?;
+    Ok(node.tree)
+}
+
+// parses the text of a single definition for a registry, which may call the functions in `options`
+pub(crate) fn parse_definition(text: &str, options: &ParseOptions) -> Result<Definition, Error> {
+    check_length(text, &options.limits)?;
+    let tokens = lex(text, options)?;
+    let options = with_definitions(&tokens, options);
+    let tokens = lex(text, &options)?;
+    check_tokens(&tokens, &options.limits)?;
+    let semicolon = tokens
+        .iter()
+        .any(|token| token.token_type == TokenType::Semicolon);
+    if semicolon || definition_head(&tokens).is_none() {
+        let end = tokens.last().map_or(text.len(), |token| token.end);
+        return Err(Error::from_code(ErrorCode::ExpectedDefinition, 0, end));
+    }
+    let mut state = ParserState::new(&tokens, false, &options);
+    match state.parse_statement(&tokens, None)? {
+        Some((Statement::Definition(name, parameters), _, body)) => Ok(Definition {
+            name,
+            parameters,
+            body: body.tree,
+        }),
+        _ => unreachable!("the statement starts with a definition"),
+    }
+}
+
+/// Parses a Serious expression without stopping at the first error, given [`ParseOptions`](crate::parser::ParseOptions).
+///
+/// Returns every error in the text, ordered by position, along with a partial tree in which the parts which failed to parse
+/// are [`Error`](crate::parser::ExpressionData::Error) nodes. Parsing resumes at the next operator, parenthesis or statement after each error,
+/// so the errors are the same as [`parse_with`](crate::parser::parse_with) would report after each earlier one was fixed.
+/// If the list of errors is empty, the tree is the same as the one [`parse_with`](crate::parser::parse_with) returns.
+///
+/// ```
+/// use serious::parser::{parse_recovering, ParseOptions};
+///
+/// let (tree, errors) = parse_recovering("2 + * x) - (3 $ y", &ParseOptions::default());
+/// let codes: Vec<&str> = errors.iter().map(|err| err.code.id()).collect();
+///
+/// assert_eq!(codes, ["expected_expression", "unmatched_paren", "unmatched_paren", "invalid_character"]);
+/// assert_eq!(tree.to_string(), "2 + ?*x - 3*?*y");
+/// ```
+pub fn parse_recovering(text: &str, options: &ParseOptions) -> (Expression, Vec<Error>) {
+    if let Err(err) = check_length(text, &options.limits) {
+        return (Expression::new_error(0, text.len()), vec![err]);
+    }
+    let (tokens, lex_errors) = lex_recovering(text, options);
+    let (tokens, lex_errors) = match with_definitions(&tokens, options) {
+        Cow::Owned(options) => lex_recovering(text, &options),
+        Cow::Borrowed(_) => (tokens, lex_errors),
+    };
+    if let Err(err) = check_tokens(&tokens, &options.limits) {
+        return (Expression::new_error(0, text.len()), vec![err]);
+    }
+    let mut state = ParserState::new(&tokens, true, options);
+    state.errors = lex_errors;
+    let tree = match state.parse_program() {
+        Ok(node) => node.tree,
+        Err(err) => {
+            let tree = Expression::new_error(err.start, err.end);
+            state.errors.push(err);
+            tree
+        }
+    };
+    let mut errors = state.errors;
+    errors.sort_by_key(|err| err.start);
+    (tree, errors)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorType;
 
     #[test]
     fn empty() {
         let err = parse("").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(ErrorType::BadParse, "expected token".to_string(), 0, 1)
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::EmptyInput, 0, 1));
     }
 
     #[test]
@@ -282,12 +1224,7 @@ mod tests {
         let err = parse("2*0.2.3").unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::BadParse,
-                "invalid float literal".to_string(),
-                2,
-                7
-            )
+            Error::from_code(ErrorCode::InvalidNumber("0.2.3".to_string()), 2, 7)
         );
     }
 
@@ -423,13 +1360,13 @@ mod tests {
                     Expression::new_id('x', 4, 5),
                     Operation::Multiply,
                     Expression::new_op(
+                        Expression::new_id('y', 5, 6),
+                        Operation::Exponentiate,
                         Expression::new_op(
-                            Expression::new_id('y', 5, 6),
+                            Expression::new_const(2., 7, 8),
                             Operation::Exponentiate,
-                            Expression::new_const(2., 7, 8)
-                        ),
-                        Operation::Exponentiate,
-                        Expression::new_const(3., 9, 10)
+                            Expression::new_const(3., 9, 10)
+                        )
                     )
                 )
             )
@@ -437,20 +1374,74 @@ mod tests {
     }
 
     #[test]
-    fn simple_parens() {
-        let tree = parse("2*( x + 0.4 )").unwrap();
+    fn right_associate_exponent() {
+        let tree = parse("2^3^2^x / 4").unwrap();
         assert_eq!(
             tree,
             Expression::new_op(
-                Expression::new_const(2., 0, 1),
-                Operation::Multiply,
                 Expression::new_op(
-                    Expression::new_id('x', 4, 5),
-                    Operation::Add,
-                    Expression::new_const(0.4, 8, 11)
-                )
-                .with_bounds(2, 13)
-            )
+                    Expression::new_const(2., 0, 1),
+                    Operation::Exponentiate,
+                    Expression::new_op(
+                        Expression::new_const(3., 2, 3),
+                        Operation::Exponentiate,
+                        Expression::new_op(
+                            Expression::new_const(2., 4, 5),
+                            Operation::Exponentiate,
+                            Expression::new_id('x', 6, 7)
+                        )
+                    )
+                ),
+                Operation::Divide,
+                Expression::new_const(4., 10, 11)
+            )
+        );
+    }
+
+    #[test]
+    fn right_associate_exponent_in_product() {
+        let tree = parse("a^b^c d").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_op(
+                    Expression::new_id('a', 0, 1),
+                    Operation::Exponentiate,
+                    Expression::new_op(
+                        Expression::new_id('b', 2, 3),
+                        Operation::Exponentiate,
+                        Expression::new_id('c', 4, 5)
+                    )
+                ),
+                Operation::Multiply,
+                Expression::new_id('d', 6, 7)
+            )
+        );
+    }
+
+    #[test]
+    fn eq_ignoring_spans() {
+        let tree = parse("2(x + sin(y))").unwrap();
+        assert!(tree.eq_ignoring_spans(&parse("2 * ( x+sin( y ) )").unwrap()));
+        assert!(!tree.eq_ignoring_spans(&parse("2(x + cos(y))").unwrap()));
+        assert!(!tree.eq_ignoring_spans(&parse("2(x - sin(y))").unwrap()));
+    }
+
+    #[test]
+    fn simple_parens() {
+        let tree = parse("2*( x + 0.4 )").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_const(2., 0, 1),
+                Operation::Multiply,
+                Expression::new_op(
+                    Expression::new_id('x', 4, 5),
+                    Operation::Add,
+                    Expression::new_const(0.4, 8, 11)
+                )
+                .with_bounds(2, 13)
+            )
         );
     }
 
@@ -489,29 +1480,13 @@ mod tests {
     #[test]
     fn extra_open_paren() {
         let err = parse("(1*(2+3)").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(
-                ErrorType::BadParse,
-                "failed to match paren".to_string(),
-                0,
-                1
-            )
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 0, 1));
     }
 
     #[test]
     fn extra_close_paren() {
         let err = parse("3 + ((4*1)^2+(2+3))) + 6").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(
-                ErrorType::BadParse,
-                "expected expression".to_string(),
-                19,
-                20
-            )
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 19, 20));
     }
 
     #[test]
@@ -540,36 +1515,20 @@ mod tests {
         let err = parse("x3").unwrap_err();
         assert_eq!(
             err,
-            Error::new(
-                ErrorType::BadParse,
-                "constant on RHS of implicit multiplication".to_string(),
-                1,
-                2
-            )
+            Error::from_code(ErrorCode::ImplicitMultiplicationByConstant(3.), 1, 2)
         );
     }
 
     #[test]
     fn empty_paren() {
         let err = parse("x()").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(ErrorType::BadParse, "expected expression".to_string(), 2, 3)
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::ExpectedExpression, 2, 3));
     }
 
     #[test]
     fn just_paren() {
         let err = parse("(").unwrap_err();
-        assert_eq!(
-            err,
-            Error::new(
-                ErrorType::BadParse,
-                "failed to match paren".to_string(),
-                0,
-                1
-            )
-        );
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 0, 1));
     }
 
     #[test]
@@ -704,13 +1663,9 @@ mod tests {
         assert_eq!(
             tree,
             Expression::new_op(
-                Expression::new_const(0., 0, 0),
-                Operation::Subtract,
-                Expression::new_op(
-                    Expression::new_const(2., 1, 2),
-                    Operation::Multiply,
-                    Expression::new_id('x', 2, 3)
-                )
+                Expression::new_neg(Expression::new_const(2., 1, 2), 0, 2),
+                Operation::Multiply,
+                Expression::new_id('x', 2, 3)
             )
         );
     }
@@ -722,16 +1677,12 @@ mod tests {
             tree,
             Expression::new_op(
                 Expression::new_op(
-                    Expression::new_const(0., 0, 0),
-                    Operation::Subtract,
+                    Expression::new_neg(Expression::new_const(2., 1, 2), 0, 2),
+                    Operation::Multiply,
                     Expression::new_op(
-                        Expression::new_const(2., 1, 2),
-                        Operation::Multiply,
-                        Expression::new_op(
-                            Expression::new_id('x', 2, 3),
-                            Operation::Exponentiate,
-                            Expression::new_const(2., 4, 5),
-                        )
+                        Expression::new_id('x', 2, 3),
+                        Operation::Exponentiate,
+                        Expression::new_const(2., 4, 5),
                     )
                 ),
                 Operation::Subtract,
@@ -741,19 +1692,79 @@ mod tests {
     }
 
     #[test]
-    fn unary_minus_error() {
-        let err = parse("3*-2x").unwrap_err();
+    fn unary_minus_exponent() {
+        let tree = parse("-x^2").unwrap();
         assert_eq!(
-            err,
-            Error::new(
-                ErrorType::BadParse,
-                "expected expression; wrap in parens for unary minus".to_string(),
-                2,
-                3
+            tree,
+            Expression::new_neg(
+                Expression::new_op(
+                    Expression::new_id('x', 1, 2),
+                    Operation::Exponentiate,
+                    Expression::new_const(2., 3, 4)
+                ),
+                0,
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn unary_minus_operand() {
+        let tree = parse("3*-2x").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_op(
+                    Expression::new_const(3., 0, 1),
+                    Operation::Multiply,
+                    Expression::new_neg(Expression::new_const(2., 3, 4), 2, 4)
+                ),
+                Operation::Multiply,
+                Expression::new_id('x', 4, 5)
+            )
+        );
+    }
+
+    #[test]
+    fn unary_minus_in_exponent() {
+        let tree = parse("x^-y^2").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_id('x', 0, 1),
+                Operation::Exponentiate,
+                Expression::new_neg(
+                    Expression::new_op(
+                        Expression::new_id('y', 3, 4),
+                        Operation::Exponentiate,
+                        Expression::new_const(2., 5, 6)
+                    ),
+                    2,
+                    6
+                )
             )
         );
     }
 
+    #[test]
+    fn repeated_prefix() {
+        let tree = parse("a - -+b").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_id('a', 0, 1),
+                Operation::Subtract,
+                Expression::new_neg(Expression::new_id('b', 6, 7).with_bounds(5, 7), 4, 7)
+            )
+        );
+    }
+
+    #[test]
+    fn unary_minus_missing_operand() {
+        let err = parse("2*-").unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::ExpectedExpression, 3, 4));
+    }
+
     #[test]
     fn unary_minus_nested() {
         let tree = parse("3*(-2xy)").unwrap();
@@ -763,17 +1774,13 @@ mod tests {
                 Expression::new_const(3., 0, 1),
                 Operation::Multiply,
                 Expression::new_op(
-                    Expression::new_const(0., 3, 3),
-                    Operation::Subtract,
                     Expression::new_op(
-                        Expression::new_op(
-                            Expression::new_const(2., 4, 5),
-                            Operation::Multiply,
-                            Expression::new_id('x', 5, 6)
-                        ),
+                        Expression::new_neg(Expression::new_const(2., 4, 5), 3, 5),
                         Operation::Multiply,
-                        Expression::new_id('y', 6, 7)
-                    )
+                        Expression::new_id('x', 5, 6)
+                    ),
+                    Operation::Multiply,
+                    Expression::new_id('y', 6, 7)
                 )
                 .with_bounds(2, 8)
             )
@@ -812,4 +1819,752 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn simple_call() {
+        let tree = parse("sqrt(x + 1)").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_call(
+                "sqrt",
+                vec![Expression::new_op(
+                    Expression::new_id('x', 5, 6),
+                    Operation::Add,
+                    Expression::new_const(1., 9, 10)
+                )],
+                0,
+                11
+            )
+        );
+    }
+
+    #[test]
+    fn implicit_mult_call() {
+        let tree = parse("2cos(t)^2").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_const(2., 0, 1),
+                Operation::Multiply,
+                Expression::new_op(
+                    Expression::new_call("cos", vec![Expression::new_id('t', 5, 6)], 1, 7),
+                    Operation::Exponentiate,
+                    Expression::new_const(2., 8, 9)
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn nested_call() {
+        let tree = parse("ln(exp((y)))").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_call(
+                "ln",
+                vec![Expression::new_call(
+                    "exp",
+                    vec![Expression::new_id('y', 8, 9).with_bounds(7, 10)],
+                    3,
+                    11
+                )],
+                0,
+                12
+            )
+        );
+    }
+
+    #[test]
+    fn call_wrong_arity() {
+        let err = parse("1 + sin(x, y)").unwrap_err();
+        assert_eq!(
+            err,
+            Error::from_code(
+                ErrorCode::WrongArgumentCount {
+                    function: "sin".to_string(),
+                    expected: 1,
+                    supplied: 2,
+                },
+                4,
+                13
+            )
+        );
+    }
+
+    #[test]
+    fn replaced_builtin_arity() {
+        let options = ParseOptions {
+            functions: vec!["sin".to_string()],
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("1 + sin(x, y)", &options).unwrap();
+        assert_eq!(tree.to_string(), "1 + sin(x, y)");
+    }
+
+    #[test]
+    fn call_no_args() {
+        let err = parse("sin()").unwrap_err();
+        assert_eq!(err, wrong_argument_count("sin", 1, 0, 0, 5));
+        let err = parse("sin(,)").unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::ExpectedExpression, 4, 5));
+    }
+
+    #[test]
+    fn call_unmatched_paren() {
+        let err = parse("cos(2x").unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 3, 4));
+    }
+
+    #[test]
+    fn comma_outside_call() {
+        let err = parse("(1, 2)").unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::UnexpectedComma, 2, 3));
+    }
+
+    #[test]
+    fn trailing_open_paren() {
+        let err = parse("x(").unwrap_err();
+        assert_eq!(err, Error::from_code(ErrorCode::UnmatchedParen, 1, 2));
+    }
+
+    #[test]
+    fn word_identifiers() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("2rate time + x_1", &options).unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_op(
+                    Expression::new_op(
+                        Expression::new_const(2., 0, 1),
+                        Operation::Multiply,
+                        Expression::new_id("rate", 1, 5)
+                    ),
+                    Operation::Multiply,
+                    Expression::new_id("time", 6, 10)
+                ),
+                Operation::Add,
+                Expression::new_id("x_1", 13, 16)
+            )
+        );
+    }
+
+    #[test]
+    fn declared_identifiers() {
+        let options = ParseOptions {
+            names: vec!["dt".to_string()],
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("vdt", &options).unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_id('v', 0, 1),
+                Operation::Multiply,
+                Expression::new_id("dt", 1, 3)
+            )
+        );
+    }
+
+    #[test]
+    fn named_constants() {
+        let tree = parse("2pi r").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_op(
+                    Expression::new_const(2., 0, 1),
+                    Operation::Multiply,
+                    Expression::new_named_const("pi", 1, 3)
+                ),
+                Operation::Multiply,
+                Expression::new_id('r', 4, 5)
+            )
+        );
+    }
+
+    #[test]
+    fn constant_on_rhs_of_implicit_mult() {
+        let tree = parse("xe^2").unwrap();
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_id('x', 0, 1),
+                Operation::Multiply,
+                Expression::new_op(
+                    Expression::new_named_const("e", 1, 2),
+                    Operation::Exponentiate,
+                    Expression::new_const(2., 3, 4)
+                )
+            )
+        );
+    }
+
+    fn recover(text: &str) -> (Expression, Vec<Error>) {
+        parse_recovering(text, &ParseOptions::default())
+    }
+
+    fn spans(errors: &[Error]) -> Vec<(usize, usize)> {
+        errors.iter().map(|err| (err.start, err.end)).collect()
+    }
+
+    #[test]
+    fn recovering_valid() {
+        for text in [
+            "34.2x + y^2(-2x^3 + 1)/5.2",
+            "-(x - 2y)^3/(4 - -x)",
+            "sin(cos(x))^2",
+        ]
+        .iter()
+        {
+            let (tree, errors) = recover(text);
+            assert!(errors.is_empty());
+            assert_eq!(tree, parse(text).unwrap());
+        }
+    }
+
+    #[test]
+    fn recovering_first_error_matches_parse() {
+        for text in [
+            "2 + * 3 - / 4",
+            "(1 + 2",
+            "2x 3 + 4y 5",
+            "x, y, z",
+            "1 $ 2 # 3",
+            "sin(1, 2) + )",
+        ]
+        .iter()
+        {
+            let (_, errors) = recover(text);
+            assert_eq!(errors[0], parse(text).unwrap_err());
+        }
+    }
+
+    #[test]
+    fn recovering_missing_operands() {
+        let (tree, errors) = recover("2 + * 3 - / 4 +");
+        assert_eq!(spans(&errors), [(4, 5), (10, 11), (15, 16)]);
+        assert!(errors
+            .iter()
+            .all(|err| err.code == ErrorCode::ExpectedExpression));
+        assert!(tree.eq_ignoring_spans(&Expression::new_op(
+            Expression::new_op(
+                Expression::new_op(
+                    Expression::new_const(2., 0, 0),
+                    Operation::Add,
+                    Expression::new_op(
+                        Expression::new_error(0, 0),
+                        Operation::Multiply,
+                        Expression::new_const(3., 0, 0)
+                    )
+                ),
+                Operation::Subtract,
+                Expression::new_op(
+                    Expression::new_error(0, 0),
+                    Operation::Divide,
+                    Expression::new_const(4., 0, 0)
+                )
+            ),
+            Operation::Add,
+            Expression::new_error(0, 0)
+        )));
+    }
+
+    #[test]
+    fn recovering_parens() {
+        let (tree, errors) = recover("2(x + 1");
+        assert_eq!(spans(&errors), [(1, 2)]);
+        assert_eq!(errors[0].code, ErrorCode::UnmatchedParen);
+        assert!(tree.eq_ignoring_spans(&parse("2(x + 1)").unwrap()));
+        assert_eq!(tree.end, 7);
+
+        let (_, errors) = recover("(x + ) + (y");
+        assert_eq!(spans(&errors), [(5, 6), (9, 10)]);
+
+        let (tree, errors) = recover("x) + y)");
+        assert_eq!(spans(&errors), [(1, 2), (6, 7)]);
+        assert!(errors
+            .iter()
+            .all(|error| error.code == ErrorCode::UnmatchedParen));
+        assert_eq!(tree.to_string(), "x + y");
+
+        let (tree, errors) = recover("(1 + ) * (2 * ) + 3)");
+        assert_eq!(spans(&errors), [(5, 6), (14, 15), (19, 20)]);
+        assert_eq!(errors[2].code, ErrorCode::UnmatchedParen);
+        assert_eq!(tree.to_string(), "(1 + ?)*(2*?) + 3");
+    }
+
+    #[test]
+    fn recovering_commas() {
+        let (tree, errors) = recover("(1, 2 +) + 3, 4");
+        assert_eq!(spans(&errors), [(2, 3), (7, 8), (12, 13)]);
+        assert_eq!(tree.to_string(), "? + 3*?*4");
+    }
+
+    #[test]
+    fn recovering_calls() {
+        let (tree, errors) = recover("sin(1, 2) + cos(* x");
+        assert_eq!(spans(&errors), [(0, 9), (15, 16), (16, 17)]);
+        assert_eq!(
+            errors[0].code,
+            ErrorCode::WrongArgumentCount {
+                function: "sin".to_string(),
+                expected: 1,
+                supplied: 2
+            }
+        );
+        assert_eq!(tree.to_string(), "sin(1, 2) + cos(?*x)");
+    }
+
+    #[test]
+    fn recovering_constants() {
+        let (tree, errors) = recover("2x 3 + 4y 5");
+        assert_eq!(spans(&errors), [(3, 4), (10, 11)]);
+        assert_eq!(tree.to_string(), "2x*? + 4y*?");
+    }
+
+    #[test]
+    fn recovering_lex_errors() {
+        let (tree, errors) = recover("1 $ 2 # 3.4.5");
+        assert_eq!(spans(&errors), [(2, 3), (6, 7), (8, 13)]);
+        assert_eq!(
+            errors[2].code,
+            ErrorCode::InvalidNumber("3.4.5".to_string())
+        );
+        assert_eq!(tree.to_string(), "1*?*2*?*?");
+
+        let (tree, errors) = recover("");
+        assert_eq!(spans(&errors), [(0, 1)]);
+        assert_eq!(errors[0].code, ErrorCode::EmptyInput);
+        assert_eq!(tree.data, ExpressionData::Error);
+    }
+
+    fn parse_unicode(text: &str) -> Expression {
+        let options = ParseOptions {
+            unicode: true,
+            ..ParseOptions::default()
+        };
+        parse_with(text, &options).unwrap()
+    }
+
+    #[test]
+    fn unicode_operators() {
+        let tree = parse_unicode("2×3·x÷5 − −1");
+        assert!(tree.eq_ignoring_spans(&parse("2*3*x/5 - -1").unwrap()));
+        assert_eq!((tree.start, tree.end), (0, "2×3·x÷5 − −1".len()));
+    }
+
+    #[test]
+    fn unicode_superscripts() {
+        assert!(parse_unicode("2x²y³").eq_ignoring_spans(&parse("2x^2y^3").unwrap()));
+        assert!(parse_unicode("x⁻¹²").eq_ignoring_spans(&parse("x^-12").unwrap()));
+        assert!(parse_unicode("−x²").eq_ignoring_spans(&parse("-x^2").unwrap()));
+        assert!(parse_unicode("(x + 1)²").eq_ignoring_spans(&parse("(x + 1)^2").unwrap()));
+
+        let tree = parse_unicode("x²");
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_id('x', 0, 1),
+                Operation::Exponentiate,
+                Expression::new_const(2., 1, 3)
+            )
+        );
+    }
+
+    #[test]
+    fn unicode_roots() {
+        assert!(parse_unicode("√x²").eq_ignoring_spans(&parse("sqrt(x)^2").unwrap()));
+        assert!(parse_unicode("2√(x + 1)").eq_ignoring_spans(&parse("2sqrt(x + 1)").unwrap()));
+        assert!(parse_unicode("∛−8").eq_ignoring_spans(&parse("cbrt(-8)").unwrap()));
+        assert!(parse_unicode("√√x").eq_ignoring_spans(&parse("sqrt(sqrt(x))").unwrap()));
+
+        let tree = parse_unicode("√x");
+        assert_eq!(
+            tree,
+            Expression::new_call("sqrt", vec![Expression::new_id('x', 3, 4)], 0, 4)
+        );
+    }
+
+    #[test]
+    fn unicode_constants() {
+        assert!(parse_unicode("2πr + τ·φ").eq_ignoring_spans(&parse("2pi r + tau*phi").unwrap()));
+    }
+
+    fn limited(limits: Limits) -> ParseOptions {
+        ParseOptions {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn deep_parens() {
+        let options = limited(Limits::unlimited());
+        let text = format!("{}x{}", "(".repeat(100_000), ")".repeat(100_000));
+        let err = parse_with(&text, &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooDeep(128));
+        assert_eq!((err.start, err.end), (128, 129));
+        let (_, errors) = parse_recovering(&text, &options);
+        assert_eq!(errors.last().unwrap().code, ErrorCode::TooDeep(128));
+
+        let text = format!("{}x{}", "(".repeat(100), ")".repeat(100));
+        assert!(parse(&text).is_ok());
+    }
+
+    #[test]
+    fn deep_prefixes_and_calls() {
+        let options = limited(Limits::unlimited());
+        let err = parse_with(&"-".repeat(10_000), &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooDeep(128));
+        let text = format!("{}x{}", "sin(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(
+            parse_with(&text, &options).unwrap_err().code,
+            ErrorCode::TooDeep(128)
+        );
+        let text = format!("x{}", "^x".repeat(10_000));
+        assert_eq!(
+            parse_with(&text, &options).unwrap_err().code,
+            ErrorCode::TooDeep(128)
+        );
+    }
+
+    #[test]
+    fn long_chains() {
+        // a flat chain only nests its right-hand sides
+        let text = format!("x{}", "+x".repeat(199));
+        let tree = parse(&text).unwrap();
+        assert_eq!((tree.start, tree.end), (0, 399));
+        let text = format!("x{}", "*(y + 1)".repeat(200));
+        assert!(parse(&text).is_ok());
+        let text = format!("x{}", "+x".repeat(8000));
+        assert!(parse(&text).is_ok());
+
+        let err = parse(&format!("x{}", "^x".repeat(200))).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooDeep(128));
+    }
+
+    #[test]
+    fn custom_depth() {
+        let options = limited(Limits {
+            max_depth: 3,
+            ..Limits::default()
+        });
+        assert!(parse_with("(x + 1)*2 + 3*x - 4", &options).is_ok());
+        assert!(parse_with("((x + 1)*2)^2", &options).is_ok());
+        let err = parse_with("2^(2^(x + 1))", &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooDeep(3));
+        assert_eq!((err.start, err.end), (0, 13));
+    }
+
+    #[test]
+    fn too_long() {
+        let options = limited(Limits {
+            max_length: 8,
+            ..Limits::default()
+        });
+        assert!(parse_with("2x + 1", &options).is_ok());
+        let err = parse_with("2x + 1 + y", &options).unwrap_err();
+        assert_eq!(
+            err.code,
+            ErrorCode::TooLong {
+                length: 10,
+                limit: 8
+            }
+        );
+        assert_eq!((err.start, err.end), (8, 10));
+        let (tree, errors) = parse_recovering("2x + 1 + y", &options);
+        assert_eq!(tree, Expression::new_error(0, 10));
+        assert_eq!(errors, vec![err]);
+        assert!(parse(&"1".repeat(70_000)).is_err());
+    }
+
+    #[test]
+    fn too_many_tokens() {
+        let options = limited(Limits {
+            max_tokens: 5,
+            ..Limits::default()
+        });
+        assert!(parse_with("2x + 1", &options).is_ok());
+        let err = parse_with("2x + 1 - y/z", &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManyTokens { count: 8, limit: 5 });
+        assert_eq!(err.error_type(), ErrorType::LimitExceeded);
+        assert_eq!((err.start, err.end), (9, 12));
+        let (_, errors) = parse_recovering("2x + 1 - y/z", &options);
+        assert_eq!(errors, vec![err]);
+    }
+
+    #[test]
+    fn too_many_nodes() {
+        let options = limited(Limits {
+            max_nodes: 7,
+            ..Limits::default()
+        });
+        assert!(parse_with("sin(x) + 2x", &options).is_ok());
+        let err = parse_with("sin(x) + 2x + 1", &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManyNodes(7));
+        assert_eq!((err.start, err.end), (0, 15));
+        let err = parse_with("-(x + y)(x - y)", &options).unwrap_err();
+        assert_eq!(err.code, ErrorCode::TooManyNodes(7));
+    }
+
+    #[test]
+    fn free_identifiers() {
+        let tree = parse("sin(theta) + 2theta - e").unwrap();
+        let free = tree.free_identifiers();
+        assert_eq!(free.keys().collect::<Vec<_>>(), ["a", "h", "t"]);
+        assert_eq!(free["t"], [(4, 5), (7, 8), (14, 15), (17, 18)]);
+        assert!(!free.contains_key("e"));
+
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            builtin_constants: false,
+            ..Default::default()
+        };
+        let tree = parse_with("sin(theta) + 2theta - e", &options).unwrap();
+        let free = tree.free_identifiers();
+        assert_eq!(free.keys().collect::<Vec<_>>(), ["e", "theta"]);
+        assert_eq!(free["theta"], [(4, 9), (14, 19)]);
+    }
+
+    #[test]
+    fn no_free_identifiers() {
+        assert!(parse("2pi + sqrt(2)")
+            .unwrap()
+            .free_identifiers()
+            .is_empty());
+    }
+
+    #[test]
+    fn validate() {
+        let tree = parse("x^2 + y/z - x").unwrap();
+        assert_eq!(tree.validate(&["x", "y", "z"]), Ok(()));
+        let errors = tree.validate(&["y"]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                unbound_identifier("x", 0, 1),
+                unbound_identifier("z", 8, 9),
+                unbound_identifier("x", 12, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_partial_tree() {
+        let (tree, errors) = parse_recovering("x + * y", &ParseOptions::default());
+        assert_eq!(errors.len(), 1);
+        let errors = tree.validate(&["x"]).unwrap_err();
+        assert_eq!(errors, vec![unbound_identifier("y", 6, 7)]);
+    }
+
+    #[test]
+    fn assignments() {
+        let tree = parse("d = b^2 - 4a c; (-b + d^0.5)/(2a)").unwrap();
+        let expected = Expression::new_let(
+            "d",
+            parse("b^2 - 4a c").unwrap(),
+            parse("(-b + d^0.5)/(2a)").unwrap(),
+            0,
+            33,
+        );
+        assert!(tree.eq_ignoring_spans(&expected));
+        assert_eq!((tree.start, tree.end), (0, 33));
+        match tree.data {
+            ExpressionData::Let(_, value, body) => {
+                assert_eq!((value.start, value.end), (4, 14));
+                assert_eq!((body.start, body.end), (16, 33));
+            }
+            _ => panic!("expected an assignment"),
+        }
+    }
+
+    #[test]
+    fn chained_assignments() {
+        let tree = parse("a = 1; b = a + 1;e = 3; a b e").unwrap();
+        let expected = Expression::new_let(
+            "a",
+            parse("1").unwrap(),
+            Expression::new_let(
+                "b",
+                parse("a + 1").unwrap(),
+                Expression::new_let("e", parse("3").unwrap(), parse("a b e").unwrap(), 0, 0),
+                0,
+                0,
+            ),
+            0,
+            0,
+        );
+        assert!(tree.eq_ignoring_spans(&expected));
+    }
+
+    #[test]
+    fn bad_assignments() {
+        let err = |text| parse(text).unwrap_err();
+        assert_eq!(
+            err("2x; x"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 2)
+        );
+        assert_eq!(
+            err("ab = 1; x"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 6)
+        );
+        assert_eq!(
+            err("; x"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 1)
+        );
+        assert_eq!(
+            err("(d = 1; d)"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 6)
+        );
+        assert_eq!(
+            err("x + y = 2"),
+            Error::from_code(ErrorCode::UnexpectedEquals, 6, 7)
+        );
+        assert_eq!(
+            err("d = 3"),
+            Error::from_code(ErrorCode::UnexpectedEquals, 2, 3)
+        );
+        assert_eq!(
+            err("d = 1;"),
+            Error::from_code(ErrorCode::ExpectedExpression, 6, 7)
+        );
+        assert_eq!(
+            err("d = ; d"),
+            Error::from_code(ErrorCode::ExpectedExpression, 3, 4)
+        );
+        assert_eq!(
+            err("d = (1 = 2); d"),
+            Error::from_code(ErrorCode::UnexpectedEquals, 7, 8)
+        );
+    }
+
+    #[test]
+    fn recovering_assignments() {
+        let (tree, errors) = recover("2x; y = ; y = 3 +; y = 3");
+        assert_eq!(spans(&errors), [(0, 2), (7, 8), (17, 18), (21, 22)]);
+        let codes: Vec<&str> = errors.iter().map(|err| err.code.id()).collect();
+        assert_eq!(
+            codes,
+            [
+                "expected_assignment",
+                "expected_expression",
+                "expected_expression",
+                "unexpected_equals"
+            ]
+        );
+        assert_eq!(tree.to_string(), "y = ?; y = 3 + ?; y*?*3");
+    }
+
+    #[test]
+    fn assigned_names_are_not_free() {
+        let tree = parse("d = b^2 - 4a c; (-b + d^0.5)/(2a)").unwrap();
+        let free = tree.free_identifiers();
+        assert_eq!(free.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(tree.validate(&["a", "b", "c"]), Ok(()));
+
+        let tree = parse("x = 2x; x^2").unwrap();
+        assert_eq!(tree.free_identifiers()["x"], [(5, 6)]);
+    }
+
+    #[test]
+    fn definitions() {
+        let tree = parse("f(t) = 3t^2 + 1; f(x) + f(y)").unwrap();
+        assert_eq!((tree.start, tree.end), (0, 28));
+        match &tree.data {
+            ExpressionData::Define(definition, rest) => {
+                assert_eq!(definition.name, "f");
+                assert_eq!(definition.parameters, ["t"]);
+                assert!(definition
+                    .body
+                    .eq_ignoring_spans(&parse("3t^2 + 1").unwrap()));
+                assert_eq!((definition.body.start, definition.body.end), (7, 15));
+                let call =
+                    |arg| Expression::new_call("f", vec![Expression::new_id(arg, 0, 0)], 0, 0);
+                let expected = Expression::new_op(call("x"), Operation::Add, call("y"));
+                assert!(rest.eq_ignoring_spans(&expected));
+            }
+            _ => panic!("expected a definition"),
+        }
+    }
+
+    #[test]
+    fn definitions_with_parameters() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("area(w, h) = w h; d = 2; area(d, 3)", &options).unwrap();
+        match &tree.data {
+            ExpressionData::Define(definition, rest) => {
+                assert_eq!(definition.parameters, ["w", "h"]);
+                assert!(matches!(rest.data, ExpressionData::Let(_, _, _)));
+            }
+            _ => panic!("expected a definition"),
+        }
+
+        let options = ParseOptions {
+            functions: vec!["area".to_string()],
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("area(w, h) = w h; area(2, 3)", &options).unwrap();
+        assert_eq!(tree.to_string(), "area(w, h) = wh; area(2, 3)");
+    }
+
+    #[test]
+    fn bad_definitions() {
+        let err = |text| parse(text).unwrap_err();
+        assert_eq!(
+            err("f(x, x) = x; f(1, 2)"),
+            Error::from_code(ErrorCode::DuplicateParameter("x".to_string()), 5, 6)
+        );
+        assert_eq!(
+            err("f(t) = t f(t - 1); f(2)"),
+            Error::from_code(ErrorCode::RecursiveDefinition("f".to_string()), 9, 17)
+        );
+        assert_eq!(
+            err("sin(x) = x; sin(1)"),
+            Error::from_code(ErrorCode::RedefinedBuiltin("sin".to_string()), 0, 3)
+        );
+        assert_eq!(
+            err("f(t) = t; f(1, 2)"),
+            wrong_argument_count("f", 1, 2, 10, 17)
+        );
+        assert_eq!(
+            err("f(2) = 1; f(1)"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 8)
+        );
+        assert_eq!(
+            err("f() = 1; f(1)"),
+            Error::from_code(ErrorCode::ExpectedAssignment, 0, 7)
+        );
+        assert_eq!(
+            err("f(t) = t"),
+            Error::from_code(ErrorCode::UnexpectedEquals, 5, 6)
+        );
+    }
+
+    #[test]
+    fn recovering_definitions() {
+        let (tree, errors) = recover("f(x, x) = x; g(t) = g(t); f(1)");
+        let codes: Vec<&str> = errors.iter().map(|err| err.code.id()).collect();
+        assert_eq!(codes, ["duplicate_parameter", "recursive_definition"]);
+        assert_eq!(spans(&errors), [(5, 6), (20, 24)]);
+        assert_eq!(tree.to_string(), "f(x) = x; g(t) = g(t); f(1)");
+    }
+
+    #[test]
+    fn parameters_are_not_free() {
+        let tree = parse("f(t) = a t; f(x)").unwrap();
+        let free = tree.free_identifiers();
+        assert_eq!(free.keys().collect::<Vec<_>>(), ["a", "x"]);
+        assert_eq!(free["a"], [(7, 8)]);
+        assert!(tree.validate(&["a", "x"]).is_ok());
+
+        let tree = parse("t = 1; f(x) = t x; f(t)").unwrap();
+        assert!(tree.free_identifiers().is_empty());
+    }
 }
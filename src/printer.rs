@@ -0,0 +1,1049 @@
+use super::lexer::lex;
+use super::parser::{Expression, ExpressionData, Operation, ParseOptions};
+use std::fmt;
+
+/// Options for [`print`](crate::printer::print).
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrintOptions {
+    /// Whether to omit `*` where the parser would infer multiplication, e.g. `2x(y + 1)`; defaults to `true`.
+    /// An explicit `*` is still written where juxtaposition would not parse back to the same tree, e.g. `x*2`,
+    /// and a space where a power or a name of more than one letter would run into its neighbour, e.g. `pi r^2`.
+    pub implicit_multiplication: bool,
+    /// The options with which the output is meant to be parsed; these decide which juxtapositions are safe.
+    pub parse_options: ParseOptions,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            implicit_multiplication: true,
+            parse_options: ParseOptions::default(),
+        }
+    }
+}
+
+/// How tightly an expression binds when it is written out, from sums, assignments and definitions (0) to atoms (4).
+pub(crate) fn binding(tree: &Expression) -> u8 {
+    match &tree.data {
+        ExpressionData::Op(_, Operation::Add, _)
+        | ExpressionData::Op(_, Operation::Subtract, _)
+        | ExpressionData::Let(_, _, _)
+        | ExpressionData::Define(_, _) => 0,
+        ExpressionData::Op(_, Operation::Multiply, _)
+        | ExpressionData::Op(_, Operation::Divide, _) => 1,
+        ExpressionData::Negate(_) => 2,
+        ExpressionData::Constant(val) if val.is_sign_negative() => 2,
+        ExpressionData::Op(_, Operation::Exponentiate, _) => 3,
+        ExpressionData::Constant(_)
+        | ExpressionData::Identifier(_)
+        | ExpressionData::NamedConstant(_)
+        | ExpressionData::Call(_, _)
+        | ExpressionData::Error => 4,
+    }
+}
+
+/// Whether the left-hand side of an operation must be wrapped in parentheses to parse back to the same tree.
+pub(crate) fn lhs_needs_parens(op: Operation, lhs: &Expression) -> bool {
+    match op {
+        // `(-x)^2` and `(x^2)^3`
+        Operation::Exponentiate => binding(lhs) <= 3,
+        _ => binding(lhs) < binding_of_op(op),
+    }
+}
+
+/// Whether the right-hand side of an operation must be wrapped in parentheses to parse back to the same tree.
+pub(crate) fn rhs_needs_parens(op: Operation, rhs: &Expression) -> bool {
+    match op {
+        // a prefix operator in an exponent takes the rest of the power chain, so `x^-y^2` needs no parens
+        Operation::Exponentiate => binding(rhs) < 2,
+        // all other operations are left-associative
+        _ => binding(rhs) <= binding_of_op(op),
+    }
+}
+
+/// Whether the operand of a negation must be wrapped in parentheses to parse back to the same tree.
+pub(crate) fn operand_needs_parens(operand: &Expression) -> bool {
+    binding(operand) < 2
+}
+
+fn binding_of_op(op: Operation) -> u8 {
+    match op {
+        Operation::Add | Operation::Subtract => 0,
+        Operation::Multiply | Operation::Divide => 1,
+        Operation::Exponentiate => 3,
+    }
+}
+
+// two pieces of text can be juxtaposed if they lex to the same tokens together as apart
+fn lexes_apart(lhs: &str, separator: &str, rhs: &str, options: &ParseOptions) -> bool {
+    let joined = format!("{}{}{}", lhs, separator, rhs);
+    match (lex(lhs, options), lex(rhs, options), lex(&joined, options)) {
+        (Ok(lhs), Ok(rhs), Ok(joined)) => {
+            lhs.len() + rhs.len() == joined.len()
+                && lhs
+                    .iter()
+                    .chain(rhs.iter())
+                    .zip(joined.iter())
+                    .all(|(apart, joined)| apart.token_type == joined.token_type)
+        }
+        _ => false,
+    }
+}
+
+// a power or a name of more than one letter is hard to read when run together with its neighbours
+fn stands_apart(operand: &Expression) -> bool {
+    match &operand.data {
+        ExpressionData::Op(_, Operation::Exponentiate, _) => true,
+        ExpressionData::Identifier(name) | ExpressionData::NamedConstant(name) => {
+            name.chars().count() > 1
+        }
+        _ => false,
+    }
+}
+
+// whether a product of `lhs` and `rhs` is written with a space between them; a number stays
+// against what follows it, as in `2x^2`
+fn spaced_apart(lhs: &Expression, rhs: &Expression) -> bool {
+    let last = match &lhs.data {
+        ExpressionData::Op(_, Operation::Multiply | Operation::Divide, last)
+        | ExpressionData::Negate(last) => last,
+        _ => lhs,
+    };
+    !matches!(last.data, ExpressionData::Constant(_)) && (stands_apart(last) || stands_apart(rhs))
+}
+
+// writes a chain of left-hand sides, e.g. a long sum, from its leftmost operand outwards rather than by
+// recursion; `combine` is given the text of each left-hand side along with the operation above it
+fn write_chain<W, C>(tree: &Expression, write: W, mut combine: C) -> String
+where
+    W: FnOnce(&Expression) -> String,
+    C: FnMut(String, &Expression, Operation, &Expression) -> String,
+{
+    let (leftmost, chain) = tree.left_chain();
+    let (mut text, mut lhs) = (write(leftmost), leftmost);
+    for (node, op, rhs) in chain {
+        text = combine(text, lhs, op, rhs);
+        lhs = node;
+    }
+    text
+}
+
+struct Printer<'a> {
+    options: &'a PrintOptions,
+}
+
+impl<'a> Printer<'a> {
+    fn wrapped(&self, tree: &Expression, parens: bool) -> String {
+        if parens {
+            format!("({})", self.print(tree))
+        } else {
+            self.print(tree)
+        }
+    }
+
+    // juxtaposed factors which are powers or multi-letter names are kept apart with a space,
+    // e.g. `a^b c^2` rather than `a^bc^2`
+    fn multiply(&self, lhs: String, rhs: String, spaced: bool) -> String {
+        let rhs_is_operand = rhs.starts_with(|c: char| c.is_alphabetic() || c == '(');
+        if self.options.implicit_multiplication && rhs_is_operand {
+            let parse_options = &self.options.parse_options;
+            // a token always ends at these characters, so only the text after the last of them can
+            // run into the right-hand side, and a long run of juxtaposed factors is broken with a
+            // space; this keeps a long product from being lexed once per factor
+            let tail = lhs
+                .char_indices()
+                .rev()
+                .find(|(_, c)| c.is_whitespace() || "()*/^,;=".contains(*c))
+                .map_or(0, |(i, _)| i);
+            let separators: &[&str] = if spaced || lhs.len() - tail > 64 {
+                &[" "]
+            } else {
+                &["", " "]
+            };
+            for separator in separators {
+                if lexes_apart(&lhs[tail..], separator, &rhs, parse_options) {
+                    return format!("{}{}{}", lhs, separator, rhs);
+                }
+            }
+        }
+        format!("{}*{}", lhs, rhs)
+    }
+
+    fn operation(&self, mut lhs: String, op: Operation, rhs: String, spaced: bool) -> String {
+        let operator = match op {
+            Operation::Add => " + ",
+            Operation::Subtract => " - ",
+            Operation::Divide => "/",
+            Operation::Exponentiate => "^",
+            Operation::Multiply => return self.multiply(lhs, rhs, spaced),
+        };
+        lhs.push_str(operator);
+        lhs.push_str(&rhs);
+        lhs
+    }
+
+    fn print(&self, tree: &Expression) -> String {
+        match &tree.data {
+            ExpressionData::Constant(val) => format!("{}", val),
+            ExpressionData::Identifier(name) | ExpressionData::NamedConstant(name) => name.clone(),
+            ExpressionData::Error => "?".to_string(),
+            ExpressionData::Let(name, value, body) => {
+                format!("{} = {}; {}", name, self.print(value), self.print(body))
+            }
+            // a name followed by parentheses is a call after the definition, so it can no longer
+            // be juxtaposed with parentheses
+            ExpressionData::Define(definition, rest) => {
+                let mut options = self.options.clone();
+                let functions = &mut options.parse_options.functions;
+                functions.push(definition.name.clone());
+                let printer = Printer { options: &options };
+                format!(
+                    "{}({}) = {}; {}",
+                    definition.name,
+                    definition.parameters.join(", "),
+                    printer.print(&definition.body),
+                    printer.print(rest)
+                )
+            }
+            ExpressionData::Negate(operand) => {
+                format!("-{}", self.wrapped(operand, operand_needs_parens(operand)))
+            }
+            ExpressionData::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.print(arg)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            ExpressionData::Op(_, _, _) => write_chain(
+                tree,
+                |leftmost| self.print(leftmost),
+                |text, lhs, op, rhs| {
+                    let parens = (lhs_needs_parens(op, lhs), rhs_needs_parens(op, rhs));
+                    let spaced = parens == (false, false) && spaced_apart(lhs, rhs);
+                    let lhs = if parens.0 {
+                        format!("({})", text)
+                    } else {
+                        text
+                    };
+                    let rhs = self.wrapped(rhs, parens.1);
+                    self.operation(lhs, op, rhs, spaced)
+                },
+            ),
+        }
+    }
+}
+
+/// Writes a pre-parsed Serious expression back to Serious source, with only the parentheses that precedence requires.
+///
+/// Parsing the output with [`PrintOptions::parse_options`](crate::printer::PrintOptions::parse_options) yields a tree which is
+/// [equal](crate::parser::Expression::eq_ignoring_spans) to `tree`, as long as `tree` was parsed with the same options.
+///
+/// ```
+/// use serious::{parser::parse, printer::{print, PrintOptions}};
+///
+/// let tree = parse("((2)*(x^(2)))+(-(3*x))").unwrap();
+/// assert_eq!(print(&tree, &PrintOptions::default()), "2x^2 + -(3x)");
+///
+/// let options = PrintOptions {
+///     implicit_multiplication: false,
+///     ..PrintOptions::default()
+/// };
+/// assert_eq!(print(&tree, &options), "2*x^2 + -(3*x)");
+/// ```
+pub fn print(tree: &Expression, options: &PrintOptions) -> String {
+    Printer { options }.print(tree)
+}
+
+/// Writes the expression as Serious source with the default [`PrintOptions`](crate::printer::PrintOptions).
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", print(self, &PrintOptions::default()))
+    }
+}
+
+fn latex_name(name: &str) -> String {
+    if name.chars().count() == 1 {
+        name.to_string()
+    } else {
+        format!("\\mathit{{{}}}", name.replace('_', "\\_"))
+    }
+}
+
+fn latex_constant(name: &str) -> String {
+    match name {
+        "pi" | "tau" | "phi" => format!("\\{}", name),
+        "e" => "e".to_string(),
+        _ => format!("\\mathrm{{{}}}", name.replace('_', "\\_")),
+    }
+}
+
+fn latex_parens(inner: String) -> String {
+    format!("\\left({}\\right)", inner)
+}
+
+fn latex_call(name: &str, args: Vec<String>) -> String {
+    match (name, args.as_slice()) {
+        ("sqrt", [arg]) => format!("\\sqrt{{{}}}", arg),
+        ("cbrt", [arg]) => format!("\\sqrt[3]{{{}}}", arg),
+        ("abs", [arg]) => format!("\\left|{}\\right|", arg),
+        ("floor", [arg]) => format!("\\left\\lfloor {}\\right\\rfloor", arg),
+        ("ceil", [arg]) => format!("\\left\\lceil {}\\right\\rceil", arg),
+        _ => {
+            let operator = match name {
+                "exp" | "ln" | "sin" | "cos" | "tan" | "sinh" | "cosh" | "tanh" => {
+                    format!("\\{}", name)
+                }
+                "log" => "\\log_{10}".to_string(),
+                "log2" => "\\log_{2}".to_string(),
+                "asin" | "acos" | "atan" => format!("\\arc{}", &name[1..]),
+                _ if name.chars().count() == 1 => name.to_string(),
+                _ => format!("\\operatorname{{{}}}", name.replace('_', "\\_")),
+            };
+            format!("{}{}", operator, latex_parens(args.join(", ")))
+        }
+    }
+}
+
+fn is_fraction(tree: &Expression) -> bool {
+    matches!(tree.data, ExpressionData::Op(_, Operation::Divide, _))
+}
+
+// a fraction is set apart from its neighbours, so it only needs parentheses as the base of a power
+fn latex_wrapped(tree: &Expression, parens: bool) -> String {
+    latex_wrap(latex(tree), tree, parens)
+}
+
+fn latex_wrap(text: String, tree: &Expression, parens: bool) -> String {
+    if parens && !is_fraction(tree) {
+        latex_parens(text)
+    } else {
+        text
+    }
+}
+
+fn latex_operation(lhs_text: String, lhs: &Expression, op: Operation, rhs: &Expression) -> String {
+    match op {
+        Operation::Divide => format!("\\frac{{{}}}{{{}}}", lhs_text, latex(rhs)),
+        Operation::Exponentiate => {
+            let base = if lhs_needs_parens(Operation::Exponentiate, lhs) {
+                latex_parens(lhs_text)
+            } else {
+                lhs_text
+            };
+            format!("{}^{{{}}}", base, latex(rhs))
+        }
+        _ => {
+            let lhs = latex_wrap(lhs_text, lhs, lhs_needs_parens(op, lhs));
+            let rhs = latex_wrapped(rhs, rhs_needs_parens(op, rhs));
+            match op {
+                Operation::Add => format!("{} + {}", lhs, rhs),
+                Operation::Subtract => format!("{} - {}", lhs, rhs),
+                _ if rhs.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') => {
+                    format!("{} \\cdot {}", lhs, rhs)
+                }
+                _ => format!("{} {}", lhs, rhs),
+            }
+        }
+    }
+}
+
+/// Renders a pre-parsed Serious expression as LaTeX math-mode source, which compiles with the `amsmath` package.
+/// - Division is written as `\frac{}{}` and exponentiation as a superscript.
+/// - Multiplication is written as juxtaposition, or `\cdot` before a number or a sign.
+/// - Built-in functions and named constants use their usual commands, e.g. `\sin`, `\sqrt{}`, and `\pi`.
+///
+/// ```
+/// use serious::{parser::parse, printer::latex};
+///
+/// let tree = parse("2pi sqrt(x^2 + 1)/(3y)^-2").unwrap();
+/// assert_eq!(latex(&tree), "\\frac{2 \\pi \\sqrt{x^{2} + 1}}{\\left(3 y\\right)^{-2}}");
+/// ```
+pub fn latex(tree: &Expression) -> String {
+    match &tree.data {
+        ExpressionData::Constant(val) => format!("{}", val),
+        ExpressionData::Identifier(name) => latex_name(name),
+        ExpressionData::NamedConstant(name) => latex_constant(name),
+        ExpressionData::Error => "?".to_string(),
+        ExpressionData::Let(name, value, body) => {
+            format!(
+                "{} = {};\\quad {}",
+                latex_name(name),
+                latex(value),
+                latex(body)
+            )
+        }
+        ExpressionData::Define(definition, rest) => {
+            let parameters = definition.parameters.iter().map(|name| latex_name(name));
+            format!(
+                "{} = {};\\quad {}",
+                latex_call(&definition.name, parameters.collect()),
+                latex(&definition.body),
+                latex(rest)
+            )
+        }
+        ExpressionData::Negate(operand) => {
+            format!("-{}", latex_wrapped(operand, operand_needs_parens(operand)))
+        }
+        ExpressionData::Call(name, args) => latex_call(name, args.iter().map(latex).collect()),
+        ExpressionData::Op(_, _, _) => write_chain(tree, latex, latex_operation),
+    }
+}
+
+fn superscript(text: &str) -> Option<String> {
+    text.chars()
+        .map(|c| match c {
+            '0' => Some('⁰'),
+            '1' => Some('¹'),
+            '2' => Some('²'),
+            '3' => Some('³'),
+            '4' => Some('⁴'),
+            '5' => Some('⁵'),
+            '6' => Some('⁶'),
+            '7' => Some('⁷'),
+            '8' => Some('⁸'),
+            '9' => Some('⁹'),
+            '-' | '−' => Some('⁻'),
+            _ => None,
+        })
+        .collect()
+}
+
+fn unicode_constant(name: &str) -> String {
+    match name {
+        "pi" => "π".to_string(),
+        "tau" => "τ".to_string(),
+        "phi" => "φ".to_string(),
+        _ => name.to_string(),
+    }
+}
+
+fn unicode_number(val: f64) -> String {
+    if val.is_sign_negative() {
+        format!("−{}", -val)
+    } else {
+        format!("{}", val)
+    }
+}
+
+// a radical only covers the atom after it, so it needs parentheses as the base of a power
+fn is_radical(tree: &Expression) -> bool {
+    matches!(&tree.data, ExpressionData::Call(name, args) if (name == "sqrt" || name == "cbrt") && args.len() == 1)
+}
+
+fn unicode_wrapped(tree: &Expression, parens: bool) -> String {
+    unicode_wrap(unicode(tree), parens)
+}
+
+fn unicode_wrap(text: String, parens: bool) -> String {
+    if parens {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn unicode_operation(
+    lhs_text: String,
+    lhs: &Expression,
+    op: Operation,
+    rhs: &Expression,
+) -> String {
+    if op == Operation::Exponentiate {
+        let base = unicode_wrap(
+            lhs_text,
+            lhs_needs_parens(Operation::Exponentiate, lhs) || is_radical(lhs),
+        );
+        let exponent = match &rhs.data {
+            ExpressionData::Constant(val) if val.fract() == 0. => superscript(&unicode(rhs)),
+            ExpressionData::Negate(operand) => match operand.data {
+                ExpressionData::Constant(val) if val.fract() == 0. => superscript(&unicode(rhs)),
+                _ => None,
+            },
+            _ => None,
+        };
+        return match exponent {
+            Some(exponent) => format!("{}{}", base, exponent),
+            None => format!(
+                "{}^{}",
+                base,
+                unicode_wrapped(rhs, rhs_needs_parens(Operation::Exponentiate, rhs))
+            ),
+        };
+    }
+    let lhs = unicode_wrap(lhs_text, lhs_needs_parens(op, lhs));
+    let rhs = unicode_wrapped(rhs, rhs_needs_parens(op, rhs));
+    match op {
+        Operation::Add => format!("{} + {}", lhs, rhs),
+        Operation::Subtract => format!("{} − {}", lhs, rhs),
+        Operation::Divide => format!("{}/{}", lhs, rhs),
+        _ => unicode_multiply(lhs, rhs),
+    }
+}
+
+fn unicode_call(name: &str, args: &[Expression]) -> String {
+    match (name, args) {
+        ("sqrt", [arg]) => format!("√{}", unicode_wrapped(arg, binding(arg) < 4)),
+        ("cbrt", [arg]) => format!("∛{}", unicode_wrapped(arg, binding(arg) < 4)),
+        ("abs", [arg]) => format!("|{}|", unicode(arg)),
+        ("floor", [arg]) => format!("⌊{}⌋", unicode(arg)),
+        ("ceil", [arg]) => format!("⌈{}⌉", unicode(arg)),
+        _ => {
+            let args: Vec<String> = args.iter().map(unicode).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+    }
+}
+
+// letters are only run together when both sides are single letters, so `xy` but `x·sin(y)`
+fn unicode_multiply(lhs: String, rhs: String) -> String {
+    let trailing = lhs.chars().rev().take_while(|c| c.is_alphabetic()).count();
+    let leading = rhs.chars().take_while(|c| c.is_alphabetic()).count();
+    let explicit = rhs.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '−')
+        || (trailing > 0 && leading > 0 && (trailing > 1 || leading > 1));
+    if explicit {
+        format!("{}·{}", lhs, rhs)
+    } else {
+        format!("{}{}", lhs, rhs)
+    }
+}
+
+/// Renders a pre-parsed Serious expression as plain text with Unicode math symbols, e.g. `2x² − √(x + 1)`.
+/// - Integer exponents are written as superscripts, and other exponents after `^`.
+/// - Multiplication is written as juxtaposition where it reads unambiguously, and `·` otherwise.
+/// - Parentheses are placed as in [`print`](crate::printer::print).
+///
+/// Apart from `|x|`, `⌊x⌋` and `⌈x⌉`, the output parses back with [`ParseOptions::unicode`](crate::parser::ParseOptions::unicode).
+///
+/// ```
+/// use serious::{parser::parse, printer::unicode};
+///
+/// let tree = parse("2pi r^2 - sqrt(x + 1)/x^-1 * 3").unwrap();
+/// assert_eq!(unicode(&tree), "2πr² − √(x + 1)/x⁻¹·3");
+/// ```
+pub fn unicode(tree: &Expression) -> String {
+    match &tree.data {
+        ExpressionData::Constant(val) => unicode_number(*val),
+        ExpressionData::Identifier(name) => name.clone(),
+        ExpressionData::NamedConstant(name) => unicode_constant(name),
+        ExpressionData::Error => "?".to_string(),
+        ExpressionData::Let(name, value, body) => {
+            format!("{} = {}; {}", name, unicode(value), unicode(body))
+        }
+        ExpressionData::Define(definition, rest) => format!(
+            "{}({}) = {}; {}",
+            definition.name,
+            definition.parameters.join(", "),
+            unicode(&definition.body),
+            unicode(rest)
+        ),
+        ExpressionData::Negate(operand) => {
+            format!(
+                "−{}",
+                unicode_wrapped(operand, operand_needs_parens(operand))
+            )
+        }
+        ExpressionData::Call(name, args) => unicode_call(name, args),
+        ExpressionData::Op(_, _, _) => write_chain(tree, unicode, unicode_operation),
+    }
+}
+
+fn mathml_row(children: &[String]) -> String {
+    format!("<mrow>{}</mrow>", children.concat())
+}
+
+fn mathml_fenced(open: &str, inner: String, close: &str) -> String {
+    mathml_row(&[
+        format!("<mo>{}</mo>", open),
+        inner,
+        format!("<mo>{}</mo>", close),
+    ])
+}
+
+// as in LaTeX, a fraction is set apart from its neighbours, so it only needs parentheses as the base of a power
+fn mathml_wrapped(tree: &Expression, parens: bool) -> String {
+    mathml_wrap(mathml_element(tree), tree, parens)
+}
+
+fn mathml_wrap(text: String, tree: &Expression, parens: bool) -> String {
+    if parens && !is_fraction(tree) {
+        mathml_fenced("(", text, ")")
+    } else {
+        text
+    }
+}
+
+// whether the first thing written for `tree` is a number or a sign, in which case juxtaposing it would be ambiguous
+fn mathml_leads_with_number(mut tree: &Expression) -> bool {
+    loop {
+        match &tree.data {
+            ExpressionData::Constant(_) | ExpressionData::Negate(_) => return true,
+            ExpressionData::Op(_, Operation::Divide, _) => return false,
+            ExpressionData::Op(lhs, op, _) if !lhs_needs_parens(*op, lhs) => tree = lhs,
+            _ => return false,
+        }
+    }
+}
+
+fn mathml_operation(lhs_text: String, lhs: &Expression, op: Operation, rhs: &Expression) -> String {
+    match op {
+        Operation::Divide => format!("<mfrac>{}{}</mfrac>", lhs_text, mathml_element(rhs)),
+        Operation::Exponentiate => {
+            let base = if lhs_needs_parens(Operation::Exponentiate, lhs) {
+                mathml_fenced("(", lhs_text, ")")
+            } else {
+                lhs_text
+            };
+            format!("<msup>{}{}</msup>", base, mathml_element(rhs))
+        }
+        _ => {
+            let operator = match op {
+                Operation::Add => "+",
+                Operation::Subtract => "−",
+                _ if mathml_leads_with_number(rhs) && !rhs_needs_parens(op, rhs) => "·",
+                _ => "&#x2062;",
+            };
+            mathml_row(&[
+                mathml_wrap(lhs_text, lhs, lhs_needs_parens(op, lhs)),
+                format!("<mo>{}</mo>", operator),
+                mathml_wrapped(rhs, rhs_needs_parens(op, rhs)),
+            ])
+        }
+    }
+}
+
+fn mathml_call(name: &str, args: &[Expression]) -> String {
+    match (name, args) {
+        ("sqrt", [arg]) => format!("<msqrt>{}</msqrt>", mathml_element(arg)),
+        ("cbrt", [arg]) => format!("<mroot>{}<mn>3</mn></mroot>", mathml_element(arg)),
+        ("abs", [arg]) => mathml_fenced("|", mathml_element(arg), "|"),
+        ("floor", [arg]) => mathml_fenced("⌊", mathml_element(arg), "⌋"),
+        ("ceil", [arg]) => mathml_fenced("⌈", mathml_element(arg), "⌉"),
+        _ => {
+            let args: Vec<String> = args.iter().map(mathml_element).collect();
+            mathml_row(&[
+                format!("<mi>{}</mi>", name),
+                "<mo>&#x2061;</mo>".to_string(),
+                mathml_fenced("(", args.join("<mo>,</mo>"), ")"),
+            ])
+        }
+    }
+}
+
+fn mathml_element(tree: &Expression) -> String {
+    match &tree.data {
+        ExpressionData::Constant(val) if val.is_sign_negative() => {
+            mathml_row(&["<mo>−</mo>".to_string(), format!("<mn>{}</mn>", -val)])
+        }
+        ExpressionData::Constant(val) => format!("<mn>{}</mn>", val),
+        ExpressionData::Identifier(name) => format!("<mi>{}</mi>", name),
+        ExpressionData::NamedConstant(name) => format!("<mi>{}</mi>", unicode_constant(name)),
+        ExpressionData::Error => "<merror><mi>?</mi></merror>".to_string(),
+        ExpressionData::Let(name, value, body) => mathml_row(&[
+            format!("<mi>{}</mi>", name),
+            "<mo>=</mo>".to_string(),
+            mathml_element(value),
+            "<mo separator=\"true\">;</mo>".to_string(),
+            mathml_element(body),
+        ]),
+        ExpressionData::Define(definition, rest) => {
+            let parameters: Vec<Expression> = definition
+                .parameters
+                .iter()
+                .map(|name| Expression::new_id(name.as_str(), tree.start, tree.start))
+                .collect();
+            mathml_row(&[
+                mathml_call(&definition.name, &parameters),
+                "<mo>=</mo>".to_string(),
+                mathml_element(&definition.body),
+                "<mo separator=\"true\">;</mo>".to_string(),
+                mathml_element(rest),
+            ])
+        }
+        ExpressionData::Negate(operand) => mathml_row(&[
+            "<mo>−</mo>".to_string(),
+            mathml_wrapped(operand, operand_needs_parens(operand)),
+        ]),
+        ExpressionData::Call(name, args) => mathml_call(name, args),
+        ExpressionData::Op(_, _, _) => write_chain(tree, mathml_element, mathml_operation),
+    }
+}
+
+/// Renders a pre-parsed Serious expression as a Presentation MathML `<math>` element.
+/// - Division is written as `<mfrac>` and exponentiation as `<msup>`.
+/// - Multiplication is written with an invisible times operator, or `·` before a number or a sign.
+/// - Parentheses are placed as in [`latex`](crate::printer::latex).
+///
+/// ```
+/// use serious::{parser::parse, printer::mathml};
+///
+/// let tree = parse("x^2/2").unwrap();
+/// assert_eq!(
+///     mathml(&tree),
+///     "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mfrac><msup><mi>x</mi><mn>2</mn></msup><mn>2</mn></mfrac></math>"
+/// );
+/// ```
+pub fn mathml(tree: &Expression) -> String {
+    format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>",
+        mathml_element(tree)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, parse_with, IdentifierMode};
+
+    fn assert_round_trip(text: &str, options: &PrintOptions) {
+        let tree = parse_with(text, &options.parse_options).unwrap();
+        let printed = print(&tree, options);
+        let reparsed = parse_with(&printed, &options.parse_options).unwrap();
+        assert!(
+            tree.eq_ignoring_spans(&reparsed),
+            "{} printed as {}, which parsed to {:?}",
+            text,
+            printed,
+            reparsed
+        );
+    }
+
+    fn printed(text: &str) -> String {
+        parse(text).unwrap().to_string()
+    }
+
+    #[test]
+    fn minimal_parens() {
+        assert_eq!(printed("(1 + 2) + (3 + 4)"), "1 + 2 + (3 + 4)");
+        assert_eq!(printed("(a - b) - (c - d)"), "a - b - (c - d)");
+        assert_eq!(printed("(a/b)/(c/d)"), "a/b/(c/d)");
+        assert_eq!(printed("(a*b)^(c^d)"), "(ab)^c^d");
+        assert_eq!(printed("(a^b)^c"), "(a^b)^c");
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!(printed("(-x)^2"), "(-x)^2");
+        assert_eq!(printed("-(x^2)"), "-x^2");
+        assert_eq!(printed("x^(-y)"), "x^-y");
+        assert_eq!(printed("a*(-b)"), "a*-b");
+        assert_eq!(printed("a - (-b)"), "a - -b");
+        assert_eq!(printed("-(2x)"), "-(2x)");
+        assert_eq!(printed("+x"), "x");
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        assert_eq!(printed("2*x*y*(x + 1)"), "2xy(x + 1)");
+        assert_eq!(printed("x*2"), "x*2");
+        assert_eq!(printed("2*sin(x)*cos(x)"), "2sin(x)cos(x)");
+        assert_eq!(printed("a*sin(x)"), "a sin(x)");
+        assert_eq!(printed("2*e"), "2e");
+        assert_eq!(printed("pi*r^2"), "pi r^2");
+        assert_eq!(printed("a^b*a^b^2"), "a^b a^b^2");
+        assert_eq!(printed("x^2*y"), "x^2 y");
+        assert_eq!(printed("2*x^2"), "2x^2");
+    }
+
+    #[test]
+    fn explicit_multiplication() {
+        let options = PrintOptions {
+            implicit_multiplication: false,
+            ..PrintOptions::default()
+        };
+        let tree = parse("2xy(x + 1)").unwrap();
+        assert_eq!(print(&tree, &options), "2*x*y*(x + 1)");
+    }
+
+    #[test]
+    fn words() {
+        let options = PrintOptions {
+            parse_options: ParseOptions {
+                identifier_mode: IdentifierMode::Word,
+                ..ParseOptions::default()
+            },
+            ..PrintOptions::default()
+        };
+        let tree = parse_with("2*rate*time*x_1*(x_1 + 1)", &options.parse_options).unwrap();
+        assert_eq!(print(&tree, &options), "2rate time x_1(x_1 + 1)");
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(printed("sqrt((x^2) + (y^2))"), "sqrt(x^2 + y^2)");
+        assert_eq!(printed("-(ln(x))^2"), "-ln(x)^2");
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(printed("0.25 + 1.5e3"), "0.25 + 1500");
+        assert_eq!(Expression::new_const(-2., 0, 1).to_string(), "-2");
+        let tree = Expression::new_op(
+            Expression::new_const(-2., 0, 1),
+            Operation::Exponentiate,
+            Expression::new_id('x', 2, 3),
+        );
+        assert_eq!(tree.to_string(), "(-2)^x");
+    }
+
+    #[test]
+    fn round_trip() {
+        let texts = [
+            "34.2x + y^2(-2x^3 + 1)/5.2",
+            "(x + y)(x - y) - x*x + y^2",
+            "x/y/x*y^3 - 2(x + 1)(x + 1)",
+            "-(x - 2y)^3/(4 - -x)",
+            "2^3^2^x / 4 - (2^3)^2",
+            "-x^-y^2 * +z",
+            "a - (b - (c - d)) - ((e - f) - g)",
+            "sin(cos(x))^2 + 2pi r + asin(a)a sin(b)",
+        ];
+        for text in texts.iter() {
+            assert_round_trip(text, &PrintOptions::default());
+            assert_round_trip(
+                text,
+                &PrintOptions {
+                    implicit_multiplication: false,
+                    ..PrintOptions::default()
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_declared_names() {
+        let options = PrintOptions {
+            parse_options: ParseOptions {
+                names: vec!["ab".to_string(), "x_1".to_string()],
+                ..ParseOptions::default()
+            },
+            ..PrintOptions::default()
+        };
+        assert_round_trip("a*b*ab*b*x_1*(x_1)", &options);
+    }
+
+    fn latex_of(text: &str) -> String {
+        latex(&parse(text).unwrap())
+    }
+
+    #[test]
+    fn latex_fractions() {
+        assert_eq!(latex_of("(x + 1)/(x - 1)"), "\\frac{x + 1}{x - 1}");
+        assert_eq!(latex_of("a(b/c)"), "a \\frac{b}{c}");
+        assert_eq!(latex_of("-(a/b)"), "-\\frac{a}{b}");
+        assert_eq!(latex_of("(a/b)^2"), "\\left(\\frac{a}{b}\\right)^{2}");
+    }
+
+    #[test]
+    fn latex_powers() {
+        assert_eq!(latex_of("x^(y + 1)"), "x^{y + 1}");
+        assert_eq!(latex_of("2^3^x"), "2^{3^{x}}");
+        assert_eq!(latex_of("(2^3)^x"), "\\left(2^{3}\\right)^{x}");
+        assert_eq!(latex_of("(-x)^2"), "\\left(-x\\right)^{2}");
+        assert_eq!(latex_of("-x^2"), "-x^{2}");
+    }
+
+    #[test]
+    fn latex_multiplication() {
+        assert_eq!(latex_of("2xy(x + 1)"), "2 x y \\left(x + 1\\right)");
+        assert_eq!(latex_of("x*2"), "x \\cdot 2");
+        assert_eq!(latex_of("x*-y"), "x \\cdot -y");
+        assert_eq!(
+            latex_of("(a + b)(a - b)"),
+            "\\left(a + b\\right) \\left(a - b\\right)"
+        );
+    }
+
+    #[test]
+    fn latex_functions_and_constants() {
+        assert_eq!(latex_of("sin(x)^2"), "\\sin\\left(x\\right)^{2}");
+        assert_eq!(latex_of("asin(x)"), "\\arcsin\\left(x\\right)");
+        assert_eq!(
+            latex_of("log(x) + log2(x)"),
+            "\\log_{10}\\left(x\\right) + \\log_{2}\\left(x\\right)"
+        );
+        assert_eq!(latex_of("cbrt(abs(x))"), "\\sqrt[3]{\\left|x\\right|}");
+        assert_eq!(
+            latex_of("floor(x) - ceil(x)"),
+            "\\left\\lfloor x\\right\\rfloor - \\left\\lceil x\\right\\rceil"
+        );
+        assert_eq!(latex_of("tau + phi + e"), "\\tau + \\phi + e");
+    }
+
+    #[test]
+    fn latex_names() {
+        let options = ParseOptions {
+            identifier_mode: IdentifierMode::Word,
+            ..ParseOptions::default()
+        };
+        let tree = parse_with("rate*x_1", &options).unwrap();
+        assert_eq!(latex(&tree), "\\mathit{rate} \\mathit{x\\_1}");
+    }
+
+    fn unicode_of(text: &str) -> String {
+        unicode(&parse(text).unwrap())
+    }
+
+    fn mathml_of(text: &str) -> String {
+        mathml_element(&parse(text).unwrap())
+    }
+
+    #[test]
+    fn unicode_superscripts() {
+        assert_eq!(unicode_of("x^2 + y^10"), "x² + y¹⁰");
+        assert_eq!(unicode_of("x^-1"), "x⁻¹");
+        assert_eq!(unicode_of("x^0.5 + x^y"), "x^0.5 + x^y");
+        assert_eq!(unicode_of("x^(y + 1)"), "x^(y + 1)");
+        assert_eq!(unicode_of("(x^2)^3"), "(x²)³");
+        assert_eq!(unicode_of("(-x)^2 - -x^2"), "(−x)² − −x²");
+    }
+
+    #[test]
+    fn unicode_multiplication() {
+        assert_eq!(unicode_of("2xy(x + 1)"), "2xy(x + 1)");
+        assert_eq!(unicode_of("x*2 + x*-y"), "x·2 + x·−y");
+        assert_eq!(unicode_of("a*sin(b)"), "a·sin(b)");
+        assert_eq!(unicode_of("2pi r"), "2πr");
+    }
+
+    #[test]
+    fn unicode_functions() {
+        assert_eq!(unicode_of("sqrt(x) + sqrt(x + 1)"), "√x + √(x + 1)");
+        assert_eq!(unicode_of("sqrt(x)^2"), "(√x)²");
+        assert_eq!(unicode_of("cbrt(x^3)"), "∛(x³)");
+        assert_eq!(unicode_of("abs(x) + floor(x) + ceil(x)"), "|x| + ⌊x⌋ + ⌈x⌉");
+        assert_eq!(unicode_of("ln(x)/tau"), "ln(x)/τ");
+    }
+
+    #[test]
+    fn mathml_structure() {
+        assert_eq!(
+            mathml_of("(a + b)^2"),
+            "<msup><mrow><mo>(</mo><mrow><mi>a</mi><mo>+</mo><mi>b</mi></mrow><mo>)</mo></mrow><mn>2</mn></msup>"
+        );
+        assert_eq!(
+            mathml_of("a - (b - c)"),
+            "<mrow><mi>a</mi><mo>−</mo><mrow><mo>(</mo><mrow><mi>b</mi><mo>−</mo><mi>c</mi></mrow><mo>)</mo></mrow></mrow>"
+        );
+        assert_eq!(
+            mathml_of("a(b/c)"),
+            "<mrow><mi>a</mi><mo>&#x2062;</mo><mfrac><mi>b</mi><mi>c</mi></mfrac></mrow>"
+        );
+    }
+
+    #[test]
+    fn mathml_multiplication() {
+        assert_eq!(
+            mathml_of("2x"),
+            "<mrow><mn>2</mn><mo>&#x2062;</mo><mi>x</mi></mrow>"
+        );
+        assert_eq!(
+            mathml_of("x*2"),
+            "<mrow><mi>x</mi><mo>·</mo><mn>2</mn></mrow>"
+        );
+        assert_eq!(
+            mathml_of("x*(2 + y)"),
+            "<mrow><mi>x</mi><mo>&#x2062;</mo><mrow><mo>(</mo><mrow><mn>2</mn><mo>+</mo><mi>y</mi></mrow><mo>)</mo></mrow></mrow>"
+        );
+    }
+
+    #[test]
+    fn mathml_functions() {
+        assert_eq!(mathml_of("sqrt(pi)"), "<msqrt><mi>π</mi></msqrt>");
+        assert_eq!(
+            mathml_of("sin(x)"),
+            "<mrow><mi>sin</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mi>x</mi><mo>)</mo></mrow></mrow>"
+        );
+    }
+
+    #[test]
+    fn formats_agree_on_parens() {
+        // every format parenthesizes the same subtrees, apart from the fractions that LaTeX and MathML lay out
+        for text in [
+            "-(x - 2y)^3 + (4 - -x)(a - b)",
+            "((a - b) - (c - d))^-(x + 1)",
+        ]
+        .iter()
+        {
+            let tree = parse(text).unwrap();
+            let printed = tree.to_string().matches('(').count();
+            assert_eq!(unicode(&tree).matches('(').count(), printed);
+            assert_eq!(latex(&tree).matches("\\left(").count(), printed);
+            assert_eq!(mathml(&tree).matches("<mo>(</mo>").count(), printed);
+        }
+    }
+
+    #[test]
+    fn unicode_round_trip() {
+        let options = ParseOptions {
+            unicode: true,
+            ..ParseOptions::default()
+        };
+        for text in [
+            "2pi r^2 - sqrt(x + 1)/x^-1*3",
+            "(x^2)^3 - -x^2",
+            "sqrt(x)^2 + cbrt(x^3)",
+            "x*2 + x*-y",
+        ]
+        .iter()
+        {
+            let tree = parse(text).unwrap();
+            let reparsed = parse_with(&unicode(&tree), &options).unwrap();
+            assert!(
+                tree.eq_ignoring_spans(&reparsed),
+                "{} printed as {}",
+                text,
+                unicode(&tree)
+            );
+        }
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            printed("d=b^2-4*a*c;(-b+d^0.5)/(2*a)"),
+            "d = b^2 - 4ac; (-b + d^0.5)/(2a)"
+        );
+        assert_round_trip("a = x; b = (a + 1)^2; a b", &PrintOptions::default());
+        assert_eq!(latex_of("d = 2x; d/2"), "d = 2 x;\\quad \\frac{d}{2}");
+        assert_eq!(unicode_of("d = -x; d^2"), "d = −x; d²");
+        assert_eq!(
+            mathml_of("d = 1; d"),
+            "<mrow><mi>d</mi><mo>=</mo><mn>1</mn><mo separator=\"true\">;</mo><mi>d</mi></mrow>"
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            printed("f(t)=3*t^2+1;f(x)+f(y)"),
+            "f(t) = 3t^2 + 1; f(x) + f(y)"
+        );
+        assert_round_trip(
+            "g(t) = t; x = 2; g*(x + 1) + g(x)",
+            &PrintOptions::default(),
+        );
+        assert_round_trip("h(u, v) = u/v; h(1, 2)", &PrintOptions::default());
+        assert_eq!(
+            latex_of("f(t) = 2t; f(x)"),
+            "f\\left(t\\right) = 2 t;\\quad f\\left(x\\right)"
+        );
+        assert_eq!(
+            unicode_of("f(t, u) = t^2; f(1, 2)"),
+            "f(t, u) = t²; f(1, 2)"
+        );
+        assert_eq!(
+            mathml_of("f(t) = 1; f(2)"),
+            "<mrow><mrow><mi>f</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mi>t</mi><mo>)</mo></mrow></mrow><mo>=</mo><mn>1</mn><mo separator=\"true\">;</mo><mrow><mi>f</mi><mo>&#x2061;</mo><mrow><mo>(</mo><mn>2</mn><mo>)</mo></mrow></mrow></mrow>"
+        );
+    }
+
+    #[test]
+    fn long_chains() {
+        let text = format!("x{}", "+x".repeat(8000));
+        let tree = parse(&text).unwrap();
+        assert_eq!(tree.to_string(), text.replace('+', " + "));
+        assert!(latex(&tree).starts_with("x + x"));
+        assert_round_trip(
+            &format!("x{}", "*y^2".repeat(2000)),
+            &PrintOptions::default(),
+        );
+    }
+}
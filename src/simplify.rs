@@ -0,0 +1,598 @@
+use super::functions::builtin;
+use super::parser::{Definition, Expression, ExpressionData, Operation};
+
+fn constant_value(tree: &Expression) -> Option<f64> {
+    match tree.data {
+        ExpressionData::Constant(val) => Some(val),
+        _ => None,
+    }
+}
+
+// whether a simplified tree still holds an operation on constants, which is only kept if it fails
+// to evaluate, e.g. `1/0` or `ln(-1)`; such a tree must not be folded into one which evaluates
+fn fails(tree: &Expression) -> bool {
+    match &tree.data {
+        ExpressionData::Op(_, _, _) => {
+            let (leftmost, chain) = tree.left_chain();
+            let mut lhs = leftmost;
+            fails(leftmost)
+                || chain.into_iter().any(|(node, _, rhs)| {
+                    let constant = constant_value(lhs).is_some() && constant_value(rhs).is_some();
+                    lhs = node;
+                    constant || fails(rhs)
+                })
+        }
+        ExpressionData::Negate(operand) => fails(operand),
+        ExpressionData::Call(name, args) => match args.as_slice() {
+            [arg] if builtin(name).is_some() && constant_value(arg).is_some() => true,
+            _ => args.iter().any(fails),
+        },
+        ExpressionData::Let(_, value, body) => fails(value) || fails(body),
+        ExpressionData::Define(_, rest) => fails(rest),
+        _ => false,
+    }
+}
+
+// a numeric coefficient times a product of bases raised to constant exponents
+#[derive(Debug)]
+struct Term {
+    coefficient: f64,
+    factors: Vec<(Expression, f64)>,
+}
+
+impl Term {
+    // a constant is only a base if folding it fails to evaluate, e.g. `0` in `1/0`, so it is never
+    // merged with another, which could cancel it out
+    fn multiply(&mut self, base: Expression, exponent: f64) {
+        match self
+            .factors
+            .iter_mut()
+            .filter(|(other, _)| constant_value(other).is_none())
+            .find(|(other, _)| other.eq_ignoring_spans(&base))
+        {
+            Some((_, other_exponent)) => *other_exponent += exponent,
+            None => self.factors.push((base, exponent)),
+        }
+    }
+
+    // `power` is 1 for factors in the numerator and -1 for factors in the denominator; a chain of
+    // products and quotients is collected from its first factor onwards, without recursion
+    fn collect(&mut self, tree: &Expression, power: f64) {
+        let (mut first, mut rest) = (tree, vec![]);
+        while let ExpressionData::Op(lhs, op @ (Operation::Multiply | Operation::Divide), rhs) =
+            &first.data
+        {
+            let power = if *op == Operation::Divide {
+                -power
+            } else {
+                power
+            };
+            rest.push((rhs, power));
+            first = lhs;
+        }
+        self.collect_factor(first, power);
+        for (rhs, power) in rest.into_iter().rev() {
+            self.collect(rhs, power);
+        }
+    }
+
+    fn collect_factor(&mut self, tree: &Expression, power: f64) {
+        match &tree.data {
+            ExpressionData::Negate(operand) => {
+                self.coefficient = -self.coefficient;
+                self.collect(operand, power);
+            }
+            _ => {
+                let simplified = simplify(tree);
+                match &simplified.data {
+                    ExpressionData::Constant(val) => {
+                        self.collect_constant(&simplified, *val, power)
+                    }
+                    ExpressionData::Op(_, Operation::Multiply, _)
+                    | ExpressionData::Op(_, Operation::Divide, _)
+                    | ExpressionData::Negate(_) => self.collect(&simplified, power),
+                    ExpressionData::Op(base, Operation::Exponentiate, exponent) => {
+                        match constant_value(exponent) {
+                            Some(exponent) => self.multiply(*base.clone(), exponent * power),
+                            None => self.multiply(simplified, power),
+                        }
+                    }
+                    _ => self.multiply(simplified, power),
+                }
+            }
+        }
+    }
+
+    // a constant is only folded into the coefficient while the product is finite, so that e.g.
+    // `1/0` and `1e200*1e200` still fail to evaluate; once one is kept, so is every later one,
+    // together with the coefficient so far, ahead of the other factors in the order they came
+    fn collect_constant(&mut self, tree: &Expression, val: f64, power: f64) {
+        let kept = self
+            .factors
+            .iter()
+            .filter(|(base, _)| constant_value(base).is_some())
+            .count();
+        let product = self.coefficient * val.powf(power);
+        if kept == 0 && product.is_finite() {
+            self.coefficient = product;
+            return;
+        }
+        let mut at = kept;
+        if kept == 0 && self.coefficient.abs() != 1. {
+            let coefficient = Expression::new_const(self.coefficient.abs(), tree.start, tree.end);
+            self.factors.insert(0, (coefficient, 1.));
+            self.coefficient = self.coefficient.signum();
+            at += 1;
+        }
+        self.factors.insert(at, (tree.clone(), power));
+    }
+
+    fn from_tree(tree: &Expression) -> Term {
+        let mut term = Term {
+            coefficient: 1.,
+            factors: vec![],
+        };
+        term.collect(tree, 1.);
+        term.factors.retain(|(_, exponent)| *exponent != 0.);
+        if term.coefficient == 0. && !term.fails() {
+            term.factors.clear();
+        }
+        term
+    }
+
+    fn fails(&self) -> bool {
+        self.factors
+            .iter()
+            .any(|(base, _)| constant_value(base).is_some() || fails(base))
+    }
+
+    fn is_like(&self, other: &Term) -> bool {
+        self.factors.len() == other.factors.len()
+            && self.factors.iter().all(|(base, exponent)| {
+                other.factors.iter().any(|(other_base, other_exponent)| {
+                    exponent == other_exponent && base.eq_ignoring_spans(other_base)
+                })
+            })
+    }
+
+    // the product of the factors, without the coefficient
+    fn build_factors(&self, start: usize, end: usize) -> Option<Expression> {
+        let power = |base: &Expression, exponent: f64| {
+            if exponent == 1. {
+                base.clone()
+            } else {
+                let exponent = Expression::new_const(exponent, base.start, base.end);
+                Expression::new_op(base.clone(), Operation::Exponentiate, exponent)
+            }
+        };
+        let product = |factors: Vec<Expression>| {
+            factors
+                .into_iter()
+                .reduce(|lhs, rhs| Expression::new_op(lhs, Operation::Multiply, rhs))
+        };
+
+        let numerator = product(
+            self.factors
+                .iter()
+                .filter(|(_, exponent)| *exponent > 0.)
+                .map(|(base, exponent)| power(base, *exponent))
+                .collect(),
+        );
+        let denominator = product(
+            self.factors
+                .iter()
+                .filter(|(_, exponent)| *exponent < 0.)
+                .map(|(base, exponent)| power(base, -exponent))
+                .collect(),
+        );
+
+        match (numerator, denominator) {
+            (numerator, None) => numerator,
+            (numerator, Some(denominator)) => {
+                let numerator = numerator.unwrap_or_else(|| Expression::new_const(1., start, end));
+                Some(Expression::new_op(
+                    numerator,
+                    Operation::Divide,
+                    denominator,
+                ))
+            }
+        }
+    }
+
+    // builds the term with the magnitude of its coefficient
+    fn build_magnitude(&self, start: usize, end: usize) -> Expression {
+        let coefficient = self.coefficient.abs();
+        match self.build_factors(start, end) {
+            None => Expression::new_const(coefficient, start, end),
+            Some(factors) if coefficient == 1. => factors,
+            Some(factors) => {
+                let coefficient = Expression::new_const(coefficient, start, end);
+                match factors.data {
+                    // `2*(x/y)` is written as `2x/y`
+                    ExpressionData::Op(numerator, Operation::Divide, denominator) => {
+                        let numerator = match numerator.data {
+                            ExpressionData::Constant(_) => coefficient,
+                            _ => Expression::new_op(coefficient, Operation::Multiply, *numerator),
+                        };
+                        Expression::new_op(numerator, Operation::Divide, *denominator)
+                    }
+                    _ => Expression::new_op(coefficient, Operation::Multiply, factors),
+                }
+            }
+        }
+    }
+
+    fn build(&self, start: usize, end: usize) -> Expression {
+        if self.factors.is_empty() {
+            Expression::new_const(self.coefficient, start, end)
+        } else if self.coefficient < 0. {
+            let magnitude = self.build_magnitude(start, end);
+            Expression::new_neg(magnitude, start, end)
+        } else {
+            self.build_magnitude(start, end)
+        }
+    }
+}
+
+// a sum of terms, in the order in which they first appear
+#[derive(Debug)]
+struct Sum {
+    terms: Vec<Term>,
+    // whether adding two like terms has overflowed, after which no more terms are added together
+    overflowed: bool,
+}
+
+impl Sum {
+    // a term which fails to evaluate is kept apart, so that e.g. `1/0 - 1/0` is not cancelled out,
+    // and so is one whose sum with a like term overflows, e.g. the second term of `1e308 + 1e308`
+    fn add(&mut self, term: Term) {
+        if term.fails() || self.overflowed {
+            return self.terms.push(term);
+        }
+        match self.terms.iter_mut().find(|other| other.is_like(&term)) {
+            Some(other) if (other.coefficient + term.coefficient).is_finite() => {
+                other.coefficient += term.coefficient
+            }
+            Some(_) => {
+                self.overflowed = true;
+                self.terms.push(term)
+            }
+            None => self.terms.push(term),
+        }
+    }
+
+    // a chain of sums and differences is collected from its first term onwards, without recursion
+    fn collect(&mut self, tree: &Expression, sign: f64) {
+        let (mut first, mut rest) = (tree, vec![]);
+        while let ExpressionData::Op(lhs, op @ (Operation::Add | Operation::Subtract), rhs) =
+            &first.data
+        {
+            let sign = if *op == Operation::Subtract {
+                -sign
+            } else {
+                sign
+            };
+            rest.push((rhs, sign));
+            first = lhs;
+        }
+        self.collect_term(first, sign);
+        for (rhs, sign) in rest.into_iter().rev() {
+            self.collect(rhs, sign);
+        }
+    }
+
+    fn collect_term(&mut self, tree: &Expression, sign: f64) {
+        match &tree.data {
+            ExpressionData::Negate(operand) => self.collect(operand, -sign),
+            _ => {
+                let mut term = Term::from_tree(tree);
+                term.coefficient *= sign;
+                match term.factors.as_slice() {
+                    // a term which simplified to a sum is merged into this one
+                    [(base, exponent)]
+                        if *exponent == 1. && term.coefficient.abs() == 1. && is_sum(base) =>
+                    {
+                        let base = base.clone();
+                        self.collect(&base, term.coefficient)
+                    }
+                    _ => self.add(term),
+                }
+            }
+        }
+    }
+
+    fn build(&self, start: usize, end: usize) -> Expression {
+        let mut terms = self
+            .terms
+            .iter()
+            .filter(|term| term.coefficient != 0. || term.fails());
+        let first = match terms.next() {
+            Some(first) => first.build(start, end),
+            None => return Expression::new_const(0., start, end),
+        };
+        terms.fold(first, |sum, term| {
+            let op = if term.coefficient < 0. {
+                Operation::Subtract
+            } else {
+                Operation::Add
+            };
+            Expression::new_op(sum, op, term.build_magnitude(start, end))
+        })
+    }
+}
+
+fn is_sum(tree: &Expression) -> bool {
+    matches!(
+        tree.data,
+        ExpressionData::Op(_, Operation::Add, _)
+            | ExpressionData::Op(_, Operation::Subtract, _)
+            | ExpressionData::Negate(_)
+    )
+}
+
+fn simplify_power(tree: &Expression, base: &Expression, exponent: &Expression) -> Expression {
+    let (base, exponent) = (simplify(base), simplify(exponent));
+    match (constant_value(&base), constant_value(&exponent)) {
+        (_, Some(0.)) if !fails(&base) => Expression::new_const(1., tree.start, tree.end),
+        (_, Some(1.)) => base,
+        (Some(1.), _) if !fails(&exponent) => Expression::new_const(1., tree.start, tree.end),
+        (Some(base), Some(exponent)) if base.powf(exponent).is_finite() => {
+            Expression::new_const(base.powf(exponent), tree.start, tree.end)
+        }
+        _ => Expression::new_op(base, Operation::Exponentiate, exponent),
+    }
+}
+
+fn simplify_call(tree: &Expression, name: &str, args: &[Expression]) -> Expression {
+    let args: Vec<Expression> = args.iter().map(simplify).collect();
+    if let (Some(function), [arg]) = (builtin(name), args.as_slice()) {
+        if let Some(val) = constant_value(arg) {
+            let result = (function.function)(val);
+            if result.is_finite() {
+                return Expression::new_const(result, tree.start, tree.end);
+            }
+        }
+    }
+    Expression::new_call(name, args, tree.start, tree.end)
+}
+
+/// Simplifies a pre-parsed Serious expression.
+///
+/// - Operations and built-in function calls on constants are folded, unless they would fail to evaluate (e.g. `1/0` or `1e200*1e200`).
+///   Such an operation is never eliminated either, so e.g. `0*(1/0)`, `(1/0)^0` and `1/0 - 1/0` still fail to evaluate.
+/// - Identities and annihilators are eliminated, e.g. `x*1`, `x+0`, `x^1` and `0*x`.
+/// - Like terms are collected and numeric coefficients are combined, e.g. `2*3x + x` becomes `7x`.
+/// - Repeated factors are collected into powers, e.g. `x*x/y` becomes `x^2/y`.
+///
+/// [Named constants](crate::constants) are left in place, since they can be shadowed by the [`Context`](crate::interpreter::Context).
+/// Nodes which are kept retain their spans; new nodes span the expression from which they were built.
+///
+/// The simplified expression evaluates to the same value wherever the original one is defined,
+/// but it may be defined where the original is not, e.g. `0*(1/x)` at `x = 0`.
+///
+/// ```
+/// use serious::{parser::parse, simplify::simplify};
+///
+/// let tree = simplify(&parse("2*3x + 0*y - x^1").unwrap());
+/// assert!(tree.eq_ignoring_spans(&parse("5x").unwrap()));
+/// ```
+pub fn simplify(tree: &Expression) -> Expression {
+    match &tree.data {
+        ExpressionData::Constant(_)
+        | ExpressionData::Identifier(_)
+        | ExpressionData::NamedConstant(_)
+        | ExpressionData::Error => tree.clone(),
+        ExpressionData::Op(_, Operation::Add, _)
+        | ExpressionData::Op(_, Operation::Subtract, _)
+        | ExpressionData::Negate(_) => {
+            let mut sum = Sum {
+                terms: vec![],
+                overflowed: false,
+            };
+            sum.collect(tree, 1.);
+            sum.build(tree.start, tree.end)
+        }
+        ExpressionData::Op(_, Operation::Multiply, _)
+        | ExpressionData::Op(_, Operation::Divide, _) => {
+            Term::from_tree(tree).build(tree.start, tree.end)
+        }
+        ExpressionData::Op(base, Operation::Exponentiate, exponent) => {
+            simplify_power(tree, base, exponent)
+        }
+        ExpressionData::Call(name, args) => simplify_call(tree, name, args),
+        ExpressionData::Let(name, value, body) => {
+            Expression::new_let(name, simplify(value), simplify(body), tree.start, tree.end)
+        }
+        ExpressionData::Define(definition, rest) => {
+            let definition = Definition {
+                name: definition.name.clone(),
+                parameters: definition.parameters.clone(),
+                body: simplify(&definition.body),
+            };
+            Expression::new_define(definition, simplify(rest), tree.start, tree.end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculus::derivative;
+    use crate::create_context;
+    use crate::error::ErrorCode;
+    use crate::interpreter::interpret_tree;
+    use crate::parser::parse;
+
+    // the expected text is simplified too, since e.g. `-3` parses as a negation
+    fn assert_simplifies(text: &str, expected: &str) {
+        let tree = simplify(&parse(text).unwrap());
+        assert!(
+            tree.eq_ignoring_spans(&simplify(&parse(expected).unwrap())),
+            "expected {} to simplify to {}, found {:?}",
+            text,
+            expected,
+            tree
+        );
+    }
+
+    #[test]
+    fn constant_folding() {
+        assert_simplifies("1 + 2*3 - 4/8", "6.5");
+        assert_simplifies("2^3^2", "512");
+        assert_simplifies("sqrt(4) + ln(1)", "2");
+        assert_simplifies("-(3)", "-3");
+    }
+
+    #[test]
+    fn unfoldable_constants() {
+        assert_simplifies("1/0 + x", "1/0 + x");
+        assert_simplifies("ln(-1)", "ln(-1)");
+        assert_simplifies("2e", "2e");
+        for text in [
+            "0/0",
+            "0*(1/0)",
+            "(1/0)*0",
+            "(1/0)^0",
+            "1/0 - 1/0",
+            "1^ln(-1)",
+            "(1/0)/(1/0)",
+            "0x/0",
+        ] {
+            let tree = simplify(&parse(text).unwrap());
+            let context = create_context! {'x' => 1.};
+            assert!(
+                interpret_tree(tree.clone(), &context).is_err(),
+                "{} simplified to {:?}, which evaluates",
+                text,
+                tree
+            );
+        }
+    }
+
+    #[test]
+    fn overflowing_constants() {
+        for text in [
+            "1e200*1e200",
+            "1e308 + 1e308",
+            "1e200x*1e200",
+            "2*1e308*0.1",
+            "1e200*1e200/1e200",
+            "1e308 + 1e308 - 1e308",
+            "1e308 + x + 1e308 - x",
+            "0*(1e308 + 1e308)",
+        ] {
+            let tree = simplify(&parse(text).unwrap());
+            let context = create_context! {'x' => 1.};
+            let err = interpret_tree(tree.clone(), &context).unwrap_err();
+            assert!(
+                matches!(err.code, ErrorCode::OperationOverflow { .. }),
+                "{} simplified to {}, which gives {:?}",
+                text,
+                tree,
+                err
+            );
+        }
+        assert_simplifies("1e200*1e200/1e300", "1e200*1e200/1e300");
+        assert_simplifies("1e308 - 1e308 + 1e308", "1e308");
+    }
+
+    #[test]
+    fn identities() {
+        assert_simplifies("x*1 + 0", "x");
+        assert_simplifies("1x^1/1", "x");
+        assert_simplifies("(y + 2)^0", "1");
+        assert_simplifies("1^y", "1");
+        assert_simplifies("-(-x)", "x");
+    }
+
+    #[test]
+    fn annihilators() {
+        assert_simplifies("0*x", "0");
+        assert_simplifies("y + sin(x)*0", "y");
+        assert_simplifies("0/x", "0");
+    }
+
+    #[test]
+    fn like_terms() {
+        assert_simplifies("2*3x + 0*y", "6x");
+        assert_simplifies("2x + 3x - x", "4x");
+        assert_simplifies("x - x", "0");
+        assert_simplifies("2xy + 3yx + 1 - y + 2", "5xy + 3 - y");
+        assert_simplifies("x^2 + x^2 - (x + 1)", "2x^2 - x - 1");
+        assert_simplifies("-x - x", "-2x");
+    }
+
+    #[test]
+    fn like_factors() {
+        assert_simplifies("x*x*y/x", "xy");
+        assert_simplifies("(x + 1)(x + 1)", "(x + 1)^2");
+        assert_simplifies("x^2 x^-3", "1/x");
+        assert_simplifies("3x/(2y)", "1.5x/y");
+        assert_simplifies("2(x + x)", "4x");
+    }
+
+    #[test]
+    fn nested() {
+        assert_simplifies("sin(0*x + y*1)", "sin(y)");
+        assert_simplifies("(x*1)^(2 - 1)", "x");
+    }
+
+    #[test]
+    fn spans() {
+        let tree = simplify(&parse("2*3x + 0*y").unwrap());
+        assert_eq!(
+            tree,
+            Expression::new_op(
+                Expression::new_const(6., 0, 10),
+                Operation::Multiply,
+                Expression::new_id('x', 3, 4)
+            )
+        );
+    }
+
+    #[test]
+    fn simplified_derivative() {
+        let tree = derivative(&parse("3x^2 + 2x").unwrap(), "x").unwrap();
+        let tree = simplify(&tree);
+        assert!(tree.eq_ignoring_spans(&parse("6x + 2").unwrap()));
+    }
+
+    #[test]
+    fn preserves_values() {
+        let texts = [
+            "34.2x + y^2(-2x^3 + 1)/5.2",
+            "(x + y)(x - y) - x*x + y^2",
+            "x/y/x*y^3 - 2(x + 1)(x + 1)",
+            "-(x - 2y)^3/(4 - -x)",
+        ];
+        for text in texts.iter() {
+            let tree = parse(text).unwrap();
+            let simplified = simplify(&tree);
+            for (x, y) in [(1., 2.), (1.5, -0.5), (-3., 0.25)].iter() {
+                let context = create_context! {'x' => *x, 'y' => *y};
+                let expected = interpret_tree(tree.clone(), &context).unwrap();
+                let val = interpret_tree(simplified.clone(), &context).unwrap();
+                assert!(
+                    (expected - val).abs() <= 1e-9 * expected.abs().max(1.),
+                    "{} at ({}, {}): expected {}, found {}",
+                    text,
+                    x,
+                    y,
+                    expected,
+                    val
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn assignments() {
+        assert_simplifies("d = 2*3; d x + x*1", "d = 6; d x + x");
+    }
+
+    #[test]
+    fn definitions() {
+        let tree = simplify(&parse("f(t) = 2*3t + 0; f(x*1)").unwrap());
+        assert_eq!(tree.to_string(), "f(t) = 6t; f(x)");
+    }
+}
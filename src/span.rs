@@ -0,0 +1,138 @@
+/// A position in multi-line text; lines are separated by `\n`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineColumn {
+    /// The line, counted from 1.
+    pub line: usize,
+    /// The column within the line in chars, counted from 1.
+    pub column: usize,
+}
+
+// an offset inside a multi-byte char or past the end of the text is moved back to the start of the char
+pub(crate) fn clamp(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// Converts a byte offset from a span into a line and column.
+///
+/// ```
+/// use serious::span::{line_column, LineColumn};
+///
+/// let text = "2π r +\n  × 3";
+/// assert_eq!(line_column(text, 4), LineColumn { line: 1, column: 4 });
+/// assert_eq!(line_column(text, 10), LineColumn { line: 2, column: 3 });
+/// ```
+pub fn line_column(text: &str, offset: usize) -> LineColumn {
+    let offset = clamp(text, offset);
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    LineColumn {
+        line: text[..offset].matches('\n').count() + 1,
+        column: text[line_start..offset].chars().count() + 1,
+    }
+}
+
+/// Converts a line and column into a byte offset which can be compared with a span.
+///
+/// Returns [`None`] if the line does not exist or the column is past the end of the line.
+///
+/// ```
+/// use serious::span::{byte_offset, LineColumn};
+///
+/// let text = "2π r +\n  × 3";
+/// assert_eq!(byte_offset(text, LineColumn { line: 2, column: 3 }), Some(10));
+/// assert_eq!(byte_offset(text, LineColumn { line: 3, column: 1 }), None);
+/// ```
+pub fn byte_offset(text: &str, position: LineColumn) -> Option<usize> {
+    let line_start = if position.line == 1 {
+        0
+    } else {
+        text.match_indices('\n')
+            .nth(position.line.checked_sub(2)?)?
+            .0
+            + 1
+    };
+    let line = text[line_start..].split('\n').next()?;
+    let column = position.column.checked_sub(1)?;
+    match line.char_indices().nth(column) {
+        Some((offset, _)) => Some(line_start + offset),
+        None if column == line.chars().count() => Some(line_start + line.len()),
+        None => None,
+    }
+}
+
+/// Converts a byte offset from a span into an offset in chars.
+pub fn char_offset(text: &str, offset: usize) -> usize {
+    text[..clamp(text, offset)].chars().count()
+}
+
+/// Converts a byte offset from a span into an offset in UTF-16 code units, as used by JavaScript strings and browser editors.
+pub fn utf16_offset(text: &str, offset: usize) -> usize {
+    text[..clamp(text, offset)]
+        .chars()
+        .map(char::len_utf16)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn ascii_offsets() {
+        let text = "2x + 1";
+        for offset in 0..=text.len() {
+            assert_eq!(char_offset(text, offset), offset);
+            assert_eq!(utf16_offset(text, offset), offset);
+            assert_eq!(
+                line_column(text, offset),
+                LineColumn {
+                    line: 1,
+                    column: offset + 1
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn wide_offsets() {
+        let text = "é𝑥 + 1";
+        let err = parse(text).unwrap_err();
+        assert_eq!((err.start, err.end), (0, 2));
+        assert_eq!(&text[err.start..err.end], "é");
+        assert_eq!(char_offset(text, 2), 1);
+        assert_eq!(char_offset(text, 6), 2);
+        assert_eq!(utf16_offset(text, 6), 3);
+        assert_eq!(char_offset(text, 100), 6);
+        assert_eq!(char_offset(text, 3), 1);
+    }
+
+    #[test]
+    fn lines() {
+        let text = "1 +\n2 +\n\n3";
+        assert_eq!(line_column(text, 3), LineColumn { line: 1, column: 4 });
+        assert_eq!(line_column(text, 4), LineColumn { line: 2, column: 1 });
+        assert_eq!(line_column(text, 8), LineColumn { line: 3, column: 1 });
+        assert_eq!(line_column(text, 9), LineColumn { line: 4, column: 1 });
+        for offset in 0..=text.len() {
+            assert_eq!(byte_offset(text, line_column(text, offset)), Some(offset));
+        }
+        assert_eq!(byte_offset(text, LineColumn { line: 1, column: 6 }), None);
+        assert_eq!(byte_offset(text, LineColumn { line: 0, column: 1 }), None);
+        assert_eq!(byte_offset(text, LineColumn { line: 1, column: 0 }), None);
+    }
+
+    #[test]
+    fn multi_line_expression() {
+        let text = "2x +\n\t3y";
+        let tree = parse(text).unwrap();
+        assert_eq!(tree.end, text.len());
+        assert_eq!(
+            line_column(text, tree.end),
+            LineColumn { line: 2, column: 4 }
+        );
+    }
+}